@@ -0,0 +1,293 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encoding and decoding inside async pipelines.
+//!
+//! `EncoderCodec` and `DecoderCodec` are tokio-util codecs which carry Opus
+//! packets with a 16-bit big-endian length prefix, encoding and decoding PCM
+//! on the way through. They suit stream transports such as TCP, with
+//! `FramedWrite` on the sending side and `FramedRead` on the receiving side.
+//!
+//! `OggOpusStream` decodes an Ogg Opus file from a tokio `AsyncRead` as a
+//! futures `Stream` of PCM chunks.
+//!
+//! This module is only available with the `async` feature.
+
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::codec;
+
+use super::ogg::{self, HeaderReader, OggOpusReader, OpusHead, OpusTags};
+use super::{Decoder, StreamEncoder};
+
+/// The largest frame a decoder can produce, in samples per channel at 48 kHz.
+const MAX_FRAME_SIZE: usize = 5760;
+
+// ============================================================================
+// Codecs
+
+/// Encodes interleaved floating point PCM into length-prefixed Opus packets.
+///
+/// Input chunks may be of any length; see `StreamEncoder`. Input which does
+/// not fill a frame is held until more arrives or the input ends. Through
+/// `FramedWrite`, send chunks as `Some` items and end with `None`, which
+/// pads out and writes the held input like [`finish`](#method.finish).
+#[derive(Debug)]
+pub struct EncoderCodec {
+	stream: StreamEncoder,
+}
+
+impl EncoderCodec {
+	/// Create a codec which encodes through the given stream encoder.
+	pub fn new(stream: StreamEncoder) -> EncoderCodec {
+		EncoderCodec { stream }
+	}
+
+	/// Get the underlying stream encoder.
+	pub fn stream_mut(&mut self) -> &mut StreamEncoder {
+		&mut self.stream
+	}
+
+	/// Pad out the remaining input and write the final packets.
+	pub fn finish(&mut self, dst: &mut BytesMut) -> io::Result<()> {
+		self.stream.finish();
+		self.write_packets(dst)
+	}
+
+	fn write_packets(&mut self, dst: &mut BytesMut) -> io::Result<()> {
+		while let Some(packet) = self.stream.next_packet()? {
+			dst.reserve(2 + packet.data.len());
+			dst.put_u16(packet.data.len() as u16);
+			dst.put_slice(&packet.data);
+		}
+		Ok(())
+	}
+}
+
+impl<'a> codec::Encoder<&'a [f32]> for EncoderCodec {
+	type Error = io::Error;
+
+	fn encode(&mut self, item: &'a [f32], dst: &mut BytesMut) -> io::Result<()> {
+		self.stream.write_float(item)?;
+		self.write_packets(dst)
+	}
+}
+
+impl codec::Encoder<Vec<f32>> for EncoderCodec {
+	type Error = io::Error;
+
+	fn encode(&mut self, item: Vec<f32>, dst: &mut BytesMut) -> io::Result<()> {
+		codec::Encoder::encode(self, &item[..], dst)
+	}
+}
+
+impl codec::Encoder<Option<Vec<f32>>> for EncoderCodec {
+	type Error = io::Error;
+
+	fn encode(&mut self, item: Option<Vec<f32>>, dst: &mut BytesMut) -> io::Result<()> {
+		match item {
+			Some(item) => codec::Encoder::encode(self, &item[..], dst),
+			None => self.finish(dst),
+		}
+	}
+}
+
+/// Decodes length-prefixed Opus packets into interleaved floating point PCM.
+///
+/// An empty packet is treated as lost, and concealed with as much audio as
+/// the packet before it, or 20 ms if no packet has been decoded yet.
+#[derive(Debug)]
+pub struct DecoderCodec {
+	decoder: Decoder,
+	output: Vec<f32>,
+}
+
+impl DecoderCodec {
+	/// Create a codec which decodes with the given decoder.
+	pub fn new(decoder: Decoder) -> DecoderCodec {
+		let output = vec![0.0; MAX_FRAME_SIZE * decoder.channels as usize];
+		DecoderCodec { decoder, output }
+	}
+
+	/// Get the underlying decoder.
+	pub fn decoder_mut(&mut self) -> &mut Decoder {
+		&mut self.decoder
+	}
+}
+
+impl codec::Decoder for DecoderCodec {
+	type Item = Vec<f32>;
+	type Error = io::Error;
+
+	fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Vec<f32>>> {
+		if src.len() < 2 {
+			return Ok(None);
+		}
+		let len = u16::from_be_bytes([src[0], src[1]]) as usize;
+		if src.len() < 2 + len {
+			src.reserve(2 + len - src.len());
+			return Ok(None);
+		}
+		src.advance(2);
+		let packet = src.split_to(len);
+		let channels = self.decoder.channels as usize;
+		let mut frame_len = self.output.len();
+		if packet.is_empty() {
+			// Conceal as much audio as the last packet held.
+			let mut duration = self.decoder.get_last_packet_duration()? as usize;
+			if duration == 0 {
+				duration = self.decoder.get_sample_rate()? as usize / 50;
+			}
+			frame_len = duration * channels;
+		}
+		let samples = self.decoder.decode_float(&packet, &mut self.output[..frame_len], false)?;
+		Ok(Some(self.output[..samples * channels].to_vec()))
+	}
+}
+
+// ============================================================================
+// Ogg Opus Stream
+
+/// Bytes read so far from the async source, handed to the synchronous
+/// reader. Running out reports `WouldBlock` until the source has ended.
+#[derive(Debug, Clone, Default)]
+struct Feed {
+	buf: Vec<u8>,
+	pos: usize,
+	eof: bool,
+}
+
+impl Read for Feed {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.pos == self.buf.len() {
+			if self.eof {
+				return Ok(0);
+			}
+			self.buf.clear();
+			self.pos = 0;
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		let count = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+		buf[..count].copy_from_slice(&self.buf[self.pos..self.pos + count]);
+		self.pos += count;
+		Ok(count)
+	}
+}
+
+/// Decodes an Ogg Opus stream from an async source.
+///
+/// Each item is a chunk of interleaved floating point PCM at 48 kHz, as
+/// from `OggOpusReader::decode_float`. The stream ends after the first
+/// error.
+#[derive(Debug)]
+pub struct OggOpusStream<R> {
+	inner: R,
+	/// The headers read so far, until they are complete.
+	headers: Option<HeaderReader<Feed>>,
+	reader: Option<OggOpusReader<Feed>>,
+	done: bool,
+}
+
+impl<R: AsyncRead + Unpin> OggOpusStream<R> {
+	/// Create a stream which decodes from `inner`.
+	///
+	/// Nothing is read until the stream is first polled.
+	pub fn new(inner: R) -> OggOpusStream<R> {
+		OggOpusStream {
+			inner,
+			headers: Some(HeaderReader::new(Feed::default())),
+			reader: None,
+			done: false,
+		}
+	}
+
+	/// Get the stream's identification header, once it has been read.
+	pub fn head(&self) -> Option<&OpusHead> {
+		match self.headers {
+			Some(ref headers) => headers.head(),
+			None => self.reader.as_ref().map(|r| r.head()),
+		}
+	}
+
+	/// Get the stream's comment header, once it has been read.
+	pub fn tags(&self) -> Option<&OpusTags> {
+		match self.headers {
+			Some(ref headers) => headers.tags(),
+			None => self.reader.as_ref().map(|r| r.tags()),
+		}
+	}
+
+	/// Decode from the data read so far, resuming wherever the last attempt
+	/// ran out.
+	fn decode(&mut self) -> ogg::Result<Option<Vec<f32>>> {
+		if let Some(mut headers) = self.headers.take() {
+			if let Err(e) = headers.read() {
+				self.headers = Some(headers);
+				return Err(e);
+			}
+			self.reader = Some(headers.into_reader()?);
+		}
+		self.reader.as_mut().expect("headers were read").decode_float()
+	}
+
+	fn feed_mut(&mut self) -> &mut Feed {
+		if let Some(ref mut headers) = self.headers {
+			return headers.inner_mut();
+		}
+		self.reader.as_mut().expect("headers were read").inner_mut()
+	}
+}
+
+impl<R: AsyncRead + Unpin> Stream for OggOpusStream<R> {
+	type Item = ogg::Result<Vec<f32>>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		let mut chunk = [0; 4096];
+		loop {
+			if this.done {
+				return Poll::Ready(None);
+			}
+			match this.decode() {
+				Ok(Some(pcm)) => return Poll::Ready(Some(Ok(pcm))),
+				Ok(None) => {
+					this.done = true;
+					return Poll::Ready(None);
+				}
+				Err(ogg::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+				Err(e) => {
+					this.done = true;
+					return Poll::Ready(Some(Err(e)));
+				}
+			}
+
+			let mut buf = ReadBuf::new(&mut chunk);
+			match Pin::new(&mut this.inner).poll_read(cx, &mut buf) {
+				Poll::Pending => return Poll::Pending,
+				Poll::Ready(Err(e)) => {
+					this.done = true;
+					return Poll::Ready(Some(Err(e.into())));
+				}
+				Poll::Ready(Ok(())) => {
+					let data = buf.filled();
+					let feed = this.feed_mut();
+					if data.is_empty() {
+						feed.eof = true;
+					} else {
+						feed.buf.extend_from_slice(data);
+					}
+				}
+			}
+		}
+	}
+}
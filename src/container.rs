@@ -0,0 +1,117 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pieces shared by the container readers and writers.
+
+use std::io;
+
+// ============================================================================
+// Trimming
+
+/// Tracks how much of the decoded output to discard at the start and end of
+/// a stream.
+#[cfg(feature = "ogg-opus")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trim {
+	/// Decoded samples per channel still to be discarded.
+	pub skip: u64,
+	/// Decoded samples per channel still to be output, if limited.
+	pub remaining: Option<u64>,
+}
+
+#[cfg(feature = "ogg-opus")]
+impl Trim {
+	/// Cut `output`, holding `samples` freshly decoded samples per channel,
+	/// down to those which should be output. At most the first `limit` of
+	/// them are kept. Returns false, leaving `output` as it was, if none
+	/// remain.
+	pub fn apply<T>(
+		&mut self,
+		output: &mut Vec<T>,
+		samples: usize,
+		limit: usize,
+		channels: usize,
+	) -> bool {
+		let samples = samples as u64;
+		let begin = std::cmp::min(self.skip, samples);
+		self.skip -= begin;
+		let mut finish = std::cmp::min(samples, limit as u64);
+		if let Some(ref mut remaining) = self.remaining {
+			finish = std::cmp::min(finish, begin + *remaining);
+			*remaining -= finish.saturating_sub(begin);
+		}
+		if begin >= finish {
+			return false;
+		}
+		output.truncate(finish as usize * channels);
+		output.drain(..begin as usize * channels);
+		true
+	}
+}
+
+// ============================================================================
+// Error Handling
+
+/// Container Result alias.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error encountered while reading or writing a container.
+#[derive(Debug)]
+pub enum Error {
+	/// The underlying reader or writer failed.
+	Io(io::Error),
+	/// The stream is not valid for its container format, or its Opus track
+	/// is invalid.
+	Malformed(&'static str),
+	/// The stream is valid, but its layout is not supported.
+	Unsupported(&'static str),
+	/// The Opus codec reported an error.
+	Opus(super::Error),
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			Error::Io(ref e) => write!(f, "I/O error: {}", e),
+			Error::Malformed(what) => write!(f, "malformed stream: {}", what),
+			Error::Unsupported(what) => write!(f, "unsupported stream: {}", what),
+			Error::Opus(ref e) => e.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match *self {
+			Error::Io(ref e) => Some(e),
+			Error::Malformed(_) | Error::Unsupported(_) => None,
+			Error::Opus(ref e) => Some(e),
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Error {
+		Error::Io(e)
+	}
+}
+
+impl From<super::Error> for Error {
+	fn from(e: super::Error) -> Error {
+		Error::Opus(e)
+	}
+}
+
+impl From<Error> for io::Error {
+	fn from(e: Error) -> io::Error {
+		match e {
+			Error::Io(e) => e,
+			e => io::Error::new(io::ErrorKind::InvalidData, e),
+		}
+	}
+}
@@ -0,0 +1,249 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Deep Redundancy (DRED), which lets a decoder rebuild up to a second of
+//! lost audio from redundancy carried in later packets.
+//!
+//! DRED needs libopus 1.5 or later built with `--enable-dred`, so this module
+//! is only available with the `dred` feature. The libopus bundled with
+//! `audiopus_sys` is 1.3, so point it at a suitable build through pkg-config
+//! or `OPUS_LIB_DIR`; against a library without DRED, linking fails with
+//! undefined `opus_dred_*` symbols. On the sending side, enable it with
+//! `Encoder::set_dred_duration`. On the receiving side, parse the redundancy
+//! out of the first packet after a loss with a `DredDecoder`, then pass the
+//! resulting `DredState` to `Decoder::dred_decode`.
+
+use std::os::raw::c_int;
+
+use super::{ffi, len, Decoder, DnnBlob, Error, LibraryFeature, Result, OPUS_SET_DNN_BLOB_REQUEST};
+
+// The bindings predate Opus 1.5, so the DRED API is declared here.
+#[allow(non_camel_case_types)]
+enum OpusDREDDecoder {}
+#[allow(non_camel_case_types)]
+enum OpusDRED {}
+
+extern "C" {
+	fn opus_dred_decoder_create(error: *mut c_int) -> *mut OpusDREDDecoder;
+	fn opus_dred_decoder_destroy(dec: *mut OpusDREDDecoder);
+	fn opus_dred_decoder_ctl(dec: *mut OpusDREDDecoder, request: c_int, ...) -> c_int;
+	fn opus_dred_alloc(error: *mut c_int) -> *mut OpusDRED;
+	fn opus_dred_free(dec: *mut OpusDRED);
+	fn opus_dred_parse(
+		dred_dec: *mut OpusDREDDecoder,
+		dred: *mut OpusDRED,
+		data: *const u8,
+		len: i32,
+		max_dred_samples: i32,
+		sampling_rate: i32,
+		dred_end: *mut c_int,
+		defer_processing: c_int,
+	) -> c_int;
+	fn opus_decoder_dred_decode(
+		st: *mut ffi::OpusDecoder,
+		dred: *const OpusDRED,
+		dred_offset: i32,
+		pcm: *mut i16,
+		frame_size: i32,
+	) -> c_int;
+	fn opus_decoder_dred_decode_float(
+		st: *mut ffi::OpusDecoder,
+		dred: *const OpusDRED,
+		dred_offset: i32,
+		pcm: *mut f32,
+		frame_size: i32,
+	) -> c_int;
+}
+
+pub(crate) const OPUS_SET_DRED_DURATION_REQUEST: c_int = 4050;
+pub(crate) const OPUS_GET_DRED_DURATION_REQUEST: c_int = 4051;
+
+macro_rules! dred_ffi {
+	($f:ident $(, $rest:expr)*) => {
+		match unsafe { $f($($rest),*) } {
+			code if code < 0 => return Err(Error::from_code(stringify!($f), code)),
+			code => code,
+		}
+	}
+}
+
+// ============================================================================
+// DRED Decoder
+
+/// Extracts DRED redundancy from received packets.
+#[derive(Debug)]
+pub struct DredDecoder {
+	ptr: *mut OpusDREDDecoder,
+	dnn_blob: Option<DnnBlob>,
+}
+
+impl Drop for DredDecoder {
+	fn drop(&mut self) {
+		unsafe { opus_dred_decoder_destroy(self.ptr) }
+	}
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl Send for DredDecoder {}
+
+impl DredDecoder {
+	/// Create and initialize a DRED decoder.
+	pub fn new() -> Result<DredDecoder> {
+		let mut error = 0;
+		let ptr = unsafe { opus_dred_decoder_create(&mut error) };
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_dred_decoder_create", error))
+		} else {
+			Ok(DredDecoder { ptr, dnn_blob: None })
+		}
+	}
+
+	/// Load the DRED model weights, for libopus builds which do not include
+	/// them. Returns an error whose `missing_feature` is
+	/// `LibraryFeature::DnnBlob` if libopus cannot load weights at runtime.
+	pub fn set_dnn_blob(&mut self, blob: &DnnBlob) -> Result<()> {
+		let data = blob.as_bytes();
+		let request = OPUS_SET_DNN_BLOB_REQUEST;
+		let code = unsafe { opus_dred_decoder_ctl(self.ptr, request, data.as_ptr(), len(data)) };
+		if code < 0 {
+			let error = Error::from_code("opus_dred_decoder_ctl", code);
+			return Err(error.requiring(LibraryFeature::DnnBlob));
+		}
+		self.dnn_blob = Some(blob.clone());
+		Ok(())
+	}
+
+	/// Parse the redundancy in a packet into `state`.
+	///
+	/// At most `max_samples` samples at `sample_rate` are extracted. Returns
+	/// `None` if the packet has no redundancy.
+	pub fn parse(
+		&mut self,
+		state: &mut DredState,
+		packet: &[u8],
+		max_samples: u32,
+		sample_rate: u32,
+	) -> Result<Option<DredInfo>> {
+		let mut end = 0;
+		let available = dred_ffi!(
+			opus_dred_parse,
+			self.ptr,
+			state.ptr,
+			packet.as_ptr(),
+			len(packet),
+			max_samples as i32,
+			sample_rate as i32,
+			&mut end,
+			0
+		);
+		state.sample_rate = sample_rate;
+		if available == 0 {
+			Ok(None)
+		} else {
+			Ok(Some(DredInfo { available: available as u32, end: end as u32 }))
+		}
+	}
+}
+
+/// How much audio a packet's redundancy can rebuild, returned from
+/// `DredDecoder::parse`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DredInfo {
+	/// The number of samples before the packet which can be rebuilt.
+	pub available: u32,
+	/// The number of samples at the end of that span which were silence
+	/// and so were not coded.
+	pub end: u32,
+}
+
+// ============================================================================
+// DRED State
+
+/// Redundancy parsed from a packet, ready to rebuild lost audio.
+///
+/// The state owns everything it needs, so it can outlive the packet it was
+/// parsed from.
+#[derive(Debug)]
+pub struct DredState {
+	ptr: *mut OpusDRED,
+	sample_rate: u32,
+}
+
+impl Drop for DredState {
+	fn drop(&mut self) {
+		unsafe { opus_dred_free(self.ptr) }
+	}
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl Send for DredState {}
+
+impl DredState {
+	/// Allocate an empty DRED state.
+	pub fn new() -> Result<DredState> {
+		let mut error = 0;
+		let ptr = unsafe { opus_dred_alloc(&mut error) };
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_dred_alloc", error))
+		} else {
+			Ok(DredState { ptr, sample_rate: 0 })
+		}
+	}
+
+	/// Get the sample rate the state was last parsed at, or zero if it has
+	/// not been parsed into yet.
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+}
+
+// ============================================================================
+// Decoding
+
+impl Decoder {
+	/// Rebuild lost audio from parsed DRED redundancy.
+	///
+	/// `offset` is how many samples before the packet the redundancy came
+	/// from the lost audio starts. The output buffer's length sets how much
+	/// audio is rebuilt.
+	pub fn dred_decode(
+		&mut self,
+		dred: &DredState,
+		offset: u32,
+		output: &mut [i16],
+	) -> Result<usize> {
+		let len = dred_ffi!(
+			opus_decoder_dred_decode,
+			self.ptr,
+			dred.ptr,
+			offset as i32,
+			output.as_mut_ptr(),
+			len(output) / self.channels as c_int
+		);
+		Ok(len as usize)
+	}
+
+	/// Rebuild lost audio from parsed DRED redundancy with floating point
+	/// output.
+	pub fn dred_decode_float(
+		&mut self,
+		dred: &DredState,
+		offset: u32,
+		output: &mut [f32],
+	) -> Result<usize> {
+		let len = dred_ffi!(
+			opus_decoder_dred_decode_float,
+			self.ptr,
+			dred.ptr,
+			offset as i32,
+			output.as_mut_ptr(),
+			len(output) / self.channels as c_int
+		);
+		Ok(len as usize)
+	}
+}
@@ -0,0 +1,329 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An adaptive jitter buffer for received Opus packets.
+//!
+//! Packets are pushed as they arrive from the network, in any order, and
+//! audio is popped a fixed-size frame at a time on the playback clock. Gaps
+//! are filled with in-band forward error correction where possible and with
+//! packet loss concealment otherwise.
+
+use std::collections::BTreeMap;
+
+use super::rtp::CLOCK_RATE;
+use super::{packet, Decoder, Error, Result};
+
+/// Concealment and error correction work in multiples of 2.5 ms.
+const QUANTUM: i64 = CLOCK_RATE as i64 / 400;
+
+/// The largest duration to conceal in one go: 120 ms.
+const MAX_CONCEAL: i64 = 48 * QUANTUM;
+
+/// Counters describing how the buffer has coped with the network.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct JitterStats {
+	/// Packets which arrived too late to be played, including duplicates.
+	pub late: u64,
+	/// Packets which were never decoded normally.
+	pub lost: u64,
+	/// Times audio was synthesized by packet loss concealment.
+	pub concealed: u64,
+	/// Lost packets which were recovered from forward error correction data.
+	pub fec_recovered: u64,
+}
+
+#[derive(Debug)]
+struct Queued {
+	sequence: u16,
+	duration: i64,
+	payload: Vec<u8>,
+}
+
+/// Reorders received packets and decodes them into a steady stream of
+/// fixed-duration frames.
+///
+/// Durations are given in samples per channel at the decoder's sample rate,
+/// and timestamps are RTP timestamps at 48 kHz.
+#[derive(Debug)]
+pub struct JitterBuffer {
+	decoder: Decoder,
+	sample_rate: u32,
+	channels: usize,
+	/// The output frame duration, at 48 kHz.
+	frame: i64,
+	min_delay: i64,
+	max_delay: i64,
+	/// Packets waiting to be decoded, by extended timestamp.
+	packets: BTreeMap<i64, Queued>,
+	/// Decoded audio not yet returned.
+	pcm: Vec<f32>,
+	/// The extended timestamp of the next audio to decode.
+	next: Option<i64>,
+	/// The most recent timestamp pushed, and its extended value.
+	last_timestamp: (u32, i64),
+	/// The sequence number of the last packet decoded.
+	last_sequence: Option<u16>,
+	playing: bool,
+	/// The playback clock, advanced by one frame on each pop.
+	now: i64,
+	/// The previous difference between arrival time and timestamp.
+	transit: Option<i64>,
+	/// The RFC 3550 interarrival jitter estimate.
+	jitter: f64,
+	stats: JitterStats,
+}
+
+impl JitterBuffer {
+	/// Create a jitter buffer which returns frames of `frame_size` samples
+	/// per channel.
+	///
+	/// The frame size must be a multiple of 2.5 ms.
+	pub fn new(mut decoder: Decoder, frame_size: usize) -> Result<JitterBuffer> {
+		let sample_rate = decoder.get_sample_rate()?;
+		let channels = decoder.channels as usize;
+		let frame = frame_size as i64 * CLOCK_RATE as i64 / sample_rate as i64;
+		if frame_size == 0 || frame_size % (sample_rate as usize / 400) != 0 {
+			return Err(Error::bad_arg("JitterBuffer::new"));
+		}
+		Ok(JitterBuffer {
+			decoder,
+			sample_rate,
+			channels,
+			frame,
+			min_delay: frame,
+			max_delay: CLOCK_RATE as i64 / 2,
+			packets: BTreeMap::new(),
+			pcm: Vec::new(),
+			next: None,
+			last_timestamp: (0, 0),
+			last_sequence: None,
+			playing: false,
+			now: 0,
+			transit: None,
+			jitter: 0.0,
+			stats: JitterStats::default(),
+		})
+	}
+
+	/// Set the range the target delay may adapt within, in samples per
+	/// channel.
+	///
+	/// The default is from one frame to half a second.
+	pub fn set_delay_range(&mut self, min: usize, max: usize) -> Result<()> {
+		if min > max {
+			return Err(Error::bad_arg("JitterBuffer::set_delay_range"));
+		}
+		self.min_delay = self.duration(min);
+		self.max_delay = self.duration(max);
+		Ok(())
+	}
+
+	/// Get the delay currently aimed for, in samples per channel.
+	pub fn target_delay(&self) -> usize {
+		self.samples(self.target())
+	}
+
+	/// Get the amount of audio currently buffered, in samples per channel.
+	pub fn delay(&self) -> usize {
+		self.samples(self.buffered()) + self.pcm.len() / self.channels
+	}
+
+	/// Get the statistics collected so far.
+	pub fn stats(&self) -> JitterStats {
+		self.stats
+	}
+
+	/// Get the underlying decoder.
+	pub fn decoder_mut(&mut self) -> &mut Decoder {
+		&mut self.decoder
+	}
+
+	/// Add a received packet.
+	///
+	/// Packets which arrive after their audio has already been played are
+	/// counted and dropped.
+	pub fn push(&mut self, sequence: u16, timestamp: u32, payload: &[u8]) -> Result<()> {
+		let duration = packet::get_nb_samples(payload, CLOCK_RATE)? as i64;
+
+		let (last, last_extended) = self.last_timestamp;
+		let extended = if self.next.is_some() {
+			last_extended + timestamp.wrapping_sub(last) as i32 as i64
+		} else {
+			timestamp as i64
+		};
+		self.last_timestamp = (timestamp, extended);
+
+		// Update the jitter estimate as described in RFC 3550.
+		let transit = self.now - extended;
+		if let Some(previous) = self.transit {
+			let d = (transit - previous).abs() as f64;
+			self.jitter += (d - self.jitter) / 16.0;
+		}
+		self.transit = Some(transit);
+
+		match self.next {
+			Some(next) if extended < next => {
+				if self.playing {
+					self.stats.late += 1;
+					return Ok(());
+				}
+				// Still buffering, so an earlier packet can go first.
+				self.next = Some(extended);
+			}
+			None => self.next = Some(extended),
+			_ => {}
+		}
+		if self.packets.contains_key(&extended) {
+			self.stats.late += 1;
+			return Ok(());
+		}
+		self.packets.insert(extended, Queued { sequence, duration, payload: payload.to_vec() });
+
+		// Don't let the delay grow without bound if the sender's clock runs
+		// fast or playback has stalled.
+		while self.buffered() > self.max_delay + self.frame {
+			let next = self.next.unwrap();
+			let (&timestamp, queued) = self.packets.iter().next().unwrap();
+			if timestamp > next {
+				// Skip missing audio before dropping any which arrived.
+				let excess = self.buffered() - self.max_delay;
+				self.next = Some(std::cmp::min(timestamp, next + excess));
+			} else {
+				self.next = Some(timestamp + queued.duration);
+				self.packets.remove(&timestamp);
+				self.stats.late += 1;
+			}
+		}
+		Ok(())
+	}
+
+	/// Get the next frame of audio.
+	///
+	/// This should be called at the rate the audio is played. Silence is
+	/// returned until enough audio has been buffered to start.
+	pub fn pop(&mut self) -> Result<Vec<i16>> {
+		let pcm = self.pop_float()?;
+		Ok(pcm.iter().map(|&s| (s * 32768.0).round().clamp(-32768.0, 32767.0) as i16).collect())
+	}
+
+	/// Get the next frame of audio with floating point output.
+	pub fn pop_float(&mut self) -> Result<Vec<f32>> {
+		let want = self.samples(self.frame) * self.channels;
+		if !self.playing {
+			if self.next.is_none() || self.buffered() < self.target() {
+				self.now += self.frame;
+				return Ok(vec![0.0; want]);
+			}
+			self.playing = true;
+		}
+		while self.pcm.len() < want {
+			self.decode_next()?;
+		}
+		self.now += self.frame;
+		Ok(self.pcm.drain(..want).collect())
+	}
+
+	/// Decode at least some audio into `pcm`.
+	fn decode_next(&mut self) -> Result<()> {
+		let next = self.next.unwrap();
+		let (timestamp, duration) = match self.packets.iter().next() {
+			Some((&timestamp, queued)) => (timestamp, queued.duration),
+			None => {
+				// Nothing to play: conceal without moving on, which adds
+				// delay for the packets to catch up.
+				return self.conceal(self.frame);
+			}
+		};
+
+		if timestamp == next {
+			let queued = self.packets.remove(&timestamp).unwrap();
+			if let Some(last) = self.last_sequence {
+				let missing = queued.sequence.wrapping_sub(last).wrapping_sub(1);
+				if missing < 0x8000 {
+					self.stats.lost += missing as u64;
+				}
+			}
+			self.last_sequence = Some(queued.sequence);
+			self.next = Some(next + queued.duration);
+			return self.decode(&queued.payload, queued.duration, false);
+		}
+
+		// Audio is missing before the next packet. If more is buffered than
+		// needed, skip over it to reduce the delay, but not over the packet
+		// which can be recovered from the redundant copy this one carries.
+		// Only SILK frames carry one.
+		let payload = &self.packets[&timestamp].payload;
+		let redundant =
+			payload.first().map_or(false, |&toc| packet::Toc(toc).mode() != packet::Mode::Celt);
+		let excess = std::cmp::max(self.buffered() - self.target(), 0);
+		let gap = timestamp - next;
+		let mut skip = std::cmp::min(gap, excess / QUANTUM * QUANTUM);
+		if redundant && gap >= duration {
+			skip = std::cmp::min(skip, gap - duration);
+		}
+		let gap = (gap - skip) / QUANTUM * QUANTUM;
+		if gap == 0 {
+			self.next = Some(timestamp);
+			return Ok(());
+		}
+		if gap <= duration {
+			// Redundancy only covers a whole packet, so anything less is
+			// concealed.
+			if gap == duration && redundant {
+				let payload = payload.clone();
+				self.decode(&payload, gap, true)?;
+				self.stats.fec_recovered += 1;
+			} else {
+				self.conceal(gap)?;
+			}
+			self.next = Some(timestamp);
+		} else {
+			let conceal = std::cmp::min(gap - duration, MAX_CONCEAL);
+			self.conceal(conceal)?;
+			self.next = Some(next + skip + conceal);
+		}
+		Ok(())
+	}
+
+	fn conceal(&mut self, duration: i64) -> Result<()> {
+		self.stats.concealed += 1;
+		self.decode(&[], duration, false)
+	}
+
+	fn decode(&mut self, payload: &[u8], duration: i64, fec: bool) -> Result<()> {
+		let start = self.pcm.len();
+		self.pcm.resize(start + self.samples(duration) * self.channels, 0.0);
+		let decoded = self.decoder.decode_float(payload, &mut self.pcm[start..], fec)?;
+		self.pcm.truncate(start + decoded * self.channels);
+		Ok(())
+	}
+
+	/// The duration of the queued packets, from the next audio to decode.
+	fn buffered(&self) -> i64 {
+		match (self.next, self.packets.iter().next_back()) {
+			(Some(next), Some((&timestamp, queued))) => timestamp + queued.duration - next,
+			_ => 0,
+		}
+	}
+
+	fn target(&self) -> i64 {
+		let target = self.frame + (3.0 * self.jitter) as i64;
+		let target = (target + QUANTUM - 1) / QUANTUM * QUANTUM;
+		std::cmp::max(self.min_delay, std::cmp::min(target, self.max_delay))
+	}
+
+	/// Convert a duration at 48 kHz to samples at the decoder rate.
+	fn samples(&self, duration: i64) -> usize {
+		(duration * self.sample_rate as i64 / CLOCK_RATE as i64) as usize
+	}
+
+	/// Convert samples at the decoder rate to a duration at 48 kHz.
+	fn duration(&self, samples: usize) -> i64 {
+		samples as i64 * CLOCK_RATE as i64 / self.sample_rate as i64
+	}
+}
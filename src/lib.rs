@@ -13,11 +13,24 @@
 #![warn(missing_docs)]
 
 extern crate audiopus_sys as ffi;
-
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "async")]
+extern crate bytes;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "async")]
+extern crate tokio_util;
+
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::marker::PhantomData;
 use std::os::raw::c_int;
+use std::path::Path;
+use std::sync::Arc;
 
 // ============================================================================
 // Constants
@@ -58,6 +71,7 @@ pub enum Channels {
 
 /// The available bandwidth level settings.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(i32)]
 pub enum Bandwidth {
 	/// Auto/default setting.
@@ -99,6 +113,7 @@ impl Bandwidth {
 	}
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Bandwidth {
 	fn default() -> Self {
 		Bandwidth::Auto
@@ -149,8 +164,38 @@ impl ErrorCode {
 	}
 }
 
+/// Optional parts of libopus which a build may leave out.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum LibraryFeature {
+	/// Decoder complexity, which enables deep packet loss concealment and
+	/// OSCE. Needs Opus 1.5 or later, built with `--enable-deep-plc` or
+	/// `--enable-osce` for the setting to have an effect.
+	DeepPlc,
+	/// Loading neural network weights at runtime. Needs Opus 1.5 or later,
+	/// built with `USE_WEIGHTS_FILE` defined.
+	DnnBlob,
+}
+
+impl LibraryFeature {
+	/// Get a human-readable name for this feature.
+	pub fn description(self) -> &'static str {
+		match self {
+			LibraryFeature::DeepPlc => "deep PLC and OSCE (libopus 1.5 or later)",
+			LibraryFeature::DnnBlob => "runtime neural network weights (libopus 1.5 or later)",
+		}
+	}
+}
+
+impl std::fmt::Display for LibraryFeature {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str(self.description())
+	}
+}
+
 /// Possible bitrates.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Bitrate {
 	/// Explicit bitrate choice (in bits/second).
 	Bits(i32),
@@ -180,6 +225,7 @@ impl Bitrate {
 
 /// Possible signal types. Hints for the encoder's mode selection.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(i32)]
 pub enum Signal {
 	/// Auto/default setting.
@@ -205,6 +251,7 @@ impl Signal {
 	}
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Signal {
 	fn default() -> Self {
 		Signal::Auto
@@ -213,6 +260,7 @@ impl Default for Signal {
 
 /// Possible frame sizes. Controls encoder's use of variable duration frames.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(i32)]
 pub enum FrameSize {
 	/// Select frame size from the argument (default).
@@ -257,8 +305,26 @@ impl FrameSize {
 	fn raw(self) -> i32 {
 		self as i32
 	}
+
+	/// Get the number of samples per channel in a frame, or `None` for `Arg`.
+	fn samples(self, sample_rate: u32) -> Option<usize> {
+		let tenths_of_ms = match self {
+			FrameSize::Arg => return None,
+			FrameSize::Ms2_5 => 25,
+			FrameSize::Ms5 => 50,
+			FrameSize::Ms10 => 100,
+			FrameSize::Ms20 => 200,
+			FrameSize::Ms40 => 400,
+			FrameSize::Ms60 => 600,
+			FrameSize::Ms80 => 800,
+			FrameSize::Ms100 => 1000,
+			FrameSize::Ms120 => 1200,
+		};
+		Some(sample_rate as usize * tenths_of_ms / 10000)
+	}
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for FrameSize {
 	fn default() -> Self {
 		FrameSize::Arg
@@ -284,6 +350,15 @@ macro_rules! ffi {
 	}
 }
 
+/// Treat an `Unimplemented` error as a missing value.
+fn optional<T>(result: Result<T>) -> Result<Option<T>> {
+	match result {
+		Ok(value) => Ok(Some(value)),
+		Err(ref e) if e.code() == ErrorCode::Unimplemented => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
 // ============================================================================
 // Generic CTLs
 
@@ -299,10 +374,23 @@ macro_rules! ctl {
 	}
 }
 
+// Like `ctl!`, but an `Unimplemented` result means libopus lacks `$feature`.
+macro_rules! feature_ctl {
+	($feature:expr, $f:ident, $this:ident, $ctl:path $(, $rest:expr)*) => {
+		match unsafe { ffi::$f($this.ptr, $ctl $(, $rest)*) } {
+			code if code < 0 => return Err(Error::from_code(
+				concat!(stringify!($f), "(", stringify!($ctl), ")"),
+				code,
+			).requiring($feature)),
+			_ => (),
+		}
+	}
+}
+
 macro_rules! generic_ctls {
-	($t:ty, $fn:ident) => {
+	(impl<$($l:lifetime),*> $t:ty, $fn:ident) => {
 		/// Generic CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__genericctls.html).
-		impl $t {
+		impl<$($l),*> $t {
 			/// Reset the codec state to be equivalent to a freshly initialized state.
 			pub fn reset_state(&mut self) -> Result<()> {
 				ctl!($fn, self, ffi::OPUS_RESET_STATE);
@@ -352,6 +440,9 @@ macro_rules! generic_ctls {
 			}
 		}
 	};
+	($t:ty, $fn:ident) => {
+		generic_ctls!(impl<> $t, $fn);
+	};
 }
 
 // ============================================================================
@@ -361,12 +452,15 @@ macro_rules! generic_ctls {
 #[derive(Debug)]
 pub struct Encoder {
 	ptr: *mut ffi::OpusEncoder,
+	/// The length of the state memory, in `u64`s.
+	words: usize,
 	channels: Channels,
+	dnn_blob: Option<DnnBlob>,
 }
 
 impl Drop for Encoder {
 	fn drop(&mut self) {
-		unsafe { ffi::opus_encoder_destroy(self.ptr) }
+		drop(unsafe { StateMemory::from_raw(self.ptr as *mut u64, self.words) })
 	}
 }
 
@@ -385,20 +479,37 @@ unsafe impl Send for Encoder {}
 impl Encoder {
 	/// Create and initialize an encoder.
 	pub fn new(sample_rate: u32, channels: Channels, mode: Application) -> Result<Encoder> {
-		let mut error = 0;
-		let ptr = unsafe {
-			ffi::opus_encoder_create(
-				sample_rate as i32,
-				channels as c_int,
-				mode as c_int,
-				&mut error,
-			)
-		};
-		if error != ffi::OPUS_OK || ptr.is_null() {
-			Err(Error::from_code("opus_encoder_create", error))
-		} else {
-			Ok(Encoder { ptr, channels })
+		Encoder::with_memory(sample_rate, channels, mode, StateMemory::for_encoder(channels))
+	}
+
+	/// Create and initialize an encoder in existing memory, which must be
+	/// at least the size of `StateMemory::for_encoder(channels)`.
+	pub fn with_memory(
+		sample_rate: u32,
+		channels: Channels,
+		mode: Application,
+		memory: StateMemory,
+	) -> Result<Encoder> {
+		if memory.size() < StateMemory::encoder_size(channels) {
+			return Err(Error::bad_arg("Encoder::with_memory"));
 		}
+		let (ptr, words) = memory.into_raw();
+		let encoder = Encoder {
+			ptr: ptr as *mut ffi::OpusEncoder,
+			words,
+			channels,
+			dnn_blob: None,
+		};
+		ffi!(opus_encoder_init, encoder.ptr, sample_rate as i32, channels as c_int, mode as c_int);
+		Ok(encoder)
+	}
+
+	/// Destroy the encoder, keeping its memory for reuse.
+	pub fn into_memory(mut self) -> StateMemory {
+		let memory = unsafe { StateMemory::from_raw(self.ptr as *mut u64, self.words) };
+		self.dnn_blob = None;
+		std::mem::forget(self);
+		memory
 	}
 
 	/// Encode an Opus frame.
@@ -445,9 +556,9 @@ impl Encoder {
 }
 
 macro_rules! encoder_ctls {
-	($t:ty, $fn:ident) => {
+	(impl<$($l:lifetime),*> $t:ty, $fn:ident) => {
 		/// Encoder CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__encoderctls.html).
-		impl $t {
+		impl<$($l),*> $t {
 			/// Configures the encoder's computational complexity.
 			pub fn set_complexity(&mut self, value: i32) -> Result<()> {
 				ctl!($fn, self, ffi::OPUS_SET_COMPLEXITY_REQUEST, value);
@@ -672,15 +783,387 @@ macro_rules! encoder_ctls {
 				Ok(value != 0)
 			}
 
-			// TODO(#5): OPUS_SET/GET_DRED_DURATION (since Opus 1.5)
-			// TODO(#5): OPUS_SET_DNN_BLOB (since Opus 1.5)
+			/// Apply every setting present in `config`.
+			///
+			/// If any setting is rejected, the encoder is returned to its
+			/// previous configuration.
+			pub fn set_config(&mut self, config: &EncoderConfig) -> Result<()> {
+				let previous = self.get_config()?;
+				if let Err(e) = self.apply_config(config) {
+					let _ = self.apply_config(&previous);
+					return Err(e);
+				}
+				Ok(())
+			}
+
+			/// Get the encoder's current configuration.
+			///
+			/// Settings the encoder cannot report, such as some of those of
+			/// multistream encoders with older libopus, are `None`.
+			pub fn get_config(&mut self) -> Result<EncoderConfig> {
+				Ok(EncoderConfig {
+					bitrate: optional(self.get_bitrate())?,
+					vbr: optional(self.get_vbr())?,
+					vbr_constraint: optional(self.get_vbr_constraint())?,
+					complexity: optional(self.get_complexity())?,
+					inband_fec: optional(self.get_inband_fec())?,
+					packet_loss_perc: optional(self.get_packet_loss_perc())?,
+					dtx: optional(self.get_dtx())?,
+					signal: optional(self.get_signal())?,
+					max_bandwidth: optional(self.get_max_bandwidth())?,
+					lsb_depth: optional(self.get_lsb_depth())?,
+					frame_duration: optional(self.get_expert_frame_duration())?,
+					prediction_disabled: optional(self.get_prediction_disabled())?,
+				})
+			}
+
+			fn apply_config(&mut self, config: &EncoderConfig) -> Result<()> {
+				if let Some(value) = config.bitrate {
+					self.set_bitrate(value)?;
+				}
+				if let Some(value) = config.vbr {
+					self.set_vbr(value)?;
+				}
+				if let Some(value) = config.vbr_constraint {
+					self.set_vbr_constraint(value)?;
+				}
+				if let Some(value) = config.complexity {
+					self.set_complexity(value)?;
+				}
+				if let Some(value) = config.inband_fec {
+					self.set_inband_fec(value)?;
+				}
+				if let Some(value) = config.packet_loss_perc {
+					self.set_packet_loss_perc(value)?;
+				}
+				if let Some(value) = config.dtx {
+					self.set_dtx(value)?;
+				}
+				if let Some(value) = config.signal {
+					self.set_signal(value)?;
+				}
+				if let Some(value) = config.max_bandwidth {
+					self.set_max_bandwidth(value)?;
+				}
+				if let Some(value) = config.lsb_depth {
+					self.set_lsb_depth(value)?;
+				}
+				if let Some(value) = config.frame_duration {
+					self.set_expert_frame_duration(value)?;
+				}
+				if let Some(value) = config.prediction_disabled {
+					self.set_prediction_disabled(value)?;
+				}
+				Ok(())
+			}
+
+			/// Sets the maximum amount of deep redundancy (DRED) to include in
+			/// each packet, in 10 ms frames. Zero disables DRED.
+			#[cfg(feature = "dred")]
+			pub fn set_dred_duration(&mut self, frames: i32) -> Result<()> {
+				ctl!($fn, self, $crate::dred::OPUS_SET_DRED_DURATION_REQUEST, frames);
+				Ok(())
+			}
+
+			/// Gets the encoder's configured amount of deep redundancy.
+			#[cfg(feature = "dred")]
+			pub fn get_dred_duration(&mut self) -> Result<i32> {
+				let mut value: i32 = 0;
+				ctl!($fn, self, $crate::dred::OPUS_GET_DRED_DURATION_REQUEST, &mut value);
+				Ok(value)
+			}
 		}
 	};
+	($t:ty, $fn:ident) => {
+		encoder_ctls!(impl<> $t, $fn);
+	};
 }
 
 generic_ctls!(Encoder, opus_encoder_ctl);
 encoder_ctls!(Encoder, opus_encoder_ctl);
 
+/// A set of encoder settings which can be applied together with
+/// `set_config` or read back with `get_config`.
+///
+/// Settings left as `None` are not changed when applied. With the `serde`
+/// feature, configurations can be loaded from presets, where missing
+/// settings are `None`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct EncoderConfig {
+	/// The bitrate.
+	pub bitrate: Option<Bitrate>,
+	/// Whether to use variable bitrate.
+	pub vbr: Option<bool>,
+	/// Whether variable bitrate is constrained.
+	pub vbr_constraint: Option<bool>,
+	/// The computational complexity, from 0 to 10.
+	pub complexity: Option<i32>,
+	/// Whether to include forward error correction.
+	pub inband_fec: Option<bool>,
+	/// The expected packet loss, in percent.
+	pub packet_loss_perc: Option<i32>,
+	/// Whether to use discontinuous transmission.
+	pub dtx: Option<bool>,
+	/// The type of signal being encoded.
+	pub signal: Option<Signal>,
+	/// The maximum bandwidth.
+	pub max_bandwidth: Option<Bandwidth>,
+	/// The depth of the input signal, from 8 to 24 bits.
+	pub lsb_depth: Option<i32>,
+	/// The frame duration, in place of the input length.
+	pub frame_duration: Option<FrameSize>,
+	/// Whether prediction is disabled.
+	pub prediction_disabled: Option<bool>,
+}
+
+impl EncoderConfig {
+	/// Create a configuration which changes nothing.
+	pub fn new() -> EncoderConfig {
+		EncoderConfig::default()
+	}
+
+	/// Set the bitrate.
+	pub fn bitrate(mut self, value: Bitrate) -> EncoderConfig {
+		self.bitrate = Some(value);
+		self
+	}
+
+	/// Set whether to use variable bitrate.
+	pub fn vbr(mut self, value: bool) -> EncoderConfig {
+		self.vbr = Some(value);
+		self
+	}
+
+	/// Set whether variable bitrate is constrained.
+	pub fn vbr_constraint(mut self, value: bool) -> EncoderConfig {
+		self.vbr_constraint = Some(value);
+		self
+	}
+
+	/// Set the computational complexity.
+	pub fn complexity(mut self, value: i32) -> EncoderConfig {
+		self.complexity = Some(value);
+		self
+	}
+
+	/// Set whether to include forward error correction.
+	pub fn inband_fec(mut self, value: bool) -> EncoderConfig {
+		self.inband_fec = Some(value);
+		self
+	}
+
+	/// Set the expected packet loss.
+	pub fn packet_loss_perc(mut self, value: i32) -> EncoderConfig {
+		self.packet_loss_perc = Some(value);
+		self
+	}
+
+	/// Set whether to use discontinuous transmission.
+	pub fn dtx(mut self, value: bool) -> EncoderConfig {
+		self.dtx = Some(value);
+		self
+	}
+
+	/// Set the type of signal being encoded.
+	pub fn signal(mut self, value: Signal) -> EncoderConfig {
+		self.signal = Some(value);
+		self
+	}
+
+	/// Set the maximum bandwidth.
+	pub fn max_bandwidth(mut self, value: Bandwidth) -> EncoderConfig {
+		self.max_bandwidth = Some(value);
+		self
+	}
+
+	/// Set the depth of the input signal.
+	pub fn lsb_depth(mut self, value: i32) -> EncoderConfig {
+		self.lsb_depth = Some(value);
+		self
+	}
+
+	/// Set the frame duration.
+	pub fn frame_duration(mut self, value: FrameSize) -> EncoderConfig {
+		self.frame_duration = Some(value);
+		self
+	}
+
+	/// Set whether prediction is disabled.
+	pub fn prediction_disabled(mut self, value: bool) -> EncoderConfig {
+		self.prediction_disabled = Some(value);
+		self
+	}
+}
+
+// ============================================================================
+// Stream Encoder
+
+/// Encodes PCM supplied in chunks of any length into packets of a fixed
+/// frame size.
+///
+/// Input is buffered until a whole frame is available. Once the input ends,
+/// [`finish`](#method.finish) pads the final partial frame with silence, and
+/// each packet records how much of its decoded output is padding.
+///
+/// Decoded output is delayed by the encoder's [lookahead](#method.lookahead),
+/// which should be discarded from the start of the stream, as with the Ogg
+/// Opus pre-skip. Packets are encoded until the decoded output covers this
+/// delay as well as the input.
+#[derive(Debug)]
+pub struct StreamEncoder {
+	encoder: Encoder,
+	channels: usize,
+	/// Samples per channel in each frame.
+	frame_size: usize,
+	/// The encoder's lookahead, in samples per channel.
+	lookahead: usize,
+	/// Input not yet encoded.
+	buffer: Vec<f32>,
+	/// Samples per channel of input received so far.
+	samples: u64,
+	/// The timestamp of the next packet.
+	timestamp: u64,
+	finished: bool,
+	packet: Vec<u8>,
+}
+
+/// A packet produced by a `StreamEncoder`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StreamPacket {
+	/// The encoded packet.
+	pub data: Vec<u8>,
+	/// The position of the packet's first decoded sample, counted per
+	/// channel at the encoder's sample rate from the start of the stream.
+	pub timestamp: u64,
+	/// The number of samples per channel the packet decodes to.
+	pub duration: usize,
+	/// The number of samples per channel at the end of the decoded packet
+	/// which are padding and should be discarded.
+	pub padding: usize,
+}
+
+impl StreamEncoder {
+	/// Wrap an encoder to produce packets of the given frame size.
+	///
+	/// The encoder's settings may still be changed through
+	/// [`encoder_mut`](#method.encoder_mut).
+	pub fn new(mut encoder: Encoder, frame_size: FrameSize) -> Result<StreamEncoder> {
+		let sample_rate = encoder.get_sample_rate()?;
+		let frame_size = match frame_size.samples(sample_rate) {
+			Some(samples) => samples,
+			None => return Err(Error::bad_arg("StreamEncoder::new")),
+		};
+		let lookahead = encoder.get_lookahead()? as usize;
+		Ok(StreamEncoder {
+			channels: encoder.channels as usize,
+			encoder,
+			frame_size,
+			lookahead,
+			buffer: Vec::new(),
+			samples: 0,
+			timestamp: 0,
+			finished: false,
+			packet: vec![0; 4000],
+		})
+	}
+
+	/// Get the number of samples per channel in each packet.
+	pub fn frame_size(&self) -> usize {
+		self.frame_size
+	}
+
+	/// Get the number of samples per channel to discard from the start of
+	/// the decoded stream.
+	pub fn lookahead(&self) -> usize {
+		self.lookahead
+	}
+
+	/// Get the timestamp the next packet will have.
+	pub fn timestamp(&self) -> u64 {
+		self.timestamp
+	}
+
+	/// Get the underlying encoder.
+	pub fn encoder_mut(&mut self) -> &mut Encoder {
+		&mut self.encoder
+	}
+
+	/// Unwrap the underlying encoder, discarding any buffered input.
+	pub fn into_inner(self) -> Encoder {
+		self.encoder
+	}
+
+	/// Buffer interleaved PCM.
+	pub fn write(&mut self, pcm: &[i16]) -> Result<()> {
+		self.check_input(pcm.len())?;
+		self.buffer.extend(pcm.iter().map(|&s| s as f32 / 32768.0));
+		Ok(())
+	}
+
+	/// Buffer interleaved floating point PCM.
+	pub fn write_float(&mut self, pcm: &[f32]) -> Result<()> {
+		self.check_input(pcm.len())?;
+		self.buffer.extend_from_slice(pcm);
+		Ok(())
+	}
+
+	/// Mark the end of the input, so that the remaining input is padded out
+	/// to whole packets.
+	///
+	/// No more input may be written afterwards, unless the encoder is
+	/// [reset](#method.reset).
+	pub fn finish(&mut self) {
+		self.finished = true;
+	}
+
+	/// Encode the next packet, if enough input has been written.
+	///
+	/// Returns `None` when the buffered input is less than a frame, or once
+	/// everything has been encoded after [`finish`](#method.finish).
+	pub fn next_packet(&mut self) -> Result<Option<StreamPacket>> {
+		let frame_len = self.frame_size * self.channels;
+		let end = self.samples + self.lookahead as u64;
+		let mut padding = 0;
+		if self.buffer.len() < frame_len {
+			if !self.finished || self.timestamp >= end {
+				return Ok(None);
+			}
+			padding = (self.timestamp + self.frame_size as u64).saturating_sub(end) as usize;
+			self.buffer.resize(frame_len, 0.0);
+		}
+		let len = self.encoder.encode_float(&self.buffer[..frame_len], &mut self.packet)?;
+		self.buffer.drain(..frame_len);
+		let packet = StreamPacket {
+			data: self.packet[..len].to_vec(),
+			timestamp: self.timestamp,
+			duration: self.frame_size,
+			padding,
+		};
+		self.timestamp += self.frame_size as u64;
+		Ok(Some(packet))
+	}
+
+	/// Discard buffered input and reset the encoder to start a new stream.
+	pub fn reset(&mut self) -> Result<()> {
+		self.encoder.reset_state()?;
+		self.buffer.clear();
+		self.samples = 0;
+		self.timestamp = 0;
+		self.finished = false;
+		Ok(())
+	}
+
+	fn check_input(&mut self, len: usize) -> Result<()> {
+		if self.finished || len % self.channels != 0 {
+			return Err(Error::bad_arg("StreamEncoder::write"));
+		}
+		self.samples += (len / self.channels) as u64;
+		Ok(())
+	}
+}
+
 // ============================================================================
 // Decoder
 
@@ -688,12 +1171,15 @@ encoder_ctls!(Encoder, opus_encoder_ctl);
 #[derive(Debug)]
 pub struct Decoder {
 	ptr: *mut ffi::OpusDecoder,
+	/// The length of the state memory, in `u64`s.
+	words: usize,
 	channels: Channels,
+	dnn_blob: Option<DnnBlob>,
 }
 
 impl Drop for Decoder {
 	fn drop(&mut self) {
-		unsafe { ffi::opus_decoder_destroy(self.ptr) }
+		drop(unsafe { StateMemory::from_raw(self.ptr as *mut u64, self.words) })
 	}
 }
 
@@ -703,14 +1189,36 @@ unsafe impl Send for Decoder {}
 impl Decoder {
 	/// Create and initialize a decoder.
 	pub fn new(sample_rate: u32, channels: Channels) -> Result<Decoder> {
-		let mut error = 0;
-		let ptr =
-			unsafe { ffi::opus_decoder_create(sample_rate as i32, channels as c_int, &mut error) };
-		if error != ffi::OPUS_OK || ptr.is_null() {
-			Err(Error::from_code("opus_decoder_create", error))
-		} else {
-			Ok(Decoder { ptr, channels })
+		Decoder::with_memory(sample_rate, channels, StateMemory::for_decoder(channels))
+	}
+
+	/// Create and initialize a decoder in existing memory, which must be at
+	/// least the size of `StateMemory::for_decoder(channels)`.
+	pub fn with_memory(
+		sample_rate: u32,
+		channels: Channels,
+		memory: StateMemory,
+	) -> Result<Decoder> {
+		if memory.size() < StateMemory::decoder_size(channels) {
+			return Err(Error::bad_arg("Decoder::with_memory"));
 		}
+		let (ptr, words) = memory.into_raw();
+		let decoder = Decoder {
+			ptr: ptr as *mut ffi::OpusDecoder,
+			words,
+			channels,
+			dnn_blob: None,
+		};
+		ffi!(opus_decoder_init, decoder.ptr, sample_rate as i32, channels as c_int);
+		Ok(decoder)
+	}
+
+	/// Destroy the decoder, keeping its memory for reuse.
+	pub fn into_memory(mut self) -> StateMemory {
+		let memory = unsafe { StateMemory::from_raw(self.ptr as *mut u64, self.words) };
+		self.dnn_blob = None;
+		std::mem::forget(self);
+		memory
 	}
 
 	/// Decode an Opus packet.
@@ -767,9 +1275,30 @@ impl Decoder {
 }
 
 macro_rules! decoder_ctls {
-	($t:ty, $fn:ident) => {
+	(impl<$($l:lifetime),*> $t:ty, $fn:ident) => {
 		/// Decoder CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__decoderctls.html).
-		impl $t {
+		impl<$($l),*> $t {
+			/// Configures the decoder's computational complexity.
+			///
+			/// Since Opus 1.5, 5 enables deep packet loss concealment, 6 enables
+			/// the LACE speech enhancer and 7 enables NoLACE, each only if
+			/// libopus was built with support for them (`--enable-deep-plc` and
+			/// `--enable-osce`). Older libopus returns an `Unimplemented` error
+			/// whose `missing_feature` is `LibraryFeature::DeepPlc`.
+			pub fn set_complexity(&mut self, value: i32) -> Result<()> {
+				let request = ffi::OPUS_SET_COMPLEXITY_REQUEST;
+				feature_ctl!(LibraryFeature::DeepPlc, $fn, self, request, value);
+				Ok(())
+			}
+
+			/// Gets the decoder's complexity configuration.
+			pub fn get_complexity(&mut self) -> Result<i32> {
+				let mut value: i32 = 0;
+				let request = ffi::OPUS_GET_COMPLEXITY_REQUEST;
+				feature_ctl!(LibraryFeature::DeepPlc, $fn, self, request, &mut value);
+				Ok(value)
+			}
+
 			/// Configures decoder gain adjustment.
 			///
 			/// Scales the decoded output by a factor specified in Q8 dB units. This has
@@ -811,125 +1340,823 @@ macro_rules! decoder_ctls {
 			}
 		}
 	};
+	($t:ty, $fn:ident) => {
+		decoder_ctls!(impl<> $t, $fn);
+	};
 }
 
 generic_ctls!(Decoder, opus_decoder_ctl);
 decoder_ctls!(Decoder, opus_decoder_ctl);
 
 // ============================================================================
-// Packet Analysis
+// State Memory
 
-/// Analyze raw Opus packets.
-pub mod packet {
-	use super::ffi;
-	use super::*;
-	use std::{ptr, slice};
+// Encoder and decoder states are flat memory without pointers into
+// themselves, so they are allocated here rather than by libopus. This lets
+// them be copied byte for byte, and their memory be reused.
 
-	/// Get the bandwidth of an Opus packet.
-	pub fn get_bandwidth(packet: &[u8]) -> Result<Bandwidth> {
-		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_bandwidth"));
+/// Memory to hold an encoder or decoder state, so that it can be reused
+/// rather than allocated for every codec.
+///
+/// Codecs are created in it with `with_memory`, and give it back with
+/// `into_memory`.
+#[derive(Clone)]
+pub struct StateMemory {
+	data: Box<[u64]>,
+}
+
+impl StateMemory {
+	/// Allocate memory of at least `size` bytes.
+	pub fn new(size: usize) -> StateMemory {
+		StateMemory {
+			data: vec![0; (size + 7) / 8].into_boxed_slice(),
 		}
-		let bandwidth = ffi!(opus_packet_get_bandwidth, packet.as_ptr());
-		Bandwidth::decode(bandwidth, "opus_packet_get_bandwidth")
 	}
 
-	/// Get the number of channels from an Opus packet.
-	pub fn get_nb_channels(packet: &[u8]) -> Result<Channels> {
-		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_nb_channels"));
-		}
-		let channels = ffi!(opus_packet_get_nb_channels, packet.as_ptr());
-		match channels {
-			1 => Ok(Channels::Mono),
-			2 => Ok(Channels::Stereo),
-			_ => Err(Error::bad_arg("opus_packet_get_nb_channels")),
-		}
+	/// Allocate memory for an encoder with the given channels.
+	pub fn for_encoder(channels: Channels) -> StateMemory {
+		StateMemory::new(StateMemory::encoder_size(channels))
 	}
 
-	/// Get the number of frames in an Opus packet.
-	pub fn get_nb_frames(packet: &[u8]) -> Result<usize> {
-		let frames = ffi!(opus_packet_get_nb_frames, packet.as_ptr(), len(packet));
-		Ok(frames as usize)
+	/// Allocate memory for a decoder with the given channels.
+	pub fn for_decoder(channels: Channels) -> StateMemory {
+		StateMemory::new(StateMemory::decoder_size(channels))
 	}
 
-	/// Get the number of samples of an Opus packet.
-	pub fn get_nb_samples(packet: &[u8], sample_rate: u32) -> Result<usize> {
-		let frames =
-			ffi!(opus_packet_get_nb_samples, packet.as_ptr(), len(packet), sample_rate as c_int);
-		Ok(frames as usize)
+	/// Get the size of the memory in bytes.
+	pub fn size(&self) -> usize {
+		self.data.len() * 8
 	}
 
-	/// Get the number of samples per frame from an Opus packet.
-	pub fn get_samples_per_frame(packet: &[u8], sample_rate: u32) -> Result<usize> {
-		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_samples_per_frame"));
-		}
-		let samples =
-			ffi!(opus_packet_get_samples_per_frame, packet.as_ptr(), sample_rate as c_int);
-		Ok(samples as usize)
+	fn encoder_size(channels: Channels) -> usize {
+		unsafe { ffi::opus_encoder_get_size(channels as c_int) as usize }
 	}
 
-	/// Parse an Opus packet into one or more frames.
-	pub fn parse(packet: &[u8]) -> Result<Packet> {
-		let mut toc: u8 = 0;
-		let mut frames = [ptr::null(); 48];
-		let mut sizes = [0i16; 48];
-		let mut payload_offset: i32 = 0;
-		let num_frames = ffi!(
-			opus_packet_parse,
-			packet.as_ptr(),
-			len(packet),
-			&mut toc,
-			frames.as_mut_ptr(),
-			sizes.as_mut_ptr(),
-			&mut payload_offset
-		);
+	fn decoder_size(channels: Channels) -> usize {
+		unsafe { ffi::opus_decoder_get_size(channels as c_int) as usize }
+	}
 
-		let mut frames_vec = Vec::with_capacity(num_frames as usize);
-		for i in 0..num_frames as usize {
-			frames_vec.push(unsafe { slice::from_raw_parts(frames[i], sizes[i] as usize) });
+	/// Copy `size` bytes of a codec state.
+	unsafe fn copy(ptr: *const u64, size: usize) -> StateMemory {
+		StateMemory {
+			data: std::slice::from_raw_parts(ptr, (size + 7) / 8).into(),
 		}
+	}
 
-		Ok(Packet {
-			toc,
-			frames: frames_vec,
-			payload_offset: payload_offset as usize,
-		})
+	/// Copy the memory into a codec state, which must be at least as large.
+	unsafe fn copy_to(&self, ptr: *mut u64) {
+		std::ptr::copy_nonoverlapping(self.data.as_ptr(), ptr, self.data.len());
 	}
 
-	/// A parsed Opus packet, retuned from `parse`.
-	#[derive(Debug)]
-	pub struct Packet<'a> {
-		/// The TOC byte of the packet.
-		pub toc: u8,
-		/// The frames contained in the packet.
-		pub frames: Vec<&'a [u8]>,
-		/// The offset into the packet at which the payload is located.
-		pub payload_offset: usize,
+	/// Hand the memory over to a codec, as a pointer and length in `u64`s.
+	fn into_raw(self) -> (*mut u64, usize) {
+		let words = self.data.len();
+		(Box::into_raw(self.data) as *mut u64, words)
 	}
 
-	/// Pad a given Opus packet to a larger size.
-	///
-	/// The packet will be extended from the first `prev_len` bytes of the
-	/// buffer into the rest of the available space.
-	pub fn pad(packet: &mut [u8], prev_len: usize) -> Result<usize> {
-		let result = ffi!(opus_packet_pad, packet.as_mut_ptr(), check_len(prev_len), len(packet));
-		Ok(result as usize)
+	/// Take back memory from `into_raw`.
+	unsafe fn from_raw(ptr: *mut u64, words: usize) -> StateMemory {
+		StateMemory {
+			data: Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, words)),
+		}
 	}
+}
 
-	/// Remove all padding from a given Opus packet and rewrite the TOC sequence
-	/// to minimize space usage.
-	pub fn unpad(packet: &mut [u8]) -> Result<usize> {
-		let result = ffi!(opus_packet_unpad, packet.as_mut_ptr(), len(packet));
-		Ok(result as usize)
+impl std::fmt::Debug for StateMemory {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("StateMemory").field("size", &self.size()).finish()
 	}
+}
 
-	/// Pad a given Opus multi-stream packet to a larger size.
-	///
-	/// The packet will be extended from the first `prev_len` bytes of the
-	/// buffer into the rest of the available space.
-	pub fn multistream_pad(packet: &mut [u8], prev_len: usize, nb_streams: u8) -> Result<usize> {
+impl Clone for Encoder {
+	fn clone(&self) -> Encoder {
+		let size = StateMemory::encoder_size(self.channels);
+		let (ptr, words) = unsafe { StateMemory::copy(self.ptr as *const u64, size) }.into_raw();
+		Encoder {
+			ptr: ptr as *mut ffi::OpusEncoder,
+			words,
+			channels: self.channels,
+			dnn_blob: self.dnn_blob.clone(),
+		}
+	}
+}
+
+impl Clone for Decoder {
+	fn clone(&self) -> Decoder {
+		let size = StateMemory::decoder_size(self.channels);
+		let (ptr, words) = unsafe { StateMemory::copy(self.ptr as *const u64, size) }.into_raw();
+		Decoder {
+			ptr: ptr as *mut ffi::OpusDecoder,
+			words,
+			channels: self.channels,
+			dnn_blob: self.dnn_blob.clone(),
+		}
+	}
+}
+
+/// A saved encoder state, returned from `Encoder::snapshot`.
+#[derive(Debug, Clone)]
+pub struct EncoderSnapshot {
+	state: StateMemory,
+	channels: Channels,
+	dnn_blob: Option<DnnBlob>,
+}
+
+impl Encoder {
+	/// Save the encoder's state, so it can be returned to with `restore`.
+	pub fn snapshot(&self) -> EncoderSnapshot {
+		let size = StateMemory::encoder_size(self.channels);
+		EncoderSnapshot {
+			state: unsafe { StateMemory::copy(self.ptr as *const u64, size) },
+			channels: self.channels,
+			dnn_blob: self.dnn_blob.clone(),
+		}
+	}
+
+	/// Return to a saved state, which must be from an encoder with the same
+	/// number of channels.
+	pub fn restore(&mut self, snapshot: &EncoderSnapshot) -> Result<()> {
+		if snapshot.channels != self.channels {
+			return Err(Error::bad_arg("Encoder::restore"));
+		}
+		unsafe { snapshot.state.copy_to(self.ptr as *mut u64) };
+		self.dnn_blob = snapshot.dnn_blob.clone();
+		Ok(())
+	}
+}
+
+/// A saved decoder state, returned from `Decoder::snapshot`.
+#[derive(Debug, Clone)]
+pub struct DecoderSnapshot {
+	state: StateMemory,
+	channels: Channels,
+	dnn_blob: Option<DnnBlob>,
+}
+
+impl Decoder {
+	/// Save the decoder's state, so it can be returned to with `restore`.
+	pub fn snapshot(&self) -> DecoderSnapshot {
+		let size = StateMemory::decoder_size(self.channels);
+		DecoderSnapshot {
+			state: unsafe { StateMemory::copy(self.ptr as *const u64, size) },
+			channels: self.channels,
+			dnn_blob: self.dnn_blob.clone(),
+		}
+	}
+
+	/// Return to a saved state, which must be from a decoder with the same
+	/// number of channels.
+	pub fn restore(&mut self, snapshot: &DecoderSnapshot) -> Result<()> {
+		if snapshot.channels != self.channels {
+			return Err(Error::bad_arg("Decoder::restore"));
+		}
+		unsafe { snapshot.state.copy_to(self.ptr as *mut u64) };
+		self.dnn_blob = snapshot.dnn_blob.clone();
+		Ok(())
+	}
+}
+
+/// Recycles the memory of decoders, for servers which create and destroy
+/// many of them.
+#[derive(Debug)]
+pub struct DecoderPool {
+	sample_rate: u32,
+	channels: Channels,
+	idle: Vec<StateMemory>,
+}
+
+impl DecoderPool {
+	/// Create an empty pool of decoders with the given format.
+	pub fn new(sample_rate: u32, channels: Channels) -> DecoderPool {
+		DecoderPool { sample_rate, channels, idle: Vec::new() }
+	}
+
+	/// Get a decoder, reusing the memory of one returned to the pool if
+	/// there is any.
+	///
+	/// The decoder is freshly initialized, as from `Decoder::new`, so
+	/// settings such as gain do not carry over from its last use.
+	pub fn get(&mut self) -> Result<Decoder> {
+		let memory = match self.idle.pop() {
+			Some(memory) => memory,
+			None => StateMemory::for_decoder(self.channels),
+		};
+		Decoder::with_memory(self.sample_rate, self.channels, memory)
+	}
+
+	/// Return a decoder to the pool so its memory can be reused.
+	///
+	/// Decoders with fewer channels than the pool's are simply dropped.
+	pub fn put(&mut self, decoder: Decoder) {
+		let memory = decoder.into_memory();
+		if memory.size() >= StateMemory::decoder_size(self.channels) {
+			self.idle.push(memory);
+		}
+	}
+
+	/// Get the number of decoders' memory waiting to be reused.
+	pub fn idle(&self) -> usize {
+		self.idle.len()
+	}
+
+	/// Free idle memory until at most `max` remain.
+	pub fn shrink_to(&mut self, max: usize) {
+		self.idle.truncate(max);
+	}
+}
+
+// ============================================================================
+// Packet Analysis
+
+/// Analyze raw Opus packets.
+pub mod packet {
+	use super::ffi;
+	use super::*;
+
+	/// Get the bandwidth of an Opus packet.
+	pub fn get_bandwidth(packet: &[u8]) -> Result<Bandwidth> {
+		if packet.is_empty() {
+			return Err(Error::bad_arg("opus_packet_get_bandwidth"));
+		}
+		let bandwidth = ffi!(opus_packet_get_bandwidth, packet.as_ptr());
+		Bandwidth::decode(bandwidth, "opus_packet_get_bandwidth")
+	}
+
+	/// Get the number of channels from an Opus packet.
+	pub fn get_nb_channels(packet: &[u8]) -> Result<Channels> {
+		if packet.is_empty() {
+			return Err(Error::bad_arg("opus_packet_get_nb_channels"));
+		}
+		let channels = ffi!(opus_packet_get_nb_channels, packet.as_ptr());
+		match channels {
+			1 => Ok(Channels::Mono),
+			2 => Ok(Channels::Stereo),
+			_ => Err(Error::bad_arg("opus_packet_get_nb_channels")),
+		}
+	}
+
+	/// Get the number of frames in an Opus packet.
+	pub fn get_nb_frames(packet: &[u8]) -> Result<usize> {
+		let frames = ffi!(opus_packet_get_nb_frames, packet.as_ptr(), len(packet));
+		Ok(frames as usize)
+	}
+
+	/// Get the number of samples of an Opus packet.
+	pub fn get_nb_samples(packet: &[u8], sample_rate: u32) -> Result<usize> {
+		let frames =
+			ffi!(opus_packet_get_nb_samples, packet.as_ptr(), len(packet), sample_rate as c_int);
+		Ok(frames as usize)
+	}
+
+	/// Get the number of samples per frame from an Opus packet.
+	pub fn get_samples_per_frame(packet: &[u8], sample_rate: u32) -> Result<usize> {
+		if packet.is_empty() {
+			return Err(Error::bad_arg("opus_packet_get_samples_per_frame"));
+		}
+		let samples =
+			ffi!(opus_packet_get_samples_per_frame, packet.as_ptr(), sample_rate as c_int);
+		Ok(samples as usize)
+	}
+
+	/// The longest duration a packet may have: 120 ms at 48 kHz.
+	const MAX_PACKET_SAMPLES: usize = 5760;
+
+	/// The largest size of a single frame.
+	const MAX_FRAME_BYTES: usize = 1275;
+
+	/// The coding mode of an Opus packet.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	pub enum Mode {
+		/// Linear prediction, for speech at lower bandwidths.
+		Silk,
+		/// Linear prediction for the low band and MDCT above it.
+		Hybrid,
+		/// MDCT, for music and low delay.
+		Celt,
+	}
+
+	/// The table-of-contents byte which starts every Opus packet.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	pub struct Toc(pub u8);
+
+	impl Toc {
+		/// Get the configuration number, which selects the mode, bandwidth
+		/// and frame duration.
+		pub fn config(self) -> u8 {
+			self.0 >> 3
+		}
+
+		/// Get the coding mode.
+		pub fn mode(self) -> Mode {
+			match self.config() {
+				0..=11 => Mode::Silk,
+				12..=15 => Mode::Hybrid,
+				_ => Mode::Celt,
+			}
+		}
+
+		/// Get the audio bandwidth.
+		pub fn bandwidth(self) -> Bandwidth {
+			match self.config() {
+				0..=3 | 16..=19 => Bandwidth::Narrowband,
+				4..=7 => Bandwidth::Mediumband,
+				8..=11 | 20..=23 => Bandwidth::Wideband,
+				12..=13 | 24..=27 => Bandwidth::Superwideband,
+				_ => Bandwidth::Fullband,
+			}
+		}
+
+		/// Get the number of samples per frame at 48 kHz.
+		pub fn frame_size(self) -> usize {
+			let config = self.config() as usize;
+			match self.mode() {
+				Mode::Silk => [480, 960, 1920, 2880][config & 3],
+				Mode::Hybrid => [480, 960][config & 1],
+				Mode::Celt => [120, 240, 480, 960][config & 3],
+			}
+		}
+
+		/// Get whether the frames are coded in stereo.
+		pub fn stereo(self) -> bool {
+			self.0 & 0x04 != 0
+		}
+
+		/// Get the frame count code: 0 for one frame, 1 for two frames of
+		/// equal size, 2 for two frames of different sizes, or 3 for an
+		/// arbitrary number of frames.
+		pub fn code(self) -> u8 {
+			self.0 & 0x03
+		}
+	}
+
+	/// The reasons a packet can fail to parse.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	pub enum ParseError {
+		/// The packet is empty, so has no TOC byte.
+		Empty,
+		/// A code 3 packet is missing its frame count byte.
+		MissingFrameCount,
+		/// A code 3 packet has a frame count of zero.
+		NoFrames,
+		/// The frames add up to more than 120 ms.
+		TooLong,
+		/// The packet ends in the middle of a frame length.
+		TruncatedLength,
+		/// The packet ends in the middle of the padding length.
+		TruncatedPadding,
+		/// The padding is longer than the rest of the packet.
+		PaddingOverflow,
+		/// The frame lengths add up to more than the packet holds.
+		FramesOverflow,
+		/// The space for constant-size frames does not divide evenly.
+		UnevenFrames,
+		/// A frame is larger than the 1275 bytes allowed.
+		FrameTooLarge,
+		/// The padding ends in the middle of an extension.
+		TruncatedExtension,
+		/// An extension refers to a frame past the end of the packet.
+		ExtensionFrame,
+	}
+
+	impl ParseError {
+		/// Get a description of the problem.
+		pub fn description(self) -> &'static str {
+			match self {
+				ParseError::Empty => "packet is empty",
+				ParseError::MissingFrameCount => "code 3 packet is missing its frame count",
+				ParseError::NoFrames => "code 3 packet has no frames",
+				ParseError::TooLong => "packet is longer than 120 ms",
+				ParseError::TruncatedLength => "packet ends within a frame length",
+				ParseError::TruncatedPadding => "packet ends within the padding length",
+				ParseError::PaddingOverflow => "padding is longer than the packet",
+				ParseError::FramesOverflow => "frames are longer than the packet",
+				ParseError::UnevenFrames => "constant-size frames do not divide the packet",
+				ParseError::FrameTooLarge => "frame is larger than 1275 bytes",
+				ParseError::TruncatedExtension => "padding ends within an extension",
+				ParseError::ExtensionFrame => "extension refers to a frame past the end",
+			}
+		}
+	}
+
+	impl std::fmt::Display for ParseError {
+		fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+			f.write_str(self.description())
+		}
+	}
+
+	impl std::error::Error for ParseError {}
+
+	impl From<ParseError> for Error {
+		fn from(e: ParseError) -> Error {
+			Error {
+				function: "packet::parse",
+				code: ErrorCode::InvalidPacket,
+				parse: Some(e),
+				missing: None,
+			}
+		}
+	}
+
+	/// Read a one or two byte frame length.
+	fn read_length(data: &mut &[u8]) -> std::result::Result<usize, ParseError> {
+		let (len, used) = match **data {
+			[first, ..] if first < 252 => (first as usize, 1),
+			[first, second, ..] => (4 * second as usize + first as usize, 2),
+			_ => return Err(ParseError::TruncatedLength),
+		};
+		*data = &data[used..];
+		Ok(len)
+	}
+
+	/// Read a code 3 padding length, where each byte of 255 adds 254 bytes
+	/// and continues.
+	fn read_padding_length(data: &mut &[u8]) -> std::result::Result<usize, ParseError> {
+		let mut len = 0;
+		loop {
+			let byte = match data.first() {
+				Some(&byte) => byte,
+				None => return Err(ParseError::TruncatedPadding),
+			};
+			*data = &data[1..];
+			if byte < 255 {
+				return Ok(len + byte as usize);
+			}
+			len += 254;
+		}
+	}
+
+	/// Parse an Opus packet into one or more frames.
+	///
+	/// If the packet is malformed, the reason is available from
+	/// `Error::parse_error`.
+	pub fn parse(packet: &[u8]) -> Result<Packet<'_>> {
+		Ok(parse_detailed(packet)?.packet)
+	}
+
+	/// Parse an Opus packet into one or more frames and its padding,
+	/// returning why it is malformed on failure.
+	pub fn parse_detailed(packet: &[u8]) -> std::result::Result<ParsedPacket<'_>, ParseError> {
+		let toc = match packet.first() {
+			Some(&toc) => Toc(toc),
+			None => return Err(ParseError::Empty),
+		};
+		let mut data = &packet[1..];
+		let mut padding: &[u8] = &[];
+		let mut sizes = Vec::new();
+
+		let count = match toc.code() {
+			0 => 1,
+			1 => 2,
+			2 => {
+				sizes.push(read_length(&mut data)?);
+				2
+			}
+			_ => {
+				let flags = match data.first() {
+					Some(&flags) => flags,
+					None => return Err(ParseError::MissingFrameCount),
+				};
+				data = &data[1..];
+				let count = (flags & 0x3f) as usize;
+				if count == 0 {
+					return Err(ParseError::NoFrames);
+				}
+				if count * toc.frame_size() > MAX_PACKET_SAMPLES {
+					return Err(ParseError::TooLong);
+				}
+				if flags & 0x40 != 0 {
+					let len = read_padding_length(&mut data)?;
+					if len > data.len() {
+						return Err(ParseError::PaddingOverflow);
+					}
+					let (rest, pad) = data.split_at(data.len() - len);
+					data = rest;
+					padding = pad;
+				}
+				if flags & 0x80 != 0 {
+					for _ in 1..count {
+						sizes.push(read_length(&mut data)?);
+					}
+				}
+				count
+			}
+		};
+		let payload_offset = packet.len() - padding.len() - data.len();
+
+		if sizes.len() < count - 1 {
+			// Constant-size frames share the remaining space.
+			if data.len() % count != 0 {
+				return Err(ParseError::UnevenFrames);
+			}
+			sizes = vec![data.len() / count; count - 1];
+		}
+		let total: usize = sizes.iter().sum();
+		if total > data.len() {
+			return Err(ParseError::FramesOverflow);
+		}
+		sizes.push(data.len() - total);
+
+		let mut frames = Vec::with_capacity(count);
+		for size in sizes {
+			if size > MAX_FRAME_BYTES {
+				return Err(ParseError::FrameTooLarge);
+			}
+			let (frame, rest) = data.split_at(size);
+			frames.push(frame);
+			data = rest;
+		}
+
+		Ok(ParsedPacket {
+			packet: Packet { toc: toc.0, frames, payload_offset },
+			padding,
+		})
+	}
+
+	/// A parsed Opus packet, retuned from `parse`.
+	#[derive(Debug)]
+	pub struct Packet<'a> {
+		/// The TOC byte of the packet.
+		pub toc: u8,
+		/// The frames contained in the packet.
+		pub frames: Vec<&'a [u8]>,
+		/// The offset into the packet at which the payload is located.
+		pub payload_offset: usize,
+	}
+
+	impl<'a> Packet<'a> {
+		/// Get the decoded TOC byte.
+		pub fn config(&self) -> Toc {
+			Toc(self.toc)
+		}
+
+		/// Get the number of samples in the packet at 48 kHz.
+		pub fn samples(&self) -> usize {
+			self.frames.len() * self.config().frame_size()
+		}
+	}
+
+	/// A parsed Opus packet along with its padding, returned from
+	/// `parse_detailed`.
+	#[derive(Debug)]
+	pub struct ParsedPacket<'a> {
+		/// The packet's TOC byte and frames.
+		pub packet: Packet<'a>,
+		/// The padding at the end of the packet.
+		pub padding: &'a [u8],
+	}
+
+	impl<'a> ParsedPacket<'a> {
+		/// Get the extensions carried in the padding, in order.
+		pub fn extensions(&self) -> std::result::Result<Vec<Extension<'a>>, ParseError> {
+			parse_extensions(self.padding, self.packet.frames.len())
+		}
+	}
+
+	/// Extension IDs below this carry at most one byte of data.
+	const FIRST_LONG_EXTENSION: u8 = 32;
+
+	/// A piece of side data attached to a frame through the packet padding,
+	/// as introduced in libopus 1.5.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	pub struct Extension<'a> {
+		/// The extension ID, from 2 to 127. IDs below 32 carry at most one
+		/// byte of data.
+		pub id: u8,
+		/// The index of the frame within the packet the data belongs to.
+		pub frame: usize,
+		/// The extension data.
+		pub data: &'a [u8],
+	}
+
+	/// Parse the extensions in the padding of a packet with the given
+	/// number of frames.
+	pub fn parse_extensions(
+		padding: &[u8],
+		frames: usize,
+	) -> std::result::Result<Vec<Extension<'_>>, ParseError> {
+		let mut data = padding;
+		let mut frame = 0;
+		let mut extensions = Vec::new();
+		while let Some(&header) = data.first() {
+			let (id, long) = (header >> 1, header & 1 != 0);
+			match id {
+				// Padding: a single byte, or everything that remains.
+				0 if long => data = &data[1..],
+				0 => break,
+				// Separator: move on to the next frame, or skip several.
+				1 => {
+					let step = match (long, data.get(1)) {
+						(false, _) => 1,
+						(true, Some(&step)) => step as usize,
+						(true, None) => return Err(ParseError::TruncatedExtension),
+					};
+					frame += step;
+					if frame >= frames {
+						return Err(ParseError::ExtensionFrame);
+					}
+					data = &data[1 + long as usize..];
+				}
+				2..=31 => {
+					let len = long as usize;
+					if data.len() < 1 + len {
+						return Err(ParseError::TruncatedExtension);
+					}
+					extensions.push(Extension { id, frame, data: &data[1..1 + len] });
+					data = &data[1 + len..];
+				}
+				// Without a length, the last extension takes all that remains.
+				_ if !long => {
+					extensions.push(Extension { id, frame, data: &data[1..] });
+					break;
+				}
+				_ => {
+					let mut start = 1;
+					let mut len = 0;
+					loop {
+						let byte = match data.get(start) {
+							Some(&byte) => byte,
+							None => return Err(ParseError::TruncatedExtension),
+						};
+						start += 1;
+						len += byte as usize;
+						if byte != 255 {
+							break;
+						}
+					}
+					if data.len() - start < len {
+						return Err(ParseError::TruncatedExtension);
+					}
+					extensions.push(Extension { id, frame, data: &data[start..start + len] });
+					data = &data[start + len..];
+				}
+			}
+		}
+		Ok(extensions)
+	}
+
+	/// Append encoded extensions for a packet with the given number of
+	/// frames.
+	fn write_extensions(out: &mut Vec<u8>, extensions: &[Extension], frames: usize) -> Result<()> {
+		for extension in extensions {
+			let valid = match extension.id {
+				2..=31 => extension.data.len() <= 1,
+				32..=127 => true,
+				_ => false,
+			};
+			if !valid || extension.frame >= frames {
+				return Err(Error::bad_arg("packet::build"));
+			}
+		}
+
+		let mut current = 0;
+		let mut written = 0;
+		for frame in 0..frames {
+			for extension in extensions.iter().filter(|e| e.frame == frame) {
+				match frame - current {
+					0 => {}
+					1 => out.push(0x02),
+					step => out.extend_from_slice(&[0x03, step as u8]),
+				}
+				current = frame;
+				written += 1;
+
+				let len = extension.data.len();
+				if extension.id < FIRST_LONG_EXTENSION {
+					out.push(extension.id << 1 | len as u8);
+				} else if written == extensions.len() {
+					// The last extension needs no length.
+					out.push(extension.id << 1);
+				} else {
+					out.push(extension.id << 1 | 1);
+					out.extend(std::iter::repeat(255).take(len / 255));
+					out.push((len % 255) as u8);
+				}
+				out.extend_from_slice(extension.data);
+			}
+		}
+		Ok(())
+	}
+
+	/// Append a frame length in its one or two byte form.
+	fn write_length(out: &mut Vec<u8>, len: usize) {
+		if len < 252 {
+			out.push(len as u8);
+		} else {
+			let first = 252 + (len & 3);
+			out.push(first as u8);
+			out.push(((len - first) / 4) as u8);
+		}
+	}
+
+	/// Build a packet from frames which share a TOC configuration, and any
+	/// extensions to carry in its padding.
+	///
+	/// The frame count code is chosen to use as little space as possible,
+	/// so the code bits of `toc` are ignored.
+	pub fn build(toc: Toc, frames: &[&[u8]], extensions: &[Extension]) -> Result<Vec<u8>> {
+		const WHAT: &str = "packet::build";
+		if frames.is_empty() || frames.len() * toc.frame_size() > MAX_PACKET_SAMPLES {
+			return Err(Error::bad_arg(WHAT));
+		}
+		if frames.iter().any(|f| f.len() > MAX_FRAME_BYTES) {
+			return Err(Error::bad_arg(WHAT));
+		}
+		let toc = toc.0 & 0xfc;
+		let cbr = frames.iter().all(|f| f.len() == frames[0].len());
+
+		let mut padding = Vec::new();
+		write_extensions(&mut padding, extensions, frames.len())?;
+
+		let mut out = Vec::new();
+		match frames.len() {
+			1 if padding.is_empty() => out.push(toc),
+			2 if padding.is_empty() && cbr => out.push(toc | 1),
+			2 if padding.is_empty() => {
+				out.push(toc | 2);
+				write_length(&mut out, frames[0].len());
+			}
+			count => {
+				out.push(toc | 3);
+				let vbr = if cbr { 0 } else { 0x80 };
+				let padded = if padding.is_empty() { 0 } else { 0x40 };
+				out.push(vbr | padded | count as u8);
+				if !padding.is_empty() {
+					// Each length byte of 255 adds 254 bytes and continues.
+					out.extend(std::iter::repeat(255).take(padding.len() / 254));
+					out.push((padding.len() % 254) as u8);
+				}
+				if !cbr {
+					for frame in &frames[..count - 1] {
+						write_length(&mut out, frame.len());
+					}
+				}
+			}
+		}
+		for frame in frames {
+			out.extend_from_slice(frame);
+		}
+		out.extend_from_slice(&padding);
+		Ok(out)
+	}
+
+	/// Add extensions to a packet, after any it already carries.
+	pub fn add_extensions(packet: &[u8], extensions: &[Extension]) -> Result<Vec<u8>> {
+		let parsed = parse_detailed(packet)?;
+		let mut all = parsed.extensions()?;
+		all.extend_from_slice(extensions);
+		build(parsed.packet.config(), &parsed.packet.frames, &all)
+	}
+
+	/// Concatenate packets with the same configuration into one, keeping the
+	/// extensions of each with their frames.
+	///
+	/// Unlike `Repacketizer`, this does not drop the padding of the input
+	/// packets when built against libopus older than 1.5.
+	pub fn combine(packets: &[&[u8]]) -> Result<Vec<u8>> {
+		let mut toc = None;
+		let mut frames = Vec::new();
+		let mut extensions = Vec::new();
+		for packet in packets {
+			let parsed = parse_detailed(packet)?;
+			match toc {
+				None => toc = Some(parsed.packet.toc & 0xfc),
+				Some(toc) if toc != parsed.packet.toc & 0xfc => {
+					return Err(Error::invalid_packet("packet::combine"))
+				}
+				_ => {}
+			}
+			for extension in parsed.extensions()? {
+				extensions.push(Extension { frame: extension.frame + frames.len(), ..extension });
+			}
+			frames.extend_from_slice(&parsed.packet.frames);
+		}
+		match toc {
+			Some(toc) => build(Toc(toc), &frames, &extensions),
+			None => Err(Error::bad_arg("packet::combine")),
+		}
+	}
+
+	/// Pad a given Opus packet to a larger size.
+	///
+	/// The packet will be extended from the first `prev_len` bytes of the
+	/// buffer into the rest of the available space.
+	pub fn pad(packet: &mut [u8], prev_len: usize) -> Result<usize> {
+		let result = ffi!(opus_packet_pad, packet.as_mut_ptr(), check_len(prev_len), len(packet));
+		Ok(result as usize)
+	}
+
+	/// Remove all padding from a given Opus packet and rewrite the TOC sequence
+	/// to minimize space usage.
+	pub fn unpad(packet: &mut [u8]) -> Result<usize> {
+		let result = ffi!(opus_packet_unpad, packet.as_mut_ptr(), len(packet));
+		Ok(result as usize)
+	}
+
+	/// Pad a given Opus multi-stream packet to a larger size.
+	///
+	/// The packet will be extended from the first `prev_len` bytes of the
+	/// buffer into the rest of the available space.
+	pub fn multistream_pad(packet: &mut [u8], prev_len: usize, nb_streams: u8) -> Result<usize> {
 		let result = ffi!(
 			opus_multistream_packet_pad,
 			packet.as_mut_ptr(),
@@ -953,6 +2180,104 @@ pub mod packet {
 	}
 }
 
+// ============================================================================
+// Neural Network Weights
+
+// The bindings predate Opus 1.5, so the request is declared here.
+const OPUS_SET_DNN_BLOB_REQUEST: c_int = 4052;
+
+/// Weights for the neural networks used by DRED, deep packet loss
+/// concealment and OSCE, for libopus builds which do not include them.
+///
+/// libopus does not copy the weights, so codecs keep a reference to the blob
+/// for as long as they use it. Cloning is cheap, so one blob can be shared by
+/// many codecs.
+#[derive(Clone)]
+pub struct DnnBlob {
+	data: Arc<[u8]>,
+}
+
+impl DnnBlob {
+	/// Create a blob from weights in memory.
+	pub fn new(data: &[u8]) -> DnnBlob {
+		DnnBlob { data: data.into() }
+	}
+
+	/// Read a blob from a weights file, as written by libopus's
+	/// `write_lpcnet_weights` tool.
+	pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<DnnBlob> {
+		Ok(DnnBlob::from(std::fs::read(path)?))
+	}
+
+	/// Get the weights.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.data
+	}
+}
+
+impl From<Vec<u8>> for DnnBlob {
+	fn from(data: Vec<u8>) -> DnnBlob {
+		DnnBlob { data: data.into() }
+	}
+}
+
+impl std::fmt::Debug for DnnBlob {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("DnnBlob").field("len", &self.data.len()).finish()
+	}
+}
+
+impl Encoder {
+	/// Load neural network weights, used by DRED.
+	///
+	/// Returns an `Unimplemented` error whose `missing_feature` is
+	/// `LibraryFeature::DnnBlob` if libopus is older than 1.5 or was not built
+	/// to load weights at runtime.
+	pub fn set_dnn_blob(&mut self, blob: &DnnBlob) -> Result<()> {
+		let data = blob.as_bytes();
+		let request = OPUS_SET_DNN_BLOB_REQUEST;
+		feature_ctl!(
+			LibraryFeature::DnnBlob,
+			opus_encoder_ctl,
+			self,
+			request,
+			data.as_ptr(),
+			len(data)
+		);
+		self.dnn_blob = Some(blob.clone());
+		Ok(())
+	}
+}
+
+impl Decoder {
+	/// Load neural network weights, used by deep packet loss concealment and
+	/// OSCE. See `set_complexity` to enable them.
+	///
+	/// Returns an `Unimplemented` error whose `missing_feature` is
+	/// `LibraryFeature::DnnBlob` if libopus is older than 1.5 or was not built
+	/// to load weights at runtime.
+	pub fn set_dnn_blob(&mut self, blob: &DnnBlob) -> Result<()> {
+		let data = blob.as_bytes();
+		let request = OPUS_SET_DNN_BLOB_REQUEST;
+		feature_ctl!(
+			LibraryFeature::DnnBlob,
+			opus_decoder_ctl,
+			self,
+			request,
+			data.as_ptr(),
+			len(data)
+		);
+		self.dnn_blob = Some(blob.clone());
+		Ok(())
+	}
+}
+
+// ============================================================================
+// Deep Redundancy
+
+#[cfg(feature = "dred")]
+pub mod dred;
+
 // ============================================================================
 // Float Soft Clipping
 
@@ -1095,9 +2420,307 @@ impl<'rp, 'buf> RepacketizerState<'rp, 'buf> {
 	}
 }
 
+/// A packet produced by a `PacketSplitter` or `PacketMerger`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepacketizedPacket {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The number of samples in the packet at 48 kHz.
+	pub duration: usize,
+}
+
+/// An owned copy of a frame and the extensions attached to it.
+#[derive(Debug, Clone)]
+struct OwnedFrame {
+	toc: u8,
+	data: Vec<u8>,
+	extensions: Vec<(u8, Vec<u8>)>,
+}
+
+impl OwnedFrame {
+	/// Copy the frames out of a packet.
+	fn split(packet: &[u8]) -> Result<Vec<OwnedFrame>> {
+		let parsed = packet::parse_detailed(packet)?;
+		let mut frames: Vec<OwnedFrame> = parsed
+			.packet
+			.frames
+			.iter()
+			.map(|frame| OwnedFrame {
+				toc: parsed.packet.toc & 0xfc,
+				data: frame.to_vec(),
+				extensions: Vec::new(),
+			})
+			.collect();
+		for extension in parsed.extensions()? {
+			frames[extension.frame].extensions.push((extension.id, extension.data.to_vec()));
+		}
+		Ok(frames)
+	}
+
+	/// Build a packet from frames which share a configuration.
+	fn build(frames: &[OwnedFrame]) -> Result<RepacketizedPacket> {
+		let data: Vec<&[u8]> = frames.iter().map(|f| &f.data[..]).collect();
+		let mut extensions = Vec::new();
+		for (i, frame) in frames.iter().enumerate() {
+			for &(id, ref data) in &frame.extensions {
+				extensions.push(packet::Extension { id, frame: i, data });
+			}
+		}
+		let toc = packet::Toc(frames[0].toc);
+		Ok(RepacketizedPacket {
+			data: packet::build(toc, &data, &extensions)?,
+			duration: frames.len() * toc.frame_size(),
+		})
+	}
+
+	/// Get the bytes this frame takes in a code 3 packet with its length
+	/// given, and the most its extensions can take in the padding.
+	fn size(&self) -> (usize, usize) {
+		let length = if self.data.len() < 252 { 1 } else { 2 };
+		let padding = self
+			.extensions
+			.iter()
+			.map(|(_, data)| 2 + 1 + data.len() / 255 + 1 + data.len())
+			.sum();
+		(length + self.data.len(), padding)
+	}
+}
+
+/// Splits packets of several frames into packets of one frame each.
+///
+/// Unlike `Repacketizer`, the frames are copied, so the input packets need
+/// not outlive the splitter. Extensions are kept with their frames.
+#[derive(Debug, Default)]
+pub struct PacketSplitter {
+	output: VecDeque<RepacketizedPacket>,
+}
+
+impl PacketSplitter {
+	/// Create an empty splitter.
+	pub fn new() -> PacketSplitter {
+		PacketSplitter::default()
+	}
+
+	/// Split a packet, queueing a packet for each of its frames.
+	pub fn push(&mut self, packet: &[u8]) -> Result<()> {
+		for frame in OwnedFrame::split(packet)? {
+			let packet = OwnedFrame::build(std::slice::from_ref(&frame))?;
+			self.output.push_back(packet);
+		}
+		Ok(())
+	}
+
+	/// Take the next single-frame packet, if any.
+	pub fn next_packet(&mut self) -> Option<RepacketizedPacket> {
+		self.output.pop_front()
+	}
+}
+
+/// Merges consecutive packets into larger ones, up to a target duration or
+/// size.
+///
+/// Frames are copied, so the input packets need not outlive the merger.
+/// A packet is completed when it reaches the target duration, or when the
+/// next frame would exceed a limit or has a different configuration, so
+/// input packets may be split between outputs. Call
+/// [`flush`](#method.flush) to complete the last packet.
+#[derive(Debug)]
+pub struct PacketMerger {
+	max_duration: usize,
+	max_bytes: Option<usize>,
+	pending: Vec<OwnedFrame>,
+	// The frame and padding bytes of the pending frames, as from `size`.
+	pending_size: (usize, usize),
+	output: VecDeque<RepacketizedPacket>,
+}
+
+impl Default for PacketMerger {
+	fn default() -> PacketMerger {
+		PacketMerger::new()
+	}
+}
+
+impl PacketMerger {
+	/// Create a merger which makes packets as long as possible, 120 ms.
+	pub fn new() -> PacketMerger {
+		PacketMerger {
+			max_duration: 5760,
+			max_bytes: None,
+			pending: Vec::new(),
+			pending_size: (0, 0),
+			output: VecDeque::new(),
+		}
+	}
+
+	/// Limit the duration of each output packet, in samples at 48 kHz.
+	pub fn max_duration(mut self, samples: usize) -> PacketMerger {
+		self.max_duration = std::cmp::min(samples, 5760);
+		self
+	}
+
+	/// Limit the size of each output packet, in bytes.
+	///
+	/// The size of a packet is bounded as it grows rather than measured, so
+	/// packets may end a few bytes short of the limit. A single frame larger
+	/// than this is still output on its own.
+	pub fn max_bytes(mut self, bytes: usize) -> PacketMerger {
+		self.max_bytes = Some(bytes);
+		self
+	}
+
+	/// Add a packet's frames to those being merged.
+	pub fn push(&mut self, packet: &[u8]) -> Result<()> {
+		for frame in OwnedFrame::split(packet)? {
+			if !self.fits(&frame) {
+				self.flush()?;
+			}
+			let (frame_bytes, padding_bytes) = frame.size();
+			self.pending_size.0 += frame_bytes;
+			self.pending_size.1 += padding_bytes;
+			self.pending.push(frame);
+			let duration = self.pending.len() * packet::Toc(self.pending[0].toc).frame_size();
+			if duration >= self.max_duration {
+				self.flush()?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Complete a packet from the frames received so far, if there are any.
+	pub fn flush(&mut self) -> Result<()> {
+		if !self.pending.is_empty() {
+			let packet = OwnedFrame::build(&self.pending)?;
+			self.pending.clear();
+			self.pending_size = (0, 0);
+			self.output.push_back(packet);
+		}
+		Ok(())
+	}
+
+	/// Take the next completed packet, if any.
+	pub fn next_packet(&mut self) -> Option<RepacketizedPacket> {
+		self.output.pop_front()
+	}
+
+	/// Check whether a frame can join the pending packet.
+	///
+	/// The size is bounded by that of a code 3 packet giving the length of
+	/// each frame but the last, which is never smaller than the packet built.
+	fn fits(&self, frame: &OwnedFrame) -> bool {
+		let first = match self.pending.first() {
+			Some(first) => first,
+			None => return true,
+		};
+		let duration = (self.pending.len() + 1) * packet::Toc(first.toc).frame_size();
+		if first.toc != frame.toc || duration > self.max_duration {
+			return false;
+		}
+		if let Some(max_bytes) = self.max_bytes {
+			let (_, padding_bytes) = frame.size();
+			let frames = self.pending_size.0 + frame.data.len();
+			let padding = self.pending_size.1 + padding_bytes;
+			let padding_length = if padding == 0 { 0 } else { padding / 254 + 1 };
+			return 2 + frames + padding_length + padding <= max_bytes;
+		}
+		true
+	}
+}
+
 // ============================================================================
 // Multistream API
 
+/// Vorbis channel order layouts for one to eight channels:
+/// (streams, coupled streams, mapping).
+const VORBIS_LAYOUTS: [(u8, u8, &[u8]); 8] = [
+	(1, 0, &[0]),
+	(1, 1, &[0, 1]),
+	(2, 1, &[0, 2, 1]),
+	(2, 2, &[0, 1, 2, 3]),
+	(3, 2, &[0, 4, 1, 2, 3]),
+	(4, 2, &[0, 4, 1, 2, 3, 5]),
+	(4, 3, &[0, 4, 1, 2, 3, 5, 6]),
+	(5, 3, &[0, 6, 1, 2, 3, 4, 5, 7]),
+];
+
+/// How the channels of multistream audio are split across Opus streams.
+///
+/// Coupled streams come first and decode to two channels each, followed by
+/// mono streams. The mapping gives, for each channel, its index among the
+/// decoded channels, or 255 for a silent channel.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ChannelLayout {
+	streams: u8,
+	coupled_streams: u8,
+	mapping: Vec<u8>,
+}
+
+impl ChannelLayout {
+	/// Create a layout, checking the mapping against the stream counts.
+	pub fn new(streams: u8, coupled_streams: u8, mapping: &[u8]) -> Result<ChannelLayout> {
+		let decoded = streams as usize + coupled_streams as usize;
+		if streams == 0 || coupled_streams > streams || decoded > 255 {
+			return Err(Error::bad_arg("ChannelLayout::new"));
+		}
+		if mapping.is_empty() || mapping.len() > 255 {
+			return Err(Error::bad_arg("ChannelLayout::new"));
+		}
+		if mapping.iter().any(|&m| m != 255 && m as usize >= decoded) {
+			return Err(Error::bad_arg("ChannelLayout::new"));
+		}
+		Ok(ChannelLayout { streams, coupled_streams, mapping: mapping.to_vec() })
+	}
+
+	/// Get the layout for one to eight channels in Vorbis channel order, as
+	/// used by Ogg Opus mapping family 1.
+	///
+	/// In order, these are: mono; stereo; left, center, right; quadraphonic;
+	/// 5.0; 5.1; 6.1; and 7.1 surround.
+	pub fn vorbis(channels: u8) -> Result<ChannelLayout> {
+		match channels {
+			1..=8 => {
+				let (streams, coupled_streams, mapping) = VORBIS_LAYOUTS[channels as usize - 1];
+				Ok(ChannelLayout { streams, coupled_streams, mapping: mapping.to_vec() })
+			}
+			_ => Err(Error::bad_arg("ChannelLayout::vorbis")),
+		}
+	}
+
+	/// Get the number of channels.
+	pub fn channels(&self) -> u8 {
+		self.mapping.len() as u8
+	}
+
+	/// Get the total number of streams.
+	pub fn streams(&self) -> u8 {
+		self.streams
+	}
+
+	/// Get the number of those streams which are coupled stereo pairs.
+	pub fn coupled_streams(&self) -> u8 {
+		self.coupled_streams
+	}
+
+	/// Get the mapping table.
+	pub fn mapping(&self) -> &[u8] {
+		&self.mapping
+	}
+
+	/// Get which stream a channel comes from, and which channel of that
+	/// stream: 0 or 1 for coupled streams, and always 0 for mono streams.
+	///
+	/// Returns `None` if the channel is silent.
+	///
+	/// Panics if `channel` is out of range.
+	pub fn source(&self, channel: usize) -> Option<(u8, u8)> {
+		let coupled = self.coupled_streams as usize * 2;
+		match self.mapping[channel] as usize {
+			255 => None,
+			m if m < coupled => Some(((m / 2) as u8, (m % 2) as u8)),
+			m => Some(((m - coupled / 2) as u8, 0)),
+		}
+	}
+}
+
 /// Combine individual Opus streams in a single packet, up to 255 channels.
 ///
 /// See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__multistream.html).
@@ -1144,8 +2767,62 @@ impl MSEncoder {
 		}
 	}
 
-	// TODO: new_surround -> opus_multistream_encoder_create, but it's missing
-	// Doxygen comments.
+	/// Create and initialize a multistream encoder for a channel layout.
+	pub fn with_layout(
+		sample_rate: u32,
+		layout: &ChannelLayout,
+		application: Application,
+	) -> Result<MSEncoder> {
+		MSEncoder::new(
+			sample_rate,
+			layout.streams,
+			layout.coupled_streams,
+			&layout.mapping,
+			application,
+		)
+	}
+
+	/// Create and initialize a multistream encoder for a standard channel
+	/// layout, letting libopus choose the streams.
+	///
+	/// Mapping family 0 allows one or two channels, family 1 allows the
+	/// Vorbis surround layouts of up to eight channels, family 2 allows
+	/// ambisonics, and family 255 codes up to 255 discrete channels as
+	/// separate mono streams. The chosen layout is returned along with the
+	/// encoder, ready for an Ogg `OpusHead` or `MSDecoder::new`.
+	pub fn new_surround(
+		sample_rate: u32,
+		channels: u8,
+		mapping_family: u8,
+		application: Application,
+	) -> Result<(MSEncoder, SurroundLayout)> {
+		let mut error = 0;
+		let mut streams = 0;
+		let mut coupled_streams = 0;
+		let mut mapping = vec![0; channels as usize];
+		let ptr = unsafe {
+			ffi::opus_multistream_surround_encoder_create(
+				sample_rate as i32,
+				channels as c_int,
+				mapping_family as c_int,
+				&mut streams,
+				&mut coupled_streams,
+				mapping.as_mut_ptr(),
+				application as c_int,
+				&mut error,
+			)
+		};
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_multistream_surround_encoder_create", error))
+		} else {
+			let layout = SurroundLayout {
+				streams: streams as u8,
+				coupled_streams: coupled_streams as u8,
+				mapping,
+			};
+			Ok((MSEncoder { ptr, channels: channels as c_int }, layout))
+		}
+	}
 
 	/// Encode an Opus frame.
 	pub fn encode(&mut self, input: &[i16], output: &mut [u8]) -> Result<usize> {
@@ -1188,11 +2865,45 @@ impl MSEncoder {
 		output.truncate(result);
 		Ok(output)
 	}
+
+	/// Get one stream's encoder, so CTLs can be applied to it alone.
+	pub fn stream(&mut self, index: u8) -> Result<EncoderRef<'_>> {
+		let mut ptr: *mut ffi::OpusEncoder = std::ptr::null_mut();
+		ctl!(
+			opus_multistream_encoder_ctl,
+			self,
+			ffi::OPUS_MULTISTREAM_GET_ENCODER_STATE_REQUEST,
+			index as i32,
+			&mut ptr
+		);
+		Ok(EncoderRef { ptr, phantom: PhantomData })
+	}
 }
 
 generic_ctls!(MSEncoder, opus_multistream_encoder_ctl);
 encoder_ctls!(MSEncoder, opus_multistream_encoder_ctl);
 
+/// The streams chosen by `MSEncoder::new_surround`.
+///
+/// Convert it into a `ChannelLayout` to create a matching `MSDecoder` or Ogg
+/// `OpusHead`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SurroundLayout {
+	/// The total number of streams.
+	pub streams: u8,
+	/// The number of those streams which are coupled stereo pairs.
+	pub coupled_streams: u8,
+	/// The stream channel each input channel is coded in.
+	pub mapping: Vec<u8>,
+}
+
+impl From<SurroundLayout> for ChannelLayout {
+	fn from(layout: SurroundLayout) -> ChannelLayout {
+		let SurroundLayout { streams, coupled_streams, mapping } = layout;
+		ChannelLayout { streams, coupled_streams, mapping }
+	}
+}
+
 /// Decode packets into many Opus streams, up to 255.
 ///
 /// See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__multistream.html).
@@ -1237,6 +2948,11 @@ impl MSDecoder {
 		}
 	}
 
+	/// Create and initialize a multistream decoder for a channel layout.
+	pub fn with_layout(sample_rate: u32, layout: &ChannelLayout) -> Result<MSDecoder> {
+		MSDecoder::new(sample_rate, layout.streams, layout.coupled_streams, &layout.mapping)
+	}
+
 	/// Decode a multistream Opus packet.
 	///
 	/// To represent packet loss, pass an empty slice `&[]`.
@@ -1274,11 +2990,83 @@ impl MSDecoder {
 		);
 		Ok(len as usize)
 	}
+
+	/// Get one stream's decoder, so CTLs can be applied to it alone.
+	pub fn stream(&mut self, index: u8) -> Result<DecoderRef<'_>> {
+		let mut ptr: *mut ffi::OpusDecoder = std::ptr::null_mut();
+		ctl!(
+			opus_multistream_decoder_ctl,
+			self,
+			ffi::OPUS_MULTISTREAM_GET_DECODER_STATE_REQUEST,
+			index as i32,
+			&mut ptr
+		);
+		Ok(DecoderRef { ptr, phantom: PhantomData })
+	}
 }
 
 generic_ctls!(MSDecoder, opus_multistream_decoder_ctl);
 decoder_ctls!(MSDecoder, opus_multistream_decoder_ctl);
 
+/// The encoder of a single stream within an `MSEncoder`, returned from
+/// `MSEncoder::stream`.
+#[derive(Debug)]
+pub struct EncoderRef<'a> {
+	ptr: *mut ffi::OpusEncoder,
+	phantom: PhantomData<&'a mut MSEncoder>,
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl<'a> Send for EncoderRef<'a> {}
+
+generic_ctls!(impl<'a> EncoderRef<'a>, opus_encoder_ctl);
+encoder_ctls!(impl<'a> EncoderRef<'a>, opus_encoder_ctl);
+
+/// The decoder of a single stream within an `MSDecoder`, returned from
+/// `MSDecoder::stream`.
+#[derive(Debug)]
+pub struct DecoderRef<'a> {
+	ptr: *mut ffi::OpusDecoder,
+	phantom: PhantomData<&'a mut MSDecoder>,
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl<'a> Send for DecoderRef<'a> {}
+
+generic_ctls!(impl<'a> DecoderRef<'a>, opus_decoder_ctl);
+decoder_ctls!(impl<'a> DecoderRef<'a>, opus_decoder_ctl);
+
+// ============================================================================
+// Projection API
+
+pub mod projection;
+
+// ============================================================================
+// Containers
+
+#[cfg(any(feature = "ogg-opus", feature = "wav"))]
+mod container;
+#[cfg(feature = "ogg-opus")]
+pub mod ogg;
+#[cfg(feature = "webm")]
+pub mod webm;
+#[cfg(feature = "mp4")]
+pub mod mp4;
+#[cfg(feature = "wav")]
+pub mod wav;
+
+// ============================================================================
+// Transport
+
+pub mod jitter;
+pub mod rtp;
+
+// ============================================================================
+// Async Pipelines
+
+#[cfg(feature = "async")]
+pub mod async_io;
+
 // ============================================================================
 // Error Handling
 
@@ -1290,18 +3078,43 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct Error {
 	function: &'static str,
 	code: ErrorCode,
+	parse: Option<packet::ParseError>,
+	missing: Option<LibraryFeature>,
 }
 
 impl Error {
 	fn bad_arg(what: &'static str) -> Error {
-		Error { function: what, code: ErrorCode::BadArg }
+		Error {
+			function: what,
+			code: ErrorCode::BadArg,
+			parse: None,
+			missing: None,
+		}
+	}
+
+	fn invalid_packet(what: &'static str) -> Error {
+		Error {
+			function: what,
+			code: ErrorCode::InvalidPacket,
+			parse: None,
+			missing: None,
+		}
 	}
 
 	fn from_code(what: &'static str, code: c_int) -> Error {
 		Error {
 			function: what,
 			code: ErrorCode::from_int(code),
+			parse: None,
+			missing: None,
+		}
+	}
+
+	fn requiring(mut self, feature: LibraryFeature) -> Error {
+		if self.code == ErrorCode::Unimplemented {
+			self.missing = Some(feature);
 		}
+		self
 	}
 
 	/// Get the name of the Opus function from which the error originated.
@@ -1321,11 +3134,29 @@ impl Error {
 	pub fn code(&self) -> ErrorCode {
 		self.code
 	}
+
+	/// Get why a packet is malformed, if the error came from parsing one.
+	#[inline]
+	pub fn parse_error(&self) -> Option<packet::ParseError> {
+		self.parse
+	}
+
+	/// Get the libopus feature whose absence caused the error, if any.
+	#[inline]
+	pub fn missing_feature(&self) -> Option<LibraryFeature> {
+		self.missing
+	}
 }
 
 impl std::fmt::Display for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{}: {}", self.function, self.description())
+		match (self.parse, self.missing) {
+			(Some(ref e), _) => write!(f, "{}: {}", self.function, e),
+			(None, Some(feature)) => {
+				write!(f, "{}: libopus was built without {}", self.function, feature)
+			}
+			(None, None) => write!(f, "{}: {}", self.function, self.description()),
+		}
 	}
 }
 
@@ -1333,6 +3164,16 @@ impl std::error::Error for Error {
 	fn description(&self) -> &str {
 		self.code.description()
 	}
+
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.parse.as_ref().map(|e| e as &(dyn std::error::Error + 'static))
+	}
+}
+
+impl From<Error> for std::io::Error {
+	fn from(e: Error) -> std::io::Error {
+		std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+	}
 }
 
 fn check_len(val: usize) -> c_int {
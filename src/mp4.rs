@@ -0,0 +1,1134 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading and writing Opus in MP4 and other ISO base media files.
+//!
+//! An Opus track has an `Opus` sample entry whose `dOps` box holds the
+//! same fields as an Ogg Opus identification header, in big-endian order.
+//! The pre-skip and end trimming are given by the track's edit list.
+//! Both plain files, with the packets described by the sample tables, and
+//! fragmented files are supported.
+//!
+//! This module is only available with the `mp4` feature.
+//!
+//! See [Encapsulation of Opus in ISO Base Media File Format](https://opus-codec.org/docs/opus_in_isobmff.html).
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub use super::container::{Error, Result};
+
+use super::container::Trim;
+use super::ogg::{MappedDecoder, OpusHead};
+use super::packet;
+
+/// Opus always decodes at 48 kHz.
+const SAMPLE_RATE: u64 = 48000;
+
+/// The largest possible Opus packet duration: 120 ms at 48 kHz.
+const MAX_FRAME_SIZE: usize = 5760;
+
+/// The largest size of each stream in a packet: 48 frames of 2.5 ms, each
+/// of at most 1275 bytes with a two-byte length, after the TOC byte and
+/// frame count.
+const MAX_STREAM_BYTES: u64 = 2 + 48 * (2 + 1275);
+
+/// How much audio the writer puts in each fragment: one second.
+const FRAGMENT_DURATION: u64 = 48000;
+
+/// The identity transformation matrix of movie and track headers.
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+// Track fragment header flags.
+const TFHD_BASE_DATA_OFFSET: u32 = 0x00_0001;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x00_0002;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x00_0008;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x00_0010;
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x00_0020;
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+
+// Track run flags.
+const TRUN_DATA_OFFSET: u32 = 0x00_0001;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x00_0004;
+const TRUN_SAMPLE_DURATION: u32 = 0x00_0100;
+const TRUN_SAMPLE_SIZE: u32 = 0x00_0200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x00_0400;
+const TRUN_SAMPLE_COMPOSITION_TIME_OFFSET: u32 = 0x00_0800;
+
+// ============================================================================
+// Opus Specific Box
+
+impl OpusHead {
+	/// Parse the body of a `dOps` box.
+	///
+	/// The channel mapping table is read for any mapping family other than
+	/// zero. There is no demixing matrix in MP4, so family 3 is not
+	/// supported.
+	pub fn parse_dops(data: &[u8]) -> Result<OpusHead> {
+		let mut data = Bytes(data);
+		let version = data.u8()?;
+		if version != 0 {
+			return Err(Error::Malformed("unsupported dOps version"));
+		}
+		let channels = data.u8()?;
+		let pre_skip = data.u16()?;
+		let input_sample_rate = data.u32()?;
+		let output_gain = data.u16()? as i16;
+		let mapping_family = data.u8()?;
+		let (streams, coupled_streams, mapping) = match mapping_family {
+			0 => (1, channels.saturating_sub(1), (0..channels).collect()),
+			3 => return Err(Error::Unsupported("unsupported channel mapping family")),
+			_ => {
+				let streams = data.u8()?;
+				let coupled_streams = data.u8()?;
+				(streams, coupled_streams, data.take(channels as usize)?.to_vec())
+			}
+		};
+		if channels == 0 || (mapping_family == 0 && channels > 2) {
+			return Err(Error::Malformed("invalid channel count"));
+		}
+		if streams == 0 || coupled_streams > streams {
+			return Err(Error::Malformed("dOps has invalid stream counts"));
+		}
+		let decoded_channels = streams as usize + coupled_streams as usize;
+		if decoded_channels > 255 {
+			return Err(Error::Malformed("dOps has invalid stream counts"));
+		}
+		if mapping.iter().any(|&m| m != 255 && m as usize >= decoded_channels) {
+			return Err(Error::Malformed("dOps channel mapping is out of range"));
+		}
+		Ok(OpusHead {
+			version,
+			channels,
+			pre_skip,
+			input_sample_rate,
+			output_gain,
+			mapping_family,
+			streams,
+			coupled_streams,
+			mapping,
+			demixing_matrix: Vec::new(),
+		})
+	}
+
+	/// Serialize as the body of a `dOps` box.
+	pub fn to_dops(&self) -> Vec<u8> {
+		let mut out = vec![0, self.channels];
+		out.extend_from_slice(&self.pre_skip.to_be_bytes());
+		out.extend_from_slice(&self.input_sample_rate.to_be_bytes());
+		out.extend_from_slice(&self.output_gain.to_be_bytes());
+		out.push(self.mapping_family);
+		if self.mapping_family != 0 {
+			out.push(self.streams);
+			out.push(self.coupled_streams);
+			out.extend_from_slice(&self.mapping);
+		}
+		out
+	}
+}
+
+// ============================================================================
+// Box Reading
+
+/// A big-endian reader over the body of a box.
+#[derive(Debug)]
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+		if self.0.len() < len {
+			return Err(Error::Malformed("truncated box"));
+		}
+		let (head, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(head)
+	}
+
+	fn u8(&mut self) -> Result<u8> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16> {
+		let data = self.take(2)?;
+		Ok(u16::from_be_bytes([data[0], data[1]]))
+	}
+
+	fn u32(&mut self) -> Result<u32> {
+		let data = self.take(4)?;
+		Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+	}
+
+	fn u64(&mut self) -> Result<u64> {
+		Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+	}
+
+	/// Check an entry count against the rest of the box, which must hold
+	/// `entry_len` bytes for each entry.
+	fn entries(&self, count: u32, entry_len: usize) -> Result<usize> {
+		if count as usize > self.0.len() / entry_len {
+			return Err(Error::Malformed("entry count overruns its box"));
+		}
+		Ok(count as usize)
+	}
+
+	/// Read an entry count, checking it as `entries` does.
+	fn count(&mut self, entry_len: usize) -> Result<usize> {
+		let count = self.u32()?;
+		self.entries(count, entry_len)
+	}
+
+	/// Read the version and flags of a full box.
+	fn full_box(&mut self) -> Result<(u8, u32)> {
+		let value = self.u32()?;
+		Ok(((value >> 24) as u8, value & 0x00FF_FFFF))
+	}
+}
+
+/// Iterate over the boxes in the body of a container box.
+fn children(mut body: &[u8]) -> impl Iterator<Item = Result<([u8; 4], &[u8])>> {
+	std::iter::from_fn(move || {
+		if body.is_empty() {
+			return None;
+		}
+		let result = split_box(body);
+		match result {
+			Ok((_, _, rest)) => body = rest,
+			Err(_) => body = &[],
+		}
+		Some(result.map(|(kind, child, _)| (kind, child)))
+	})
+}
+
+/// Split the first box off `data`, returning its type, its body and what
+/// follows it.
+fn split_box(data: &[u8]) -> Result<([u8; 4], &[u8], &[u8])> {
+	let mut bytes = Bytes(data);
+	let size = bytes.u32()? as u64;
+	let mut kind = [0; 4];
+	kind.copy_from_slice(bytes.take(4)?);
+	let size = match size {
+		0 => data.len() as u64,
+		1 => bytes.u64()?,
+		size => size,
+	};
+	let header = data.len() - bytes.0.len();
+	if size < header as u64 || size > data.len() as u64 {
+		return Err(Error::Malformed("child box overruns its parent"));
+	}
+	Ok((kind, &data[header..size as usize], &data[size as usize..]))
+}
+
+/// Find the first child box of the given type.
+fn find<'a>(body: &'a [u8], kind: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+	for child in children(body) {
+		let (child_kind, data) = child?;
+		if &child_kind == kind {
+			return Ok(Some(data));
+		}
+	}
+	Ok(None)
+}
+
+/// Follow a path of nested boxes.
+fn find_path<'a>(mut body: &'a [u8], path: &[&[u8; 4]]) -> Result<Option<&'a [u8]>> {
+	for kind in path {
+		body = match find(body, kind)? {
+			Some(child) => child,
+			None => return Ok(None),
+		};
+	}
+	Ok(Some(body))
+}
+
+/// Read exactly `buf.len()` bytes, returning `false` at end of stream.
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+	let mut pos = 0;
+	while pos < buf.len() {
+		match reader.read(&mut buf[pos..]) {
+			Ok(0) => return Ok(false),
+			Ok(n) => pos += n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(true)
+}
+
+// ============================================================================
+// Reader
+
+/// A packet read from an Opus track.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mp4Packet {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The packet's decode time, in samples at 48 kHz.
+	pub timestamp: u64,
+	/// The packet's duration, in samples at 48 kHz.
+	pub duration: u64,
+}
+
+/// Where a packet is in the file, in the media's timescale.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+	offset: u64,
+	size: u32,
+	timestamp: u64,
+	duration: u32,
+}
+
+/// The fields of an Opus track.
+#[derive(Debug)]
+struct Track {
+	id: u32,
+	head: OpusHead,
+	timescale: u32,
+	/// The edit list's start in the media's timescale and its duration at
+	/// 48 kHz, if it has one.
+	edit: Option<(u64, u64)>,
+	samples: Vec<Sample>,
+}
+
+/// Reads the first Opus track of an MP4 file.
+///
+/// The file's boxes are scanned when the reader is created, and packets
+/// are then read from wherever the sample tables or fragments place them,
+/// so the reader must be seekable.
+///
+/// Packets can be read as they are with
+/// [`next_packet`](#method.next_packet), or decoded to PCM at 48 kHz with
+/// the edit list applied. Without an edit list, the `dOps` pre-skip is
+/// discarded instead.
+#[derive(Debug)]
+pub struct Mp4OpusReader<R> {
+	inner: R,
+	file_len: u64,
+	head: OpusHead,
+	timescale: u32,
+	samples: Vec<Sample>,
+	next: usize,
+	decoder: MappedDecoder,
+	trim: Trim,
+	duration: Option<u64>,
+}
+
+impl<R: Read + Seek> Mp4OpusReader<R> {
+	/// Scan the file from its start, and prepare a decoder for its first
+	/// Opus track.
+	pub fn new(mut reader: R) -> Result<Mp4OpusReader<R>> {
+		let file_len = reader.seek(SeekFrom::End(0))?;
+		let mut pos = reader.seek(SeekFrom::Start(0))?;
+		let mut moov = None;
+		let mut moofs = Vec::new();
+		loop {
+			let mut header = [0; 8];
+			if !fill(&mut reader, &mut header)? {
+				break;
+			}
+			let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+			let mut header_len = 8;
+			if size == 1 {
+				let mut large = [0; 8];
+				if !fill(&mut reader, &mut large)? {
+					break;
+				}
+				size = u64::from_be_bytes(large);
+				header_len = 16;
+			}
+			// A size of zero means the box extends to the end of the file.
+			let body_len = match size {
+				0 => None,
+				size if size < header_len => return Err(Error::Malformed("invalid box size")),
+				size => Some(size - header_len),
+			};
+			match &header[4..] {
+				b"moov" | b"moof" => {
+					let mut body = Vec::new();
+					let limit = body_len.unwrap_or(u64::MAX);
+					(&mut reader).take(limit).read_to_end(&mut body)?;
+					if body_len.map_or(false, |len| (body.len() as u64) < len) {
+						// The file was cut off partway through the box.
+						break;
+					}
+					if &header[4..] == b"moov" {
+						moov = Some(body);
+					} else {
+						moofs.push((pos, body));
+					}
+				}
+				_ => match body_len {
+					Some(len) => {
+						reader.seek(SeekFrom::Current(len as i64))?;
+					}
+					None => break,
+				},
+			}
+			match body_len {
+				Some(len) => pos += header_len + len,
+				None => break,
+			}
+		}
+
+		let moov = moov.ok_or(Error::Malformed("no moov box found"))?;
+		let mut track = read_moov(&moov, file_len)?;
+		if !moofs.is_empty() {
+			let defaults = read_trex(&moov, track.id)?;
+			for (offset, moof) in &moofs {
+				read_moof(moof, *offset, track.id, defaults, file_len, &mut track.samples)?;
+			}
+		}
+
+		let decoder = MappedDecoder::new(&track.head)?;
+		let rate = track.timescale as u64;
+		let trim = match track.edit {
+			Some((start, duration)) => Trim {
+				skip: to_samples(start, rate)?,
+				// A zero duration means up to the end of the media.
+				remaining: if duration == 0 { None } else { Some(duration) },
+			},
+			None => Trim {
+				skip: track.head.pre_skip as u64,
+				remaining: None,
+			},
+		};
+		Ok(Mp4OpusReader {
+			inner: reader,
+			file_len,
+			head: track.head,
+			timescale: track.timescale,
+			samples: track.samples,
+			next: 0,
+			decoder,
+			duration: trim.remaining,
+			trim,
+		})
+	}
+
+	/// Get the track's identification header, from its `dOps` box.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the number of interleaved channels in the decoded output.
+	pub fn channels(&self) -> usize {
+		self.head.channels as usize
+	}
+
+	/// Get the number of packets in the track.
+	pub fn packets(&self) -> usize {
+		self.samples.len()
+	}
+
+	/// Get the length of the decoded output in samples per channel, if the
+	/// edit list gives it.
+	pub fn duration(&self) -> Option<u64> {
+		self.duration
+	}
+
+	/// Read the next packet of the track.
+	///
+	/// Returns `None` at the end of the track.
+	pub fn next_packet(&mut self) -> Result<Option<Mp4Packet>> {
+		let sample = match self.samples.get(self.next) {
+			Some(&sample) => sample,
+			None => return Ok(None),
+		};
+		self.next += 1;
+		if sample.size as u64 > self.head.streams as u64 * MAX_STREAM_BYTES {
+			return Err(Error::Malformed("packet is larger than any valid Opus packet"));
+		}
+		if sample.offset.saturating_add(sample.size as u64) > self.file_len {
+			return Err(Error::Malformed("packet lies past the end of the file"));
+		}
+		self.inner.seek(SeekFrom::Start(sample.offset))?;
+		let mut data = vec![0; sample.size as usize];
+		if !fill(&mut self.inner, &mut data)? {
+			return Err(Error::Malformed("packet lies past the end of the file"));
+		}
+		let rate = self.timescale as u64;
+		Ok(Some(Mp4Packet {
+			data,
+			timestamp: to_samples(sample.timestamp, rate)?,
+			duration: to_samples(sample.duration as u64, rate)?,
+		}))
+	}
+
+	/// Decode the next chunk of audio.
+	///
+	/// Returns `None` at the end of the track.
+	pub fn decode(&mut self) -> Result<Option<Vec<i16>>> {
+		let mut output = vec![0; MAX_FRAME_SIZE * self.channels()];
+		while self.trim.remaining != Some(0) {
+			let packet = match self.next_packet()? {
+				Some(packet) => packet,
+				None => break,
+			};
+			let samples = self.decoder.decode(&packet.data, &mut output)?;
+			let channels = self.channels();
+			if self.trim.apply(&mut output, samples, samples, channels) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Decode the next chunk of audio with floating point output.
+	///
+	/// Returns `None` at the end of the track.
+	pub fn decode_float(&mut self) -> Result<Option<Vec<f32>>> {
+		let mut output = vec![0.0; MAX_FRAME_SIZE * self.channels()];
+		while self.trim.remaining != Some(0) {
+			let packet = match self.next_packet()? {
+				Some(packet) => packet,
+				None => break,
+			};
+			let samples = self.decoder.decode_float(&packet.data, &mut output)?;
+			let channels = self.channels();
+			if self.trim.apply(&mut output, samples, samples, channels) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Convert a time in units of `rate` per second to samples at 48 kHz.
+fn to_samples(time: u64, rate: u64) -> Result<u64> {
+	match time.checked_mul(SAMPLE_RATE) {
+		Some(scaled) => Ok(scaled / rate),
+		None => Err(Error::Malformed("time is out of range")),
+	}
+}
+
+/// Add a duration to a time, as when laying out samples.
+fn advance(time: u64, duration: u64) -> Result<u64> {
+	time.checked_add(duration).ok_or(Error::Malformed("time is out of range"))
+}
+
+/// Read the first Opus track from the movie box, with the samples given by
+/// its sample tables.
+fn read_moov(moov: &[u8], file_len: u64) -> Result<Track> {
+	let mut movie_timescale = 1;
+	if let Some(mvhd) = find(moov, b"mvhd")? {
+		let mut mvhd = Bytes(mvhd);
+		let (version, _) = mvhd.full_box()?;
+		mvhd.take(if version == 1 { 16 } else { 8 })?;
+		movie_timescale = mvhd.u32()?;
+	}
+
+	for child in children(moov) {
+		let (kind, trak) = child?;
+		if &kind != b"trak" {
+			continue;
+		}
+		let stbl = match find_path(trak, &[b"mdia", b"minf", b"stbl"])? {
+			Some(stbl) => stbl,
+			None => continue,
+		};
+		let head = match read_stsd(stbl)? {
+			Some(head) => head,
+			None => continue,
+		};
+
+		let mut tkhd =
+			Bytes(find(trak, b"tkhd")?.ok_or(Error::Malformed("track has no tkhd box"))?);
+		let (version, _) = tkhd.full_box()?;
+		tkhd.take(if version == 1 { 16 } else { 8 })?;
+		let id = tkhd.u32()?;
+
+		let mdhd = find_path(trak, &[b"mdia", b"mdhd"])?;
+		let mut mdhd = Bytes(mdhd.ok_or(Error::Malformed("track has no mdhd box"))?);
+		let (version, _) = mdhd.full_box()?;
+		mdhd.take(if version == 1 { 16 } else { 8 })?;
+		let timescale = mdhd.u32()?;
+		if timescale == 0 || movie_timescale == 0 {
+			return Err(Error::Malformed("invalid timescale"));
+		}
+
+		let mut edit = None;
+		if let Some(elst) = find_path(trak, &[b"edts", b"elst"])? {
+			let mut elst = Bytes(elst);
+			let (version, _) = elst.full_box()?;
+			for _ in 0..elst.count(if version == 1 { 20 } else { 12 })? {
+				let (duration, start) = if version == 1 {
+					(elst.u64()?, elst.u64()? as i64)
+				} else {
+					(elst.u32()? as u64, elst.u32()? as i32 as i64)
+				};
+				elst.u32()?;
+				// Empty edits, which delay the track, are ignored.
+				if start >= 0 {
+					let duration = to_samples(duration, movie_timescale as u64)?;
+					edit = Some((start as u64, duration));
+					break;
+				}
+			}
+		}
+
+		return Ok(Track {
+			id,
+			head,
+			timescale,
+			edit,
+			samples: read_stbl(stbl, file_len)?,
+		});
+	}
+	Err(Error::Malformed("no Opus track found"))
+}
+
+/// Read the `dOps` box of a sample table's `Opus` sample entry, returning
+/// `None` if it has none.
+fn read_stsd(stbl: &[u8]) -> Result<Option<OpusHead>> {
+	let mut stsd = Bytes(find(stbl, b"stsd")?.ok_or(Error::Malformed("track has no stsd box"))?);
+	stsd.full_box()?;
+	stsd.u32()?;
+	for entry in children(stsd.0) {
+		let (kind, data) = entry?;
+		if &kind != b"Opus" {
+			continue;
+		}
+		// Skip the fields of the audio sample entry.
+		let mut data = Bytes(data);
+		data.take(28)?;
+		let dops = find(data.0, b"dOps")?.ok_or(Error::Malformed("Opus entry has no dOps box"))?;
+		return Ok(Some(OpusHead::parse_dops(dops)?));
+	}
+	Ok(None)
+}
+
+/// Read the samples described by a sample table, in a file of `file_len`
+/// bytes.
+fn read_stbl(stbl: &[u8], file_len: u64) -> Result<Vec<Sample>> {
+	let mut sizes = Vec::new();
+	if let Some(stsz) = find(stbl, b"stsz")? {
+		let mut stsz = Bytes(stsz);
+		stsz.full_box()?;
+		let size = stsz.u32()?;
+		if size == 0 {
+			for _ in 0..stsz.count(4)? {
+				sizes.push(stsz.u32()?);
+			}
+		} else {
+			let count = stsz.u32()?;
+			if count as u64 * size as u64 > file_len {
+				return Err(Error::Malformed("stsz describes more data than the file holds"));
+			}
+			sizes = vec![size; count as usize];
+		}
+	}
+
+	let mut chunks = Vec::new();
+	if let Some(stco) = find(stbl, b"stco")? {
+		let mut stco = Bytes(stco);
+		stco.full_box()?;
+		for _ in 0..stco.count(4)? {
+			chunks.push(stco.u32()? as u64);
+		}
+	} else if let Some(co64) = find(stbl, b"co64")? {
+		let mut co64 = Bytes(co64);
+		co64.full_box()?;
+		for _ in 0..co64.count(8)? {
+			chunks.push(co64.u64()?);
+		}
+	}
+
+	// Runs of chunks with the same number of samples, by first chunk.
+	let mut runs = Vec::new();
+	if let Some(stsc) = find(stbl, b"stsc")? {
+		let mut stsc = Bytes(stsc);
+		stsc.full_box()?;
+		for _ in 0..stsc.count(12)? {
+			let first = stsc.u32()? as usize;
+			let samples = stsc.u32()?;
+			stsc.u32()?;
+			runs.push((first, samples));
+		}
+	}
+
+	let mut durations = Vec::new();
+	if let Some(stts) = find(stbl, b"stts")? {
+		let mut stts = Bytes(stts);
+		stts.full_box()?;
+		for _ in 0..stts.count(8)? {
+			let count = stts.u32()?;
+			let duration = stts.u32()?;
+			if durations.len() + count as usize > sizes.len() {
+				return Err(Error::Malformed("stts describes more samples than stsz"));
+			}
+			durations.extend(std::iter::repeat(duration).take(count as usize));
+		}
+	}
+	if durations.len() != sizes.len() {
+		return Err(Error::Malformed("stts and stsz disagree"));
+	}
+
+	let mut samples = Vec::with_capacity(sizes.len());
+	let mut timestamp = 0;
+	for (i, &chunk) in chunks.iter().enumerate() {
+		let per_chunk = match runs.iter().rev().find(|run| run.0 <= i + 1) {
+			Some(run) => run.1,
+			None => return Err(Error::Malformed("stsc does not cover every chunk")),
+		};
+		let mut offset = chunk;
+		for _ in 0..per_chunk {
+			let n = samples.len();
+			if n == sizes.len() {
+				return Err(Error::Malformed("stsc describes more samples than stsz"));
+			}
+			samples.push(Sample {
+				offset,
+				size: sizes[n],
+				timestamp,
+				duration: durations[n],
+			});
+			offset = advance(offset, sizes[n] as u64)?;
+			timestamp = advance(timestamp, durations[n] as u64)?;
+		}
+	}
+	if samples.len() != sizes.len() {
+		return Err(Error::Malformed("stsc describes fewer samples than stsz"));
+	}
+	Ok(samples)
+}
+
+/// Read the default sample duration and size for a track's fragments.
+fn read_trex(moov: &[u8], track: u32) -> Result<(u32, u32)> {
+	if let Some(mvex) = find(moov, b"mvex")? {
+		for child in children(mvex) {
+			let (kind, trex) = child?;
+			let mut trex = Bytes(trex);
+			if &kind == b"trex" {
+				trex.full_box()?;
+				if trex.u32()? == track {
+					trex.u32()?;
+					return Ok((trex.u32()?, trex.u32()?));
+				}
+			}
+		}
+	}
+	Ok((0, 0))
+}
+
+/// Append the track's samples from a movie fragment which starts at
+/// `offset` in a file of `file_len` bytes.
+fn read_moof(
+	moof: &[u8],
+	offset: u64,
+	track: u32,
+	defaults: (u32, u32),
+	file_len: u64,
+	samples: &mut Vec<Sample>,
+) -> Result<()> {
+	for child in children(moof) {
+		let (kind, traf) = child?;
+		if &kind != b"traf" {
+			continue;
+		}
+		let mut tfhd = Bytes(find(traf, b"tfhd")?.ok_or(Error::Malformed("traf has no tfhd box"))?);
+		let (_, flags) = tfhd.full_box()?;
+		if tfhd.u32()? != track {
+			continue;
+		}
+		let mut base = offset;
+		let (mut default_duration, mut default_size) = defaults;
+		if flags & TFHD_BASE_DATA_OFFSET != 0 {
+			base = tfhd.u64()?;
+		}
+		if flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+			tfhd.u32()?;
+		}
+		if flags & TFHD_DEFAULT_SAMPLE_DURATION != 0 {
+			default_duration = tfhd.u32()?;
+		}
+		if flags & TFHD_DEFAULT_SAMPLE_SIZE != 0 {
+			default_size = tfhd.u32()?;
+		}
+		if flags & TFHD_DEFAULT_SAMPLE_FLAGS != 0 {
+			tfhd.u32()?;
+		}
+		if flags & (TFHD_BASE_DATA_OFFSET | TFHD_DEFAULT_BASE_IS_MOOF) == 0 {
+			// Strictly this is the end of the previous fragment's data, but
+			// files without either flag rarely have more than one.
+			base = offset;
+		}
+
+		let mut timestamp = match samples.last() {
+			Some(last) => advance(last.timestamp, last.duration as u64)?,
+			None => 0,
+		};
+		if let Some(tfdt) = find(traf, b"tfdt")? {
+			let mut tfdt = Bytes(tfdt);
+			let (version, _) = tfdt.full_box()?;
+			timestamp = if version == 1 { tfdt.u64()? } else { tfdt.u32()? as u64 };
+		}
+
+		let mut data_offset = base;
+		for run in children(traf) {
+			let (kind, trun) = run?;
+			if &kind != b"trun" {
+				continue;
+			}
+			let mut trun = Bytes(trun);
+			let (_, flags) = trun.full_box()?;
+			let count = trun.u32()?;
+			if flags & TRUN_DATA_OFFSET != 0 {
+				let delta = trun.u32()? as i32;
+				let offset = if delta >= 0 {
+					base.checked_add(delta as u64)
+				} else {
+					base.checked_sub(delta.unsigned_abs() as u64)
+				};
+				data_offset = offset.ok_or(Error::Malformed("trun data offset is out of range"))?;
+			}
+			if flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+				trun.u32()?;
+			}
+			let fields = [
+				TRUN_SAMPLE_DURATION,
+				TRUN_SAMPLE_SIZE,
+				TRUN_SAMPLE_FLAGS,
+				TRUN_SAMPLE_COMPOSITION_TIME_OFFSET,
+			];
+			let entry_len = 4 * fields.iter().filter(|&&field| flags & field != 0).count();
+			if entry_len > 0 {
+				trun.entries(count, entry_len)?;
+			}
+			// Every sample holds at least one byte.
+			if samples.len() as u64 + count as u64 > file_len {
+				return Err(Error::Malformed("trun describes more samples than the file holds"));
+			}
+			for _ in 0..count {
+				let mut duration = default_duration;
+				let mut size = default_size;
+				if flags & TRUN_SAMPLE_DURATION != 0 {
+					duration = trun.u32()?;
+				}
+				if flags & TRUN_SAMPLE_SIZE != 0 {
+					size = trun.u32()?;
+				}
+				if flags & TRUN_SAMPLE_FLAGS != 0 {
+					trun.u32()?;
+				}
+				if flags & TRUN_SAMPLE_COMPOSITION_TIME_OFFSET != 0 {
+					trun.u32()?;
+				}
+				samples.push(Sample {
+					offset: data_offset,
+					size,
+					timestamp,
+					duration,
+				});
+				data_offset = advance(data_offset, size as u64)?;
+				timestamp = advance(timestamp, duration as u64)?;
+			}
+		}
+	}
+	Ok(())
+}
+
+// ============================================================================
+// Box Writing
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+	out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+	out.extend_from_slice(kind);
+	out.extend_from_slice(body);
+}
+
+/// Start the body of a full box.
+fn full_box(version: u8, flags: u32) -> Vec<u8> {
+	(flags | (version as u32) << 24).to_be_bytes().to_vec()
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+	out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+	out.extend_from_slice(&value.to_be_bytes());
+}
+
+// ============================================================================
+// Writer
+
+/// Writes Opus packets into a fragmented MP4 file with a single track.
+///
+/// The movie box is written up front and each second of packets follows in
+/// its own fragment, so the output need not be seekable.
+/// [`finish`](#method.finish) must be called to write the last fragment.
+///
+/// The edit list skips the header's pre-skip. Its duration is not known
+/// when it is written, so it lasts to the end of the track unless a
+/// seekable writer is completed with
+/// [`finish_trimmed`](#method.finish_trimmed), which fills it in so that
+/// the padding of the last packet is trimmed.
+#[derive(Debug)]
+pub struct Mp4OpusWriter<W> {
+	inner: W,
+	head: OpusHead,
+	headers_written: bool,
+	/// The number of bytes written so far.
+	written: u64,
+	/// The offset of the edit's duration from the start of the output.
+	edit_offset: u64,
+	sequence: u32,
+	/// The decode time of the pending fragment.
+	timestamp: u64,
+	/// The pending fragment's packets, with their durations.
+	pending: Vec<(Vec<u8>, u32)>,
+	pending_duration: u64,
+}
+
+impl<W: Write> Mp4OpusWriter<W> {
+	/// Create a writer for packets described by the given header.
+	///
+	/// Packets from an `MSEncoder` need a header with the same streams and
+	/// mapping.
+	pub fn new(writer: W, head: OpusHead) -> Result<Mp4OpusWriter<W>> {
+		if head.mapping_family == 3 || head.mapping.len() != head.channels as usize {
+			return Err(Error::Opus(super::Error::bad_arg("Mp4OpusWriter::new")));
+		}
+		Ok(Mp4OpusWriter {
+			inner: writer,
+			head,
+			headers_written: false,
+			written: 0,
+			edit_offset: 0,
+			sequence: 0,
+			timestamp: 0,
+			pending: Vec::new(),
+			pending_duration: 0,
+		})
+	}
+
+	/// Get the header the writer was created with.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Write an encoded packet.
+	pub fn write_packet(&mut self, packet: &[u8]) -> Result<()> {
+		let duration = packet::get_nb_samples(packet, SAMPLE_RATE as u32)?;
+		self.pending.push((packet.to_vec(), duration as u32));
+		self.pending_duration += duration as u64;
+		if self.pending_duration >= FRAGMENT_DURATION {
+			self.flush_fragment()?;
+		}
+		Ok(())
+	}
+
+	/// Write the remaining packets and complete the file.
+	pub fn finish(mut self) -> Result<W> {
+		self.flush_fragment()?;
+		self.write_headers()?;
+		Ok(self.inner)
+	}
+
+	fn write_headers(&mut self) -> Result<()> {
+		if self.headers_written {
+			return Ok(());
+		}
+		let mut out = Vec::new();
+		let mut ftyp = b"iso6".to_vec();
+		push_u32(&mut ftyp, 0);
+		ftyp.extend_from_slice(b"iso6mp41");
+		write_box(&mut out, b"ftyp", &ftyp);
+
+		let mut mvhd = full_box(0, 0);
+		// Creation time, modification time, timescale and duration.
+		for &value in &[0, 0, SAMPLE_RATE as u32, 0] {
+			push_u32(&mut mvhd, value);
+		}
+		push_u32(&mut mvhd, 0x0001_0000);
+		push_u16(&mut mvhd, 0x0100);
+		mvhd.extend_from_slice(&[0; 10]);
+		for &value in &MATRIX {
+			push_u32(&mut mvhd, value);
+		}
+		mvhd.extend_from_slice(&[0; 24]);
+		push_u32(&mut mvhd, 2);
+
+		// Enabled and in the movie.
+		let mut tkhd = full_box(0, 3);
+		// Creation time, modification time, track ID, reserved and
+		// duration.
+		for &value in &[0, 0, 1, 0, 0] {
+			push_u32(&mut tkhd, value);
+		}
+		tkhd.extend_from_slice(&[0; 12]);
+		push_u16(&mut tkhd, 0x0100);
+		push_u16(&mut tkhd, 0);
+		for &value in &MATRIX {
+			push_u32(&mut tkhd, value);
+		}
+		tkhd.extend_from_slice(&[0; 8]);
+
+		let mut elst = full_box(1, 0);
+		push_u32(&mut elst, 1);
+		// An edit of zero duration lasts until the end of the media.
+		elst.extend_from_slice(&0u64.to_be_bytes());
+		elst.extend_from_slice(&(self.head.pre_skip as u64).to_be_bytes());
+		push_u32(&mut elst, 0x0001_0000);
+		let mut edts = Vec::new();
+		write_box(&mut edts, b"elst", &elst);
+
+		let mut mdhd = full_box(0, 0);
+		for &value in &[0, 0, SAMPLE_RATE as u32, 0] {
+			push_u32(&mut mdhd, value);
+		}
+		// The language code for "und".
+		push_u16(&mut mdhd, 0x55C4);
+		push_u16(&mut mdhd, 0);
+
+		let mut hdlr = full_box(0, 0);
+		push_u32(&mut hdlr, 0);
+		hdlr.extend_from_slice(b"soun");
+		hdlr.extend_from_slice(&[0; 12]);
+		hdlr.extend_from_slice(b"SoundHandler\0");
+
+		let mut entry = vec![0; 6];
+		push_u16(&mut entry, 1);
+		entry.extend_from_slice(&[0; 8]);
+		push_u16(&mut entry, self.head.channels as u16);
+		push_u16(&mut entry, 16);
+		push_u32(&mut entry, 0);
+		push_u32(&mut entry, (SAMPLE_RATE as u32) << 16);
+		write_box(&mut entry, b"dOps", &self.head.to_dops());
+		let mut stsd = full_box(0, 0);
+		push_u32(&mut stsd, 1);
+		write_box(&mut stsd, b"Opus", &entry);
+
+		// The sample tables are empty, as every packet is in a fragment.
+		let mut empty = full_box(0, 0);
+		push_u32(&mut empty, 0);
+		let mut stsz = full_box(0, 0);
+		push_u32(&mut stsz, 0);
+		push_u32(&mut stsz, 0);
+		let mut stbl = Vec::new();
+		write_box(&mut stbl, b"stsd", &stsd);
+		write_box(&mut stbl, b"stts", &empty);
+		write_box(&mut stbl, b"stsc", &empty);
+		write_box(&mut stbl, b"stsz", &stsz);
+		write_box(&mut stbl, b"stco", &empty);
+
+		// The media data is in this file.
+		let mut dref = full_box(0, 0);
+		push_u32(&mut dref, 1);
+		write_box(&mut dref, b"url ", &full_box(0, 1));
+		let mut dinf = Vec::new();
+		write_box(&mut dinf, b"dref", &dref);
+
+		let mut smhd = full_box(0, 0);
+		push_u32(&mut smhd, 0);
+		let mut minf = Vec::new();
+		write_box(&mut minf, b"smhd", &smhd);
+		write_box(&mut minf, b"dinf", &dinf);
+		write_box(&mut minf, b"stbl", &stbl);
+
+		let mut mdia = Vec::new();
+		write_box(&mut mdia, b"mdhd", &mdhd);
+		write_box(&mut mdia, b"hdlr", &hdlr);
+		write_box(&mut mdia, b"minf", &minf);
+
+		let mut trak = Vec::new();
+		write_box(&mut trak, b"tkhd", &tkhd);
+		write_box(&mut trak, b"edts", &edts);
+		write_box(&mut trak, b"mdia", &mdia);
+
+		let mut trex = full_box(0, 0);
+		// Track ID, sample description index, and default duration, size
+		// and flags.
+		for &value in &[1, 1, 0, 0, 0] {
+			push_u32(&mut trex, value);
+		}
+		let mut mvex = Vec::new();
+		write_box(&mut mvex, b"trex", &trex);
+
+		let mut moov = Vec::new();
+		write_box(&mut moov, b"mvhd", &mvhd);
+		write_box(&mut moov, b"trak", &trak);
+		write_box(&mut moov, b"mvex", &mvex);
+		// The edit follows the headers of the movie, track, edit and edit
+		// list boxes and the edit list's entry count.
+		let edit_offset = out.len() + 8 + (8 + mvhd.len()) + 8 + (8 + tkhd.len()) + 8 + 8 + 8;
+		write_box(&mut out, b"moov", &moov);
+		debug_assert_eq!(&out[edit_offset - 12..edit_offset - 8], b"elst");
+
+		self.inner.write_all(&out)?;
+		self.written += out.len() as u64;
+		self.edit_offset = edit_offset as u64;
+		self.headers_written = true;
+		Ok(())
+	}
+
+	fn flush_fragment(&mut self) -> Result<()> {
+		if self.pending.is_empty() {
+			return Ok(());
+		}
+		self.write_headers()?;
+		self.sequence += 1;
+
+		let mut mfhd = full_box(0, 0);
+		push_u32(&mut mfhd, self.sequence);
+		let mut tfhd = full_box(0, TFHD_DEFAULT_BASE_IS_MOOF);
+		push_u32(&mut tfhd, 1);
+		let mut tfdt = full_box(1, 0);
+		tfdt.extend_from_slice(&self.timestamp.to_be_bytes());
+
+		let mut trun = full_box(0, TRUN_DATA_OFFSET | TRUN_SAMPLE_DURATION | TRUN_SAMPLE_SIZE);
+		push_u32(&mut trun, self.pending.len() as u32);
+		// The data offset, from the start of the fragment to the packets
+		// in the following mdat box.
+		let moof_size = 8 + (8 + mfhd.len()) + 8 + (8 + tfhd.len()) + (8 + tfdt.len()) + 8;
+		let moof_size = moof_size + trun.len() + 4 + 8 * self.pending.len();
+		push_u32(&mut trun, moof_size as u32 + 8);
+		for (packet, duration) in &self.pending {
+			push_u32(&mut trun, *duration);
+			push_u32(&mut trun, packet.len() as u32);
+		}
+
+		let mut traf = Vec::new();
+		write_box(&mut traf, b"tfhd", &tfhd);
+		write_box(&mut traf, b"tfdt", &tfdt);
+		write_box(&mut traf, b"trun", &trun);
+		let mut moof = Vec::new();
+		write_box(&mut moof, b"mfhd", &mfhd);
+		write_box(&mut moof, b"traf", &traf);
+		let mut out = Vec::new();
+		write_box(&mut out, b"moof", &moof);
+		debug_assert_eq!(out.len(), moof_size);
+
+		let data: Vec<u8> = self.pending.iter().flat_map(|p| p.0.iter().cloned()).collect();
+		write_box(&mut out, b"mdat", &data);
+		self.inner.write_all(&out)?;
+		self.written += out.len() as u64;
+
+		self.timestamp += self.pending_duration;
+		self.pending.clear();
+		self.pending_duration = 0;
+		Ok(())
+	}
+}
+
+impl<W: Write + Seek> Mp4OpusWriter<W> {
+	/// Write the remaining packets and complete the file, recording in the
+	/// edit list that the track lasts `length` samples per channel after
+	/// the pre-skip.
+	pub fn finish_trimmed(mut self, length: u64) -> Result<W> {
+		self.flush_fragment()?;
+		self.write_headers()?;
+		let total = self.timestamp;
+		if length == 0 || self.head.pre_skip as u64 + length > total {
+			return Err(Error::Opus(super::Error::bad_arg("Mp4OpusWriter::finish_trimmed")));
+		}
+		let end = self.inner.stream_position()?;
+		self.inner.seek(SeekFrom::Start(end - self.written + self.edit_offset))?;
+		self.inner.write_all(&length.to_be_bytes())?;
+		self.inner.seek(SeekFrom::Start(end))?;
+		Ok(self.inner)
+	}
+}
@@ -0,0 +1,1728 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ogg Opus streams as described by [RFC 7845](https://tools.ietf.org/html/rfc7845).
+//!
+//! The Ogg framing is implemented directly, so any `std::io::Read` may be used
+//! as a source and any `std::io::Write` as a destination without pulling in a
+//! separate container crate.
+//!
+//! This module is only available with the `ogg-opus` feature.
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub use super::container::{Error, Result};
+
+use super::container::Trim;
+use super::projection::ProjectionDecoder;
+use super::{
+	packet, Application, Bitrate, ChannelLayout, Channels, Decoder, Encoder, MSDecoder, MSEncoder,
+	Repacketizer,
+};
+
+/// Opus always uses a 48 kHz granule position clock.
+const GRANULE_RATE: u32 = 48000;
+
+/// The largest possible Opus packet duration: 120 ms at 48 kHz.
+const MAX_FRAME_SIZE: usize = 5760;
+
+/// Decoding after a seek starts at least 80 ms early so the decoder can
+/// converge, as recommended by RFC 7845.
+const SEEK_PREROLL: i64 = 3840;
+
+/// Once a seek has narrowed down to this many bytes, the remaining pages are
+/// scanned in order.
+const SEEK_SCAN_BYTES: u64 = 64 * 1024;
+
+// ============================================================================
+// Headers
+
+/// The Ogg Opus identification header ("OpusHead").
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct OpusHead {
+	/// The encapsulation version. Only major version 0 is understood.
+	pub version: u8,
+	/// The number of output channels.
+	pub channels: u8,
+	/// The number of samples (at 48 kHz) to discard from the decoder output
+	/// when starting playback.
+	pub pre_skip: u16,
+	/// The sample rate of the original input, for information only.
+	pub input_sample_rate: u32,
+	/// The output gain to apply, in Q7.8 dB.
+	pub output_gain: i16,
+	/// The channel mapping family.
+	pub mapping_family: u8,
+	/// The number of Opus streams in each packet.
+	pub streams: u8,
+	/// The number of streams which decode to two channels.
+	pub coupled_streams: u8,
+	/// The decoder channel each output channel is taken from. Empty for
+	/// mapping family 3.
+	pub mapping: Vec<u8>,
+	/// For mapping family 3, the matrix mixing the decoded streams into the
+	/// output channels, as little-endian 16-bit coefficients. Empty
+	/// otherwise.
+	pub demixing_matrix: Vec<u8>,
+}
+
+impl OpusHead {
+	/// Parse an identification header packet.
+	pub fn parse(data: &[u8]) -> Result<OpusHead> {
+		if data.len() < 19 || &data[..8] != b"OpusHead" {
+			return Err(Error::Malformed("missing OpusHead magic"));
+		}
+		let version = data[8];
+		if version >> 4 != 0 {
+			return Err(Error::Malformed("unsupported OpusHead version"));
+		}
+		let channels = data[9];
+		if channels == 0 {
+			return Err(Error::Malformed("OpusHead has zero channels"));
+		}
+		let pre_skip = u16::from_le_bytes([data[10], data[11]]);
+		let input_sample_rate = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+		let output_gain = i16::from_le_bytes([data[16], data[17]]);
+		let mapping_family = data[18];
+
+		let mut demixing_matrix = Vec::new();
+		let (streams, coupled_streams, mapping) = if mapping_family == 0 {
+			if channels > 2 {
+				return Err(Error::Malformed("mapping family 0 allows at most two channels"));
+			}
+			(1, channels - 1, (0..channels).collect())
+		} else if mapping_family == 3 {
+			if data.len() < 21 {
+				return Err(Error::Malformed("OpusHead demixing matrix is truncated"));
+			}
+			let size = 2 * channels as usize * (data[19] as usize + data[20] as usize);
+			if data.len() < 21 + size {
+				return Err(Error::Malformed("OpusHead demixing matrix is truncated"));
+			}
+			demixing_matrix = data[21..21 + size].to_vec();
+			(data[19], data[20], Vec::new())
+		} else {
+			if mapping_family == 1 && channels > 8 {
+				return Err(Error::Malformed("mapping family 1 allows at most eight channels"));
+			}
+			if data.len() < 21 + channels as usize {
+				return Err(Error::Malformed("OpusHead channel mapping table is truncated"));
+			}
+			(data[19], data[20], data[21..21 + channels as usize].to_vec())
+		};
+
+		if streams == 0 || coupled_streams > streams {
+			return Err(Error::Malformed("OpusHead has invalid stream counts"));
+		}
+		let decoded_channels = streams as usize + coupled_streams as usize;
+		if decoded_channels > 255 {
+			return Err(Error::Malformed("OpusHead has invalid stream counts"));
+		}
+		if mapping.iter().any(|&m| m != 255 && m as usize >= decoded_channels) {
+			return Err(Error::Malformed("OpusHead channel mapping is out of range"));
+		}
+
+		Ok(OpusHead {
+			version,
+			channels,
+			pre_skip,
+			input_sample_rate,
+			output_gain,
+			mapping_family,
+			streams,
+			coupled_streams,
+			mapping,
+			demixing_matrix,
+		})
+	}
+
+	/// Get the channel layout. Not available for mapping family 3, which
+	/// uses a demixing matrix instead.
+	pub fn layout(&self) -> Result<ChannelLayout> {
+		if self.mapping_family == 3 {
+			return Err(Error::Malformed("mapping family 3 has no channel mapping"));
+		}
+		Ok(ChannelLayout::new(self.streams, self.coupled_streams, &self.mapping)?)
+	}
+
+	/// Set the channel layout, along with the channel count.
+	///
+	/// The mapping family is left unchanged, so should be set to one which
+	/// allows the layout.
+	pub fn set_layout(&mut self, layout: &ChannelLayout) {
+		self.channels = layout.channels();
+		self.streams = layout.streams();
+		self.coupled_streams = layout.coupled_streams();
+		self.mapping = layout.mapping().to_vec();
+		self.demixing_matrix.clear();
+	}
+
+	/// Serialize the header into an identification header packet.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(21 + self.mapping.len() + self.demixing_matrix.len());
+		data.extend_from_slice(b"OpusHead");
+		data.push(self.version);
+		data.push(self.channels);
+		data.extend_from_slice(&self.pre_skip.to_le_bytes());
+		data.extend_from_slice(&self.input_sample_rate.to_le_bytes());
+		data.extend_from_slice(&self.output_gain.to_le_bytes());
+		data.push(self.mapping_family);
+		if self.mapping_family != 0 {
+			data.push(self.streams);
+			data.push(self.coupled_streams);
+			data.extend_from_slice(&self.mapping);
+			data.extend_from_slice(&self.demixing_matrix);
+		}
+		data
+	}
+}
+
+/// The Ogg Opus comment header ("OpusTags").
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct OpusTags {
+	/// The name of the encoder which produced the stream.
+	pub vendor: String,
+	/// The user comments as `(name, value)` pairs, in stream order.
+	///
+	/// Names are case-insensitive and may repeat. A comment with no `=` is
+	/// kept as a name with an empty value.
+	pub comments: Vec<(String, String)>,
+	/// Binary data stored after the comments.
+	///
+	/// This is only kept if its first byte has the least significant bit
+	/// set; otherwise it is padding and is discarded.
+	pub binary: Vec<u8>,
+}
+
+impl OpusTags {
+	/// Parse a comment header packet.
+	pub fn parse(data: &[u8]) -> Result<OpusTags> {
+		if data.len() < 8 || &data[..8] != b"OpusTags" {
+			return Err(Error::Malformed("missing OpusTags magic"));
+		}
+		let mut rest = &data[8..];
+		let vendor = read_string(&mut rest)?;
+		let count = read_u32(&mut rest)?;
+		// Each comment takes at least four bytes, so don't trust the count
+		// for the allocation.
+		let mut comments = Vec::with_capacity(std::cmp::min(count as usize, rest.len() / 4));
+		for _ in 0..count {
+			let comment = read_string(&mut rest)?;
+			comments.push(match comment.find('=') {
+				Some(i) => (comment[..i].to_owned(), comment[i + 1..].to_owned()),
+				None => (comment, String::new()),
+			});
+		}
+		let binary =
+			if rest.first().map_or(false, |&b| b & 1 != 0) { rest.to_vec() } else { Vec::new() };
+		Ok(OpusTags { vendor, comments, binary })
+	}
+
+	/// Serialize the header into a comment header packet.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = b"OpusTags".to_vec();
+		write_string(&mut data, &self.vendor);
+		data.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+		for (name, value) in &self.comments {
+			data.extend_from_slice(&((name.len() + 1 + value.len()) as u32).to_le_bytes());
+			data.extend_from_slice(name.as_bytes());
+			data.push(b'=');
+			data.extend_from_slice(value.as_bytes());
+		}
+		data.extend_from_slice(&self.binary);
+		data
+	}
+
+	/// Get the value of the first comment with the given name.
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.comments.iter().find(|c| c.0.eq_ignore_ascii_case(name)).map(|c| &c.1[..])
+	}
+
+	/// Get the values of all comments with the given name, in order.
+	pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+		self.comments.iter().filter(move |c| c.0.eq_ignore_ascii_case(name)).map(|c| &c.1[..])
+	}
+
+	/// Add a comment after the existing ones.
+	pub fn add(&mut self, name: &str, value: &str) {
+		self.comments.push((name.to_owned(), value.to_owned()));
+	}
+
+	/// Remove all comments with the given name.
+	pub fn remove(&mut self, name: &str) {
+		self.comments.retain(|c| !c.0.eq_ignore_ascii_case(name));
+	}
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+	if data.len() < 4 {
+		return Err(Error::Malformed("OpusTags is truncated"));
+	}
+	let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+	*data = &data[4..];
+	Ok(value)
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String> {
+	let len = read_u32(data)? as usize;
+	if data.len() < len {
+		return Err(Error::Malformed("OpusTags is truncated"));
+	}
+	let value = String::from_utf8_lossy(&data[..len]).into_owned();
+	*data = &data[len..];
+	Ok(value)
+}
+
+fn write_string(data: &mut Vec<u8>, value: &str) {
+	data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+	data.extend_from_slice(value.as_bytes());
+}
+
+// ============================================================================
+// Page Framing
+
+const HEADER_CONTINUED: u8 = 0x01;
+const HEADER_BOS: u8 = 0x02;
+const HEADER_EOS: u8 = 0x04;
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+	let mut table = [0; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = (i as u32) << 24;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+	data.iter().fold(crc, |crc, &b| (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize])
+}
+
+/// A single Ogg page.
+#[derive(Debug)]
+struct Page {
+	flags: u8,
+	granule: i64,
+	serial: u32,
+	sequence: u32,
+	lacing: Vec<u8>,
+	body: Vec<u8>,
+}
+
+impl Page {
+	fn is_bos(&self) -> bool {
+		self.flags & HEADER_BOS != 0
+	}
+
+	fn is_eos(&self) -> bool {
+		self.flags & HEADER_EOS != 0
+	}
+
+	fn is_continued(&self) -> bool {
+		self.flags & HEADER_CONTINUED != 0
+	}
+
+	/// The first packet which begins on this page, if any.
+	fn first_packet(&self) -> &[u8] {
+		let mut len = 0;
+		for &lace in &self.lacing {
+			len += lace as usize;
+			if lace < 255 {
+				break;
+			}
+		}
+		&self.body[..len]
+	}
+
+	fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let mut header = [0; 27];
+		header[..4].copy_from_slice(b"OggS");
+		header[5] = self.flags;
+		header[6..14].copy_from_slice(&self.granule.to_le_bytes());
+		header[14..18].copy_from_slice(&self.serial.to_le_bytes());
+		header[18..22].copy_from_slice(&self.sequence.to_le_bytes());
+		header[26] = self.lacing.len() as u8;
+		let mut crc = crc32(0, &header);
+		crc = crc32(crc, &self.lacing);
+		crc = crc32(crc, &self.body);
+		header[22..26].copy_from_slice(&crc.to_le_bytes());
+
+		writer.write_all(&header)?;
+		writer.write_all(&self.lacing)?;
+		writer.write_all(&self.body)
+	}
+}
+
+/// Reads pages from a byte stream, resynchronizing on corrupt data.
+///
+/// Byte offsets are relative to where the stream was when reading began.
+#[derive(Debug)]
+struct PageReader<R> {
+	inner: R,
+	buf: Vec<u8>,
+	pos: usize,
+	/// The offset of the start of `buf`.
+	offset: u64,
+	/// The offset of the last page read.
+	page_offset: u64,
+	/// The absolute position of offset zero, once it is needed for seeking.
+	origin: Option<u64>,
+}
+
+impl<R: Read> PageReader<R> {
+	fn new(inner: R) -> PageReader<R> {
+		PageReader {
+			inner,
+			buf: Vec::new(),
+			pos: 0,
+			offset: 0,
+			page_offset: 0,
+			origin: None,
+		}
+	}
+
+	/// The offset of the next unread byte.
+	fn position(&self) -> u64 {
+		self.offset + self.pos as u64
+	}
+
+	/// Ensure at least `len` unconsumed bytes are buffered. Returns `false`
+	/// if the stream ended first.
+	fn fill(&mut self, len: usize) -> io::Result<bool> {
+		if self.pos > 0 && self.buf.len() - self.pos < len {
+			self.buf.drain(..self.pos);
+			self.offset += self.pos as u64;
+			self.pos = 0;
+		}
+		while self.buf.len() - self.pos < len {
+			let start = self.buf.len();
+			self.buf.resize(start + std::cmp::max(len, 4096), 0);
+			match self.inner.read(&mut self.buf[start..]) {
+				Ok(n) => {
+					self.buf.truncate(start + n);
+					if n == 0 {
+						return Ok(false);
+					}
+				}
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(start),
+				Err(e) => {
+					self.buf.truncate(start);
+					return Err(e);
+				}
+			}
+		}
+		Ok(true)
+	}
+
+	/// Read the next page with a valid checksum, or `None` at end of stream.
+	fn next_page(&mut self) -> Result<Option<Page>> {
+		loop {
+			if !self.fill(27)? {
+				return Ok(None);
+			}
+			let header = &self.buf[self.pos..self.pos + 27];
+			if &header[..4] != b"OggS" || header[4] != 0 {
+				self.pos += 1;
+				continue;
+			}
+			let segments = header[26] as usize;
+			if !self.fill(27 + segments)? {
+				return Ok(None);
+			}
+			let lacing = &self.buf[self.pos + 27..self.pos + 27 + segments];
+			let body_len: usize = lacing.iter().map(|&l| l as usize).sum();
+			let total = 27 + segments + body_len;
+			if !self.fill(total)? {
+				return Ok(None);
+			}
+
+			let raw = &self.buf[self.pos..self.pos + total];
+			let expected = u32::from_le_bytes([raw[22], raw[23], raw[24], raw[25]]);
+			let mut crc = crc32(0, &raw[..22]);
+			crc = crc32(crc, &[0; 4]);
+			crc = crc32(crc, &raw[26..]);
+			if crc != expected {
+				// A false capture pattern or a damaged page; look for the
+				// next one.
+				self.pos += 1;
+				continue;
+			}
+
+			let mut granule = [0; 8];
+			granule.copy_from_slice(&raw[6..14]);
+			let page = Page {
+				flags: raw[5],
+				granule: i64::from_le_bytes(granule),
+				serial: u32::from_le_bytes([raw[14], raw[15], raw[16], raw[17]]),
+				sequence: u32::from_le_bytes([raw[18], raw[19], raw[20], raw[21]]),
+				lacing: raw[27..27 + segments].to_vec(),
+				body: raw[27 + segments..].to_vec(),
+			};
+			self.page_offset = self.position();
+			self.pos += total;
+			return Ok(Some(page));
+		}
+	}
+}
+
+impl<R: Read + Seek> PageReader<R> {
+	fn origin(&mut self) -> io::Result<u64> {
+		match self.origin {
+			Some(origin) => Ok(origin),
+			None => {
+				let origin = self.inner.stream_position()? - (self.offset + self.buf.len() as u64);
+				self.origin = Some(origin);
+				Ok(origin)
+			}
+		}
+	}
+
+	/// Get the offset of the end of the stream.
+	fn len(&mut self) -> io::Result<u64> {
+		let origin = self.origin()?;
+		let end = self.inner.seek(SeekFrom::End(0))?;
+		// Keep the inner position consistent with the buffer.
+		self.inner.seek(SeekFrom::Start(origin + self.offset + self.buf.len() as u64))?;
+		Ok(end - origin)
+	}
+
+	/// Continue reading pages from the given offset.
+	fn seek(&mut self, offset: u64) -> io::Result<()> {
+		let origin = self.origin()?;
+		self.inner.seek(SeekFrom::Start(origin + offset))?;
+		self.buf.clear();
+		self.pos = 0;
+		self.offset = offset;
+		Ok(())
+	}
+}
+
+/// Reassembles packets which may span several pages.
+#[derive(Debug, Default)]
+struct PacketAssembler {
+	partial: Vec<u8>,
+	/// Whether `partial` holds the beginning of a packet.
+	in_packet: bool,
+}
+
+impl PacketAssembler {
+	/// Split a page into the packets which complete on it.
+	fn push(&mut self, page: &Page) -> Vec<Vec<u8>> {
+		if !page.is_continued() {
+			// Any unfinished packet from before was lost.
+			self.partial.clear();
+			self.in_packet = true;
+		} else if !self.in_packet {
+			// The tail of a packet whose beginning we never saw.
+			self.partial.clear();
+		}
+
+		let mut packets = Vec::new();
+		let mut offset = 0;
+		for &lace in &page.lacing {
+			if self.in_packet {
+				self.partial.extend_from_slice(&page.body[offset..offset + lace as usize]);
+			}
+			offset += lace as usize;
+			if lace < 255 {
+				if self.in_packet {
+					packets.push(std::mem::take(&mut self.partial));
+				}
+				self.in_packet = true;
+			}
+		}
+		packets
+	}
+}
+
+/// Packs packets into pages and writes them out.
+#[derive(Debug)]
+struct PageWriter<W> {
+	inner: W,
+	page: Page,
+	/// Whether any packet completes on the pending page.
+	has_packet: bool,
+}
+
+impl<W: Write> PageWriter<W> {
+	/// Create a writer for a new logical stream.
+	fn new(inner: W) -> PageWriter<W> {
+		// Streams should have random serial numbers so they can be chained
+		// or multiplexed.
+		let serial = RandomState::new().build_hasher().finish() as u32;
+		PageWriter {
+			inner,
+			page: Page {
+				flags: HEADER_BOS,
+				granule: -1,
+				serial,
+				sequence: 0,
+				lacing: Vec::new(),
+				body: Vec::new(),
+			},
+			has_packet: false,
+		}
+	}
+
+	/// Write the header packets, each on a page of its own.
+	fn write_headers(&mut self, head: &OpusHead, tags: &OpusTags) -> io::Result<()> {
+		self.push(&head.to_bytes(), 0)?;
+		self.flush(false)?;
+		self.push(&tags.to_bytes(), 0)?;
+		self.flush(false)
+	}
+
+	/// Add a packet to the pending page, writing out full pages as needed.
+	///
+	/// `granule` is the granule position at the end of the packet.
+	fn push(&mut self, mut packet: &[u8], granule: i64) -> io::Result<()> {
+		loop {
+			let space = 255 - self.page.lacing.len();
+			if space == 0 {
+				self.flush(false)?;
+				continue;
+			}
+			if packet.len() / 255 < space {
+				let full = self.page.lacing.len() + packet.len() / 255;
+				self.page.lacing.resize(full, 255);
+				self.page.lacing.push((packet.len() % 255) as u8);
+				self.page.body.extend_from_slice(packet);
+				self.page.granule = granule;
+				self.has_packet = true;
+				return Ok(());
+			}
+			// Fill this page and continue the packet on the next one.
+			self.page.lacing.resize(255, 255);
+			self.page.body.extend_from_slice(&packet[..space * 255]);
+			packet = &packet[space * 255..];
+			self.flush(false)?;
+			self.page.flags |= HEADER_CONTINUED;
+		}
+	}
+
+	/// Write out the pending page, if there is anything to write.
+	fn flush(&mut self, eos: bool) -> io::Result<()> {
+		if self.page.lacing.is_empty() && !eos {
+			return Ok(());
+		}
+		if eos {
+			self.page.flags |= HEADER_EOS;
+		}
+		if !self.has_packet {
+			self.page.granule = -1;
+		}
+		self.page.write_to(&mut self.inner)?;
+		self.page.flags = 0;
+		self.page.sequence += 1;
+		self.page.lacing.clear();
+		self.page.body.clear();
+		self.has_packet = false;
+		Ok(())
+	}
+}
+
+// ============================================================================
+// Reader
+
+/// The decoder matching a stream's channel mapping.
+#[derive(Debug)]
+pub(crate) enum MappedDecoder {
+	Single(Decoder),
+	Multi(MSDecoder),
+	Projection(ProjectionDecoder),
+}
+
+impl MappedDecoder {
+	pub(crate) fn new(head: &OpusHead) -> Result<MappedDecoder> {
+		let mut decoder = match head.mapping_family {
+			0 => {
+				let channels = if head.channels == 1 { Channels::Mono } else { Channels::Stereo };
+				MappedDecoder::Single(Decoder::new(GRANULE_RATE, channels)?)
+			}
+			1 | 2 | 255 => {
+				MappedDecoder::Multi(MSDecoder::with_layout(GRANULE_RATE, &head.layout()?)?)
+			}
+			3 => MappedDecoder::Projection(ProjectionDecoder::new(
+				GRANULE_RATE,
+				head.channels,
+				head.streams,
+				head.coupled_streams,
+				&head.demixing_matrix,
+			)?),
+			_ => return Err(Error::Malformed("unsupported channel mapping family")),
+		};
+		match decoder {
+			MappedDecoder::Single(ref mut d) => d.set_gain(head.output_gain as i32)?,
+			MappedDecoder::Multi(ref mut d) => d.set_gain(head.output_gain as i32)?,
+			MappedDecoder::Projection(ref mut d) => d.set_gain(head.output_gain as i32)?,
+		}
+		Ok(decoder)
+	}
+
+	pub(crate) fn decode(&mut self, input: &[u8], output: &mut [i16]) -> super::Result<usize> {
+		match *self {
+			MappedDecoder::Single(ref mut d) => d.decode(input, output, false),
+			MappedDecoder::Multi(ref mut d) => d.decode(input, output, false),
+			MappedDecoder::Projection(ref mut d) => d.decode(input, output, false),
+		}
+	}
+
+	pub(crate) fn decode_float(
+		&mut self,
+		input: &[u8],
+		output: &mut [f32],
+	) -> super::Result<usize> {
+		match *self {
+			MappedDecoder::Single(ref mut d) => d.decode_float(input, output, false),
+			MappedDecoder::Multi(ref mut d) => d.decode_float(input, output, false),
+			MappedDecoder::Projection(ref mut d) => d.decode_float(input, output, false),
+		}
+	}
+
+	fn reset_state(&mut self) -> super::Result<()> {
+		match *self {
+			MappedDecoder::Single(ref mut d) => d.reset_state(),
+			MappedDecoder::Multi(ref mut d) => d.reset_state(),
+			MappedDecoder::Projection(ref mut d) => d.reset_state(),
+		}
+	}
+}
+
+/// An audio packet waiting to be decoded.
+#[derive(Debug)]
+struct QueuedPacket {
+	data: Vec<u8>,
+	/// The granule position at which output must stop, set for packets on
+	/// the final page of the stream.
+	end: Option<i64>,
+}
+
+/// A packet read without decoding, with positions counted from the start
+/// granule of its stream.
+#[derive(Debug)]
+struct RawPacket {
+	data: Vec<u8>,
+	start: i64,
+	duration: i64,
+	/// The position at which output must stop, set for packets on the final
+	/// page of the stream.
+	end: Option<i64>,
+}
+
+/// Reads and decodes an Ogg Opus stream.
+///
+/// Output is always interleaved PCM at 48 kHz with the pre-skip and end
+/// trimming described by the stream removed and its output gain applied.
+///
+/// The links of a chained stream are decoded one after another, each with
+/// its own trimming. `head` and `tags` describe the link being decoded.
+#[derive(Debug)]
+pub struct OggOpusReader<R> {
+	pages: PageReader<R>,
+	serial: u32,
+	head: OpusHead,
+	tags: OpusTags,
+	decoder: MappedDecoder,
+	assembler: PacketAssembler,
+	queue: VecDeque<QueuedPacket>,
+	/// The offset of the first page after the headers of the current link.
+	data_offset: u64,
+	/// The granule position of the first audio sample.
+	start_granule: i64,
+	/// The granule position at the end of the decoded output so far.
+	granule: i64,
+	trim: Trim,
+	/// Whether the first page of audio has been read.
+	started: bool,
+	eos: bool,
+	/// The headers of the next link, while they are being read.
+	next_link: Option<LinkHeaders>,
+}
+
+/// The headers of one link of a stream. Each header is kept once it is
+/// complete, so reading can resume after the source reports `WouldBlock`.
+#[derive(Debug, Default)]
+struct LinkHeaders {
+	serial: u32,
+	head: Option<OpusHead>,
+	assembler: PacketAssembler,
+	tags: Option<OpusTags>,
+}
+
+impl LinkHeaders {
+	/// Read whichever headers are still missing.
+	///
+	/// The first Opus stream found is used; other multiplexed streams are
+	/// ignored. A `chained` link may follow pages of the links before it,
+	/// and `false` is returned if the stream ends before it begins.
+	fn read<R: Read>(&mut self, pages: &mut PageReader<R>, chained: bool) -> Result<bool> {
+		while self.head.is_none() {
+			let page = match pages.next_page()? {
+				Some(page) => page,
+				None if chained => return Ok(false),
+				None => return Err(Error::Malformed("no Opus stream found")),
+			};
+			if !page.is_bos() {
+				if chained {
+					continue;
+				}
+				return Err(Error::Malformed("no Opus stream found"));
+			}
+			if page.first_packet().starts_with(b"OpusHead") {
+				if page.lacing.last() == Some(&255)
+					|| page.lacing.iter().filter(|&&l| l < 255).count() != 1
+				{
+					return Err(Error::Malformed("OpusHead must be alone on the first page"));
+				}
+				self.head = Some(OpusHead::parse(page.first_packet())?);
+				self.serial = page.serial;
+			}
+		}
+
+		while self.tags.is_none() {
+			let page = match pages.next_page()? {
+				Some(page) => page,
+				None => return Err(Error::Malformed("missing OpusTags header")),
+			};
+			if page.serial != self.serial {
+				continue;
+			}
+			let packets = self.assembler.push(&page);
+			match packets.len() {
+				0 => {}
+				1 if page.lacing.last() != Some(&255) => {
+					self.tags = Some(OpusTags::parse(&packets[0])?)
+				}
+				_ => return Err(Error::Malformed("OpusTags must end its page")),
+			}
+		}
+		Ok(true)
+	}
+
+	/// Take the headers once `read` has succeeded.
+	fn finish(self) -> (u32, OpusHead, OpusTags, PacketAssembler) {
+		let head = self.head.expect("headers were read");
+		let tags = self.tags.expect("headers were read");
+		(self.serial, head, tags, self.assembler)
+	}
+}
+
+/// Reads the headers at the start of a stream, resuming after the source
+/// reports `WouldBlock`.
+#[derive(Debug)]
+pub(crate) struct HeaderReader<R> {
+	pages: PageReader<R>,
+	headers: LinkHeaders,
+}
+
+impl<R: Read> HeaderReader<R> {
+	pub(crate) fn new(reader: R) -> HeaderReader<R> {
+		HeaderReader {
+			pages: PageReader::new(reader),
+			headers: LinkHeaders::default(),
+		}
+	}
+
+	/// Get the identification header, once it has been read.
+	#[cfg(feature = "async")]
+	pub(crate) fn head(&self) -> Option<&OpusHead> {
+		self.headers.head.as_ref()
+	}
+
+	/// Get the comment header, once it has been read.
+	#[cfg(feature = "async")]
+	pub(crate) fn tags(&self) -> Option<&OpusTags> {
+		self.headers.tags.as_ref()
+	}
+
+	/// Get the underlying reader, to supply more data after it reported
+	/// `WouldBlock`.
+	#[cfg(feature = "async")]
+	pub(crate) fn inner_mut(&mut self) -> &mut R {
+		&mut self.pages.inner
+	}
+
+	/// Read whichever headers are still missing.
+	pub(crate) fn read(&mut self) -> Result<()> {
+		self.headers.read(&mut self.pages, false)?;
+		Ok(())
+	}
+
+	/// Prepare a decoder for the headers once `read` has succeeded. The
+	/// first page of audio is read along with the first packet.
+	pub(crate) fn into_reader(self) -> Result<OggOpusReader<R>> {
+		let (serial, head, tags, assembler) = self.headers.finish();
+		let decoder = MappedDecoder::new(&head)?;
+		let trim = Trim { skip: head.pre_skip as u64, remaining: None };
+		Ok(OggOpusReader {
+			data_offset: self.pages.position(),
+			pages: self.pages,
+			serial,
+			head,
+			tags,
+			decoder,
+			assembler,
+			queue: VecDeque::new(),
+			start_granule: 0,
+			granule: 0,
+			trim,
+			started: false,
+			eos: false,
+			next_link: None,
+		})
+	}
+}
+
+impl<R: Read> OggOpusReader<R> {
+	/// Read the stream headers and prepare a decoder for them.
+	///
+	/// The first Opus stream found is used; other multiplexed streams are
+	/// ignored.
+	pub fn new(reader: R) -> Result<OggOpusReader<R>> {
+		let mut headers = HeaderReader::new(reader);
+		headers.read()?;
+		let mut reader = headers.into_reader()?;
+		reader.read_first_page()?;
+		Ok(reader)
+	}
+
+	/// Get the stream's identification header.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the stream's comment header.
+	pub fn tags(&self) -> &OpusTags {
+		&self.tags
+	}
+
+	/// Get the number of interleaved channels in the decoded output.
+	pub fn channels(&self) -> usize {
+		self.head.channels as usize
+	}
+
+	/// Get the underlying reader, to supply more data after it reported
+	/// `WouldBlock`.
+	#[cfg(feature = "async")]
+	pub(crate) fn inner_mut(&mut self) -> &mut R {
+		&mut self.pages.inner
+	}
+
+	/// Decode the next chunk of audio.
+	///
+	/// Returns `None` at the end of the stream.
+	pub fn decode(&mut self) -> Result<Option<Vec<i16>>> {
+		let mut output = Vec::new();
+		while let Some(packet) = self.next_audio_packet()? {
+			// Each link of a chained stream may have its own channel count.
+			output.resize(MAX_FRAME_SIZE * self.channels(), 0);
+			let samples = self.decoder.decode(&packet.data, &mut output)?;
+			if self.trim_output(&mut output, samples, packet.end) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Decode the next chunk of audio with floating point output.
+	///
+	/// Returns `None` at the end of the stream.
+	pub fn decode_float(&mut self) -> Result<Option<Vec<f32>>> {
+		let mut output = Vec::new();
+		while let Some(packet) = self.next_audio_packet()? {
+			// Each link of a chained stream may have its own channel count.
+			output.resize(MAX_FRAME_SIZE * self.channels(), 0.0);
+			let samples = self.decoder.decode_float(&packet.data, &mut output)?;
+			if self.trim_output(&mut output, samples, packet.end) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Account for `samples` freshly decoded samples per channel in
+	/// `output`, cutting it down to those which should be output. Returns
+	/// false if none remain.
+	fn trim_output<T>(&mut self, output: &mut Vec<T>, samples: usize, end: Option<i64>) -> bool {
+		let limit = match end {
+			Some(end) => std::cmp::max(end - self.granule, 0) as usize,
+			None => samples,
+		};
+		self.granule += samples as i64;
+		let channels = self.channels();
+		self.trim.apply(output, samples, limit, channels)
+	}
+
+	/// Read the next page belonging to the Opus stream.
+	fn next_stream_page(&mut self) -> Result<Option<Page>> {
+		if self.eos {
+			return Ok(None);
+		}
+		while let Some(page) = self.pages.next_page()? {
+			if page.serial == self.serial {
+				self.eos = page.is_eos();
+				return Ok(Some(page));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Queue the packets completed on a page.
+	fn enqueue(&mut self, page: &Page, packets: Vec<Vec<u8>>) {
+		let end = if page.is_eos() { Some(page.granule) } else { None };
+		self.queue.extend(packets.into_iter().map(|data| QueuedPacket { data, end }));
+	}
+
+	/// Read up to the first page completing an audio packet and work out
+	/// the granule position at which the audio begins.
+	fn read_first_page(&mut self) -> Result<()> {
+		while let Some(page) = self.next_stream_page()? {
+			let packets = self.assembler.push(&page);
+			if packets.is_empty() {
+				continue;
+			}
+			let mut duration = 0;
+			for packet in &packets {
+				duration += packet::get_nb_samples(packet, GRANULE_RATE)? as i64;
+			}
+			self.start_granule = if page.is_eos() {
+				// The final page may end early to trim the last packet, so
+				// the audio is assumed to start at zero.
+				0
+			} else if page.granule >= duration {
+				page.granule - duration
+			} else {
+				return Err(Error::Malformed("first audio page has an invalid granule position"));
+			};
+			self.granule = self.start_granule;
+			self.enqueue(&page, packets);
+			break;
+		}
+		self.started = true;
+		Ok(())
+	}
+
+	/// Read the next packet without decoding it.
+	fn next_raw_packet(&mut self) -> Result<Option<RawPacket>> {
+		let packet = match self.next_packet()? {
+			Some(packet) => packet,
+			None => return Ok(None),
+		};
+		let duration = packet::get_nb_samples(&packet.data, GRANULE_RATE)? as i64;
+		let start = self.granule - self.start_granule;
+		self.granule += duration;
+		Ok(Some(RawPacket {
+			end: packet.end.map(|end| end - self.start_granule),
+			data: packet.data,
+			start,
+			duration,
+		}))
+	}
+
+	fn next_packet(&mut self) -> Result<Option<QueuedPacket>> {
+		if !self.started {
+			self.read_first_page()?;
+		}
+		while self.queue.is_empty() {
+			let page = match self.next_stream_page()? {
+				Some(page) => page,
+				None => return Ok(None),
+			};
+			let packets = self.assembler.push(&page);
+			self.enqueue(&page, packets);
+		}
+		Ok(self.queue.pop_front())
+	}
+
+	/// Read the next packet to decode, moving on to the next link of a
+	/// chained stream at the end of each link.
+	fn next_audio_packet(&mut self) -> Result<Option<QueuedPacket>> {
+		loop {
+			if let Some(packet) = self.next_packet()? {
+				return Ok(Some(packet));
+			}
+			let mut headers = self.next_link.take().unwrap_or_default();
+			match headers.read(&mut self.pages, true) {
+				Ok(true) => self.start_link(headers)?,
+				Ok(false) => return Ok(None),
+				Err(e) => {
+					self.next_link = Some(headers);
+					return Err(e);
+				}
+			}
+		}
+	}
+
+	/// Prepare to decode the link described by `headers`.
+	fn start_link(&mut self, headers: LinkHeaders) -> Result<()> {
+		let (serial, head, tags, assembler) = headers.finish();
+		self.decoder = MappedDecoder::new(&head)?;
+		self.trim = Trim { skip: head.pre_skip as u64, remaining: None };
+		self.serial = serial;
+		self.head = head;
+		self.tags = tags;
+		self.assembler = assembler;
+		self.queue.clear();
+		self.data_offset = self.pages.position();
+		self.started = false;
+		self.eos = false;
+		Ok(())
+	}
+}
+
+impl<R: Read + Seek> OggOpusReader<R> {
+	/// Seek so that decoding continues with the given sample.
+	///
+	/// Samples are counted per channel at 48 kHz from the first sample of
+	/// output, after the pre-skip. Decoding resumes from far enough before
+	/// the target for the decoder to converge, and the extra output is
+	/// discarded. Seeking past the end of the stream is not an error; the
+	/// next decode simply returns `None`.
+	///
+	/// In a chained stream, samples are counted from the start of the link
+	/// being decoded, and seeking past its end continues with the next link.
+	pub fn seek_to_sample(&mut self, sample: u64) -> Result<()> {
+		let pre_skip = self.head.pre_skip as i64;
+		let target = self.start_granule + pre_skip + sample as i64;
+
+		let end = self.pages.len()?;
+		let found = self.find_page_before(target - SEEK_PREROLL, end)?;
+
+		self.decoder.reset_state()?;
+		self.assembler = PacketAssembler::default();
+		self.queue.clear();
+		self.eos = false;
+		self.next_link = None;
+		match found {
+			Some((offset, granule)) => {
+				// Resume with the first packet ending after this page.
+				self.pages.seek(offset)?;
+				if let Some(page) = self.next_stream_page()? {
+					self.assembler.push(&page);
+				}
+				self.granule = granule;
+				self.trim.skip = (target - granule) as u64;
+				self.started = true;
+			}
+			None => {
+				// Too close to the beginning: decode from the start.
+				self.pages.seek(self.data_offset)?;
+				self.trim.skip = (pre_skip + sample as i64) as u64;
+				self.read_first_page()?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Find the last page of the stream which has a granule position no
+	/// greater than `target`, returning its offset and granule position.
+	fn find_page_before(&mut self, target: i64, end: u64) -> Result<Option<(u64, i64)>> {
+		let mut best = None;
+		let mut low = self.data_offset;
+		let mut high = end;
+		while high - low > SEEK_SCAN_BYTES {
+			let middle = low + (high - low) / 2;
+			self.pages.seek(middle)?;
+			match self.next_granule_page(high)? {
+				Some((offset, granule)) if granule <= target => {
+					best = Some((offset, granule));
+					low = self.pages.position();
+				}
+				_ => high = middle,
+			}
+		}
+
+		self.pages.seek(low)?;
+		while let Some((offset, granule)) = self.next_granule_page(end)? {
+			if granule > target {
+				break;
+			}
+			best = Some((offset, granule));
+		}
+		Ok(best)
+	}
+
+	/// Find the next page of the stream which completes a packet, stopping
+	/// at `limit`.
+	fn next_granule_page(&mut self, limit: u64) -> Result<Option<(u64, i64)>> {
+		while let Some(page) = self.pages.next_page()? {
+			let offset = self.pages.page_offset;
+			if offset >= limit {
+				break;
+			}
+			if page.serial == self.serial && page.granule != -1 {
+				return Ok(Some((offset, page.granule)));
+			}
+		}
+		Ok(None)
+	}
+}
+
+// ============================================================================
+// PCM Reader
+
+/// The byte format of samples produced by an `OggOpusPcmReader`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PcmFormat {
+	/// Signed 16-bit little-endian integers.
+	S16Le,
+	/// 32-bit little-endian IEEE floats.
+	F32Le,
+}
+
+impl PcmFormat {
+	/// Get the size of one sample in bytes.
+	pub fn sample_size(self) -> usize {
+		match self {
+			PcmFormat::S16Le => 2,
+			PcmFormat::F32Le => 4,
+		}
+	}
+}
+
+/// Exposes the decoded audio of an Ogg Opus stream as raw interleaved PCM
+/// through `std::io::Read`.
+///
+/// The output is exactly what `OggOpusReader::decode` or `decode_float`
+/// would produce, serialized as little-endian bytes. Decoding errors are
+/// reported as `io::Error`s wrapping the `ogg::Error`.
+#[derive(Debug)]
+pub struct OggOpusPcmReader<R> {
+	reader: OggOpusReader<R>,
+	format: PcmFormat,
+	/// Decoded bytes not yet read.
+	buffer: Vec<u8>,
+	pos: usize,
+}
+
+impl<R: Read> OggOpusPcmReader<R> {
+	/// Read the stream headers and prepare to decode in the given format.
+	pub fn new(reader: R, format: PcmFormat) -> Result<OggOpusPcmReader<R>> {
+		Ok(OggOpusPcmReader::with_reader(OggOpusReader::new(reader)?, format))
+	}
+
+	/// Wrap an existing stream reader, continuing from its current position.
+	pub fn with_reader(reader: OggOpusReader<R>, format: PcmFormat) -> OggOpusPcmReader<R> {
+		OggOpusPcmReader { reader, format, buffer: Vec::new(), pos: 0 }
+	}
+
+	/// Get the output sample format.
+	pub fn format(&self) -> PcmFormat {
+		self.format
+	}
+
+	/// Get the underlying stream reader.
+	pub fn get_ref(&self) -> &OggOpusReader<R> {
+		&self.reader
+	}
+
+	/// Unwrap the underlying stream reader.
+	///
+	/// Any decoded output which has not been read yet is lost.
+	pub fn into_inner(self) -> OggOpusReader<R> {
+		self.reader
+	}
+
+	/// Decode the next chunk into the buffer, returning false at the end of
+	/// the stream.
+	fn fill_buffer(&mut self) -> Result<bool> {
+		self.buffer.clear();
+		self.pos = 0;
+		match self.format {
+			PcmFormat::S16Le => {
+				if let Some(pcm) = self.reader.decode()? {
+					self.buffer.extend(pcm.iter().flat_map(|s| s.to_le_bytes()));
+				}
+			}
+			PcmFormat::F32Le => {
+				if let Some(pcm) = self.reader.decode_float()? {
+					self.buffer.extend(pcm.iter().flat_map(|s| s.to_le_bytes()));
+				}
+			}
+		}
+		Ok(!self.buffer.is_empty())
+	}
+}
+
+impl<R: Read + Seek> OggOpusPcmReader<R> {
+	/// Seek so that reading continues with the given sample.
+	///
+	/// See `OggOpusReader::seek_to_sample`.
+	pub fn seek_to_sample(&mut self, sample: u64) -> Result<()> {
+		self.buffer.clear();
+		self.pos = 0;
+		self.reader.seek_to_sample(sample)
+	}
+}
+
+impl<R: Read> Read for OggOpusPcmReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		if self.pos == self.buffer.len() && !self.fill_buffer()? {
+			return Ok(0);
+		}
+		let count = std::cmp::min(buf.len(), self.buffer.len() - self.pos);
+		buf[..count].copy_from_slice(&self.buffer[self.pos..self.pos + count]);
+		self.pos += count;
+		Ok(count)
+	}
+}
+
+// ============================================================================
+// Writer
+
+/// The encoder matching a stream's channel mapping.
+#[derive(Debug)]
+enum MappedEncoder {
+	Single(Encoder),
+	Multi(MSEncoder),
+}
+
+impl MappedEncoder {
+	fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> super::Result<usize> {
+		match *self {
+			MappedEncoder::Single(ref mut e) => e.encode_float(input, output),
+			MappedEncoder::Multi(ref mut e) => e.encode_float(input, output),
+		}
+	}
+
+	fn get_lookahead(&mut self) -> super::Result<i32> {
+		match *self {
+			MappedEncoder::Single(ref mut e) => e.get_lookahead(),
+			MappedEncoder::Multi(ref mut e) => e.get_lookahead(),
+		}
+	}
+}
+
+/// Encodes PCM audio into an Ogg Opus stream.
+///
+/// Input is interleaved PCM at the sample rate given on creation, in Vorbis
+/// channel order when there are more than two channels. It may be supplied
+/// in chunks of any length. The headers are written along with the first
+/// audio, and [`finish`](#method.finish) must be called to complete the
+/// stream.
+#[derive(Debug)]
+pub struct OggOpusWriter<W> {
+	pages: PageWriter<W>,
+	encoder: MappedEncoder,
+	head: OpusHead,
+	tags: OpusTags,
+	headers_written: bool,
+	channels: usize,
+	/// Samples per channel in each frame, at the input sample rate.
+	frame_size: usize,
+	/// Granule position units per input sample.
+	granule_scale: i64,
+	/// Input not yet encoded.
+	buffer: Vec<f32>,
+	/// Samples per channel of input received so far.
+	samples: i64,
+	/// The granule position at the end of the packets encoded so far.
+	granule: i64,
+	/// The granule position at the start of the pending page.
+	page_start: i64,
+	packet: Vec<u8>,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+	/// Create a writer for the given input format.
+	///
+	/// One or two channels use a single Opus stream (mapping family 0);
+	/// three to eight channels use the Vorbis surround layouts of mapping
+	/// family 1.
+	pub fn new(
+		writer: W,
+		sample_rate: u32,
+		channels: u8,
+		application: Application,
+	) -> Result<OggOpusWriter<W>> {
+		let layout = ChannelLayout::vorbis(channels)?;
+		let (encoder, mapping_family) = match channels {
+			1 | 2 => {
+				let ch = if channels == 1 { Channels::Mono } else { Channels::Stereo };
+				(MappedEncoder::Single(Encoder::new(sample_rate, ch, application)?), 0)
+			}
+			_ => {
+				let encoder = MSEncoder::with_layout(sample_rate, &layout, application)?;
+				(MappedEncoder::Multi(encoder), 1)
+			}
+		};
+
+		let channels = channels as usize;
+		Ok(OggOpusWriter {
+			pages: PageWriter::new(writer),
+			encoder,
+			head: OpusHead {
+				version: 1,
+				channels: channels as u8,
+				pre_skip: 0,
+				input_sample_rate: sample_rate,
+				output_gain: 0,
+				mapping_family,
+				streams: layout.streams(),
+				coupled_streams: layout.coupled_streams(),
+				mapping: layout.mapping().to_vec(),
+				demixing_matrix: Vec::new(),
+			},
+			tags: OpusTags {
+				vendor: super::version().to_owned(),
+				comments: Vec::new(),
+				binary: Vec::new(),
+			},
+			headers_written: false,
+			channels,
+			frame_size: sample_rate as usize / 50,
+			granule_scale: (GRANULE_RATE / sample_rate) as i64,
+			buffer: Vec::new(),
+			samples: 0,
+			granule: 0,
+			page_start: 0,
+			packet: vec![0; 4000 * layout.streams() as usize],
+		})
+	}
+
+	/// Get the comment header to be written, so comments can be added.
+	///
+	/// Changes made after the first audio is written have no effect.
+	pub fn tags_mut(&mut self) -> &mut OpusTags {
+		&mut self.tags
+	}
+
+	/// Set the output gain to store in the identification header, in Q7.8 dB.
+	///
+	/// Changes made after the first audio is written have no effect.
+	pub fn set_output_gain(&mut self, gain: i16) {
+		self.head.output_gain = gain;
+	}
+
+	/// Set the encoder's bitrate.
+	pub fn set_bitrate(&mut self, bitrate: Bitrate) -> Result<()> {
+		match self.encoder {
+			MappedEncoder::Single(ref mut e) => e.set_bitrate(bitrate)?,
+			MappedEncoder::Multi(ref mut e) => e.set_bitrate(bitrate)?,
+		}
+		Ok(())
+	}
+
+	/// Get the underlying encoder, if the stream uses a single Opus stream.
+	pub fn encoder_mut(&mut self) -> Option<&mut Encoder> {
+		match self.encoder {
+			MappedEncoder::Single(ref mut e) => Some(e),
+			MappedEncoder::Multi(_) => None,
+		}
+	}
+
+	/// Get the underlying multistream encoder, if the stream uses one.
+	pub fn ms_encoder_mut(&mut self) -> Option<&mut MSEncoder> {
+		match self.encoder {
+			MappedEncoder::Single(_) => None,
+			MappedEncoder::Multi(ref mut e) => Some(e),
+		}
+	}
+
+	/// Write interleaved PCM.
+	pub fn write(&mut self, pcm: &[i16]) -> Result<()> {
+		self.check_input(pcm.len())?;
+		self.buffer.extend(pcm.iter().map(|&s| s as f32 / 32768.0));
+		self.encode_buffered()
+	}
+
+	/// Write interleaved floating point PCM.
+	pub fn write_float(&mut self, pcm: &[f32]) -> Result<()> {
+		self.check_input(pcm.len())?;
+		self.buffer.extend_from_slice(pcm);
+		self.encode_buffered()
+	}
+
+	/// Encode the remaining input and complete the stream.
+	///
+	/// The final frame is padded with silence and the end of the stream is
+	/// marked so that readers trim the padding and the encoder delay.
+	pub fn finish(mut self) -> Result<W> {
+		self.write_headers()?;
+		let end = self.head.pre_skip as i64 + self.samples * self.granule_scale;
+		let frame_len = self.frame_size * self.channels;
+		while self.granule < end {
+			self.buffer.resize(frame_len, 0.0);
+			self.encode_frame(end)?;
+		}
+		self.pages.flush(true)?;
+		Ok(self.pages.inner)
+	}
+
+	fn check_input(&mut self, len: usize) -> Result<()> {
+		if len % self.channels != 0 {
+			return Err(Error::Opus(super::Error::bad_arg("OggOpusWriter::write")));
+		}
+		self.samples += (len / self.channels) as i64;
+		Ok(())
+	}
+
+	fn write_headers(&mut self) -> Result<()> {
+		if self.headers_written {
+			return Ok(());
+		}
+		let lookahead = self.encoder.get_lookahead()? as i64 * self.granule_scale;
+		self.head.pre_skip = lookahead as u16;
+		self.pages.write_headers(&self.head, &self.tags)?;
+		self.headers_written = true;
+		Ok(())
+	}
+
+	fn encode_buffered(&mut self) -> Result<()> {
+		self.write_headers()?;
+		while self.buffer.len() >= self.frame_size * self.channels {
+			self.encode_frame(i64::MAX)?;
+		}
+		Ok(())
+	}
+
+	/// Encode one frame from the front of the buffer, limiting the granule
+	/// position of the resulting packet to `end`.
+	fn encode_frame(&mut self, end: i64) -> Result<()> {
+		let frame_len = self.frame_size * self.channels;
+		let len = self.encoder.encode_float(&self.buffer[..frame_len], &mut self.packet)?;
+		self.buffer.drain(..frame_len);
+
+		// Keep pages to about a second of audio so seeking stays cheap.
+		if self.granule - self.page_start >= GRANULE_RATE as i64 {
+			self.pages.flush(false)?;
+			self.page_start = self.granule;
+		}
+		self.granule += self.frame_size as i64 * self.granule_scale;
+		self.pages.push(&self.packet[..len], std::cmp::min(self.granule, end))?;
+		Ok(())
+	}
+}
+
+// ============================================================================
+// Editing
+
+/// Writes packets which are already encoded into a new stream.
+#[derive(Debug)]
+struct Remuxer<W> {
+	pages: PageWriter<W>,
+	/// The granule position at the start of the pending page.
+	page_start: i64,
+}
+
+impl<W: Write> Remuxer<W> {
+	fn new(writer: W) -> Remuxer<W> {
+		Remuxer { pages: PageWriter::new(writer), page_start: 0 }
+	}
+
+	/// Add a packet beginning at granule position `start` and ending at
+	/// `granule`.
+	fn push(&mut self, packet: &[u8], start: i64, granule: i64) -> Result<()> {
+		// Keep pages to about a second of audio, as `OggOpusWriter` does.
+		if start - self.page_start >= GRANULE_RATE as i64 {
+			self.pages.flush(false)?;
+			self.page_start = start;
+		}
+		self.pages.push(packet, granule)?;
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<W> {
+		self.pages.flush(true)?;
+		Ok(self.pages.inner)
+	}
+}
+
+/// Keep only the frames of a packet which overlap the range from `begin` to
+/// `stop`, splitting it with the repacketizer if need be.
+///
+/// Returns the remaining packet data and its start and duration.
+fn keep_frames<'a>(
+	repacketizer: &mut Repacketizer,
+	buffer: &'a mut Vec<u8>,
+	packet: &'a RawPacket,
+	begin: i64,
+	stop: i64,
+) -> Result<(&'a [u8], i64, i64)> {
+	let data = &packet.data[..];
+	let frames = packet::get_nb_frames(data)? as i64;
+	if frames > 1 {
+		let frame_size = packet.duration / frames;
+		let first = std::cmp::max(begin - packet.start, 0) / frame_size;
+		let last = std::cmp::min((stop - packet.start + frame_size - 1) / frame_size, frames);
+		if first > 0 || last < frames {
+			buffer.resize(data.len(), 0);
+			let mut state = repacketizer.begin();
+			state.cat(data)?;
+			let len = state.out_range(first as usize, last as usize, buffer)?;
+			let start = packet.start + first * frame_size;
+			return Ok((&buffer[..len], start, (last - first) * frame_size));
+		}
+	}
+	Ok((data, packet.start, packet.duration))
+}
+
+/// Copy the audio from `start` to `end` of an Ogg Opus stream into a new
+/// stream, without re-encoding.
+///
+/// Samples are counted per channel at 48 kHz from the first sample of
+/// output, as for `OggOpusReader::seek_to_sample`. The copy begins 80 ms
+/// before `start` so the decoder can converge, and the new pre-skip and
+/// final granule position trim the output to exactly the requested range.
+/// Packets of several frames are split at the boundaries with the
+/// repacketizer, except in multistream files where whole packets are kept.
+/// A range extending past the end of the stream is cut short. Only the first
+/// link of a chained stream is read.
+pub fn cut<R: Read, W: Write>(reader: R, writer: W, start: u64, end: u64) -> Result<W> {
+	if start > end {
+		return Err(Error::Opus(super::Error::bad_arg("ogg::cut")));
+	}
+	let mut source = OggOpusReader::new(reader)?;
+	let mut head = source.head.clone();
+	let begin = head.pre_skip as i64 + start as i64;
+	let mut stop = head.pre_skip as i64 + end as i64;
+	let preroll = std::cmp::max(begin - SEEK_PREROLL, 0);
+	let split = head.streams == 1;
+
+	let mut output = Remuxer::new(writer);
+	// The source position of the first sample copied.
+	let mut origin = None;
+	let mut repacketizer = Repacketizer::new()?;
+	let mut buffer = Vec::new();
+	while let Some(packet) = source.next_raw_packet()? {
+		if let Some(end) = packet.end {
+			stop = std::cmp::max(std::cmp::min(stop, end), begin);
+		}
+		if packet.start >= stop {
+			break;
+		}
+		if packet.start + packet.duration <= preroll {
+			continue;
+		}
+
+		// Keep only the frames overlapping the range.
+		let (data, first_sample, duration) = if split {
+			keep_frames(&mut repacketizer, &mut buffer, &packet, preroll, stop)?
+		} else {
+			(&packet.data[..], packet.start, packet.duration)
+		};
+
+		let origin = match origin {
+			Some(origin) => origin,
+			None => {
+				if begin - first_sample > u16::MAX as i64 {
+					return Err(Error::Malformed("cut needs too long a pre-skip"));
+				}
+				head.pre_skip = (begin - first_sample) as u16;
+				output.pages.write_headers(&head, &source.tags)?;
+				*origin.get_or_insert(first_sample)
+			}
+		};
+		let granule = std::cmp::min(first_sample + duration, stop) - origin;
+		output.push(data, first_sample - origin, granule)?;
+	}
+
+	if origin.is_none() {
+		// Nothing in range: write an empty stream.
+		head.pre_skip = 0;
+		output.pages.write_headers(&head, &source.tags)?;
+	}
+	output.finish()
+}
+
+/// Join Ogg Opus streams with the same channel layout and output gain into
+/// a single stream, without re-encoding.
+///
+/// The headers are taken from the first stream, and the granule positions
+/// of each later stream are rewritten to follow on from the stream before
+/// it. The pre-skip of each later stream and the end padding of each stream
+/// but the last are cut at frame boundaries, splitting packets with the
+/// repacketizer as `cut` does, so less than a frame of each remains part of
+/// the audio at every join. Only the first link of a chained stream is read.
+pub fn concat<I, R, W>(readers: I, writer: W) -> Result<W>
+where
+	I: IntoIterator<Item = R>,
+	R: Read,
+	W: Write,
+{
+	let mut readers = readers.into_iter().peekable();
+	let mut output = Remuxer::new(writer);
+	let mut head: Option<OpusHead> = None;
+	let mut repacketizer = Repacketizer::new()?;
+	let mut buffer = Vec::new();
+	// The output granule position at which the current stream starts.
+	let mut offset = 0;
+	while let Some(reader) = readers.next() {
+		let mut source = OggOpusReader::new(reader)?;
+		// The first stream's pre-skip is kept in the header.
+		let begin = match head {
+			None => {
+				output.pages.write_headers(&source.head, &source.tags)?;
+				head = Some(source.head.clone());
+				0
+			}
+			Some(ref head) => {
+				let other = &source.head;
+				if (other.channels, other.output_gain, other.mapping_family)
+					!= (head.channels, head.output_gain, head.mapping_family)
+					|| (other.streams, other.coupled_streams)
+						!= (head.streams, head.coupled_streams)
+					|| other.mapping != head.mapping
+					|| other.demixing_matrix != head.demixing_matrix
+				{
+					return Err(Error::Opus(super::Error::bad_arg("ogg::concat")));
+				}
+				other.pre_skip as i64
+			}
+		};
+
+		let last = readers.peek().is_none();
+		let split = source.head.streams == 1;
+		// The source position of the first sample copied.
+		let mut origin = None;
+		let mut granule = offset;
+		while let Some(packet) = source.next_raw_packet()? {
+			let stop = packet.end.unwrap_or(packet.start + packet.duration);
+			if packet.start >= stop {
+				break;
+			}
+			if packet.start + packet.duration <= begin {
+				continue;
+			}
+
+			let (data, first_sample, duration) = if split {
+				keep_frames(&mut repacketizer, &mut buffer, &packet, begin, stop)?
+			} else {
+				(&packet.data[..], packet.start, packet.duration)
+			};
+			let origin = *origin.get_or_insert(first_sample);
+			let start = offset + first_sample - origin;
+			granule = start + duration;
+			let limit = match packet.end {
+				Some(end) if last => std::cmp::min(granule, offset + end - origin),
+				_ => granule,
+			};
+			output.push(data, start, limit)?;
+		}
+		offset = granule;
+	}
+	if head.is_none() {
+		return Err(Error::Opus(super::Error::bad_arg("ogg::concat")));
+	}
+	output.finish()
+}
@@ -0,0 +1,349 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ambisonics coding with the projection API, which mixes the ambisonic
+//! channels into streams with a matrix chosen for efficient coding.
+//!
+//! The encoder produces a demixing matrix which the decoder needs to undo
+//! the mixing. In Ogg Opus this is channel mapping family 3, where the
+//! matrix is carried in the `OpusHead` in place of the channel mapping.
+//!
+//! See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__projection.html).
+
+use std::os::raw::c_int;
+
+use super::{
+	len, optional, Application, Bandwidth, Bitrate, Channels, EncoderConfig, Error, FrameSize,
+	LibraryFeature, Result, Signal,
+};
+
+// The bindings do not include opus_projection.h, so it is declared here. The
+// local `ffi` module lets the CTL macros find these alongside the rest.
+mod ffi {
+	pub use ffi::*;
+	use std::os::raw::{c_int, c_uchar};
+
+	#[allow(non_camel_case_types)]
+	pub enum OpusProjectionEncoder {}
+	#[allow(non_camel_case_types)]
+	pub enum OpusProjectionDecoder {}
+
+	pub const OPUS_PROJECTION_GET_DEMIXING_MATRIX_GAIN_REQUEST: c_int = 6001;
+	pub const OPUS_PROJECTION_GET_DEMIXING_MATRIX_SIZE_REQUEST: c_int = 6003;
+	pub const OPUS_PROJECTION_GET_DEMIXING_MATRIX_REQUEST: c_int = 6005;
+
+	extern "C" {
+		pub fn opus_projection_ambisonics_encoder_create(
+			Fs: i32,
+			channels: c_int,
+			mapping_family: c_int,
+			streams: *mut c_int,
+			coupled_streams: *mut c_int,
+			application: c_int,
+			error: *mut c_int,
+		) -> *mut OpusProjectionEncoder;
+		pub fn opus_projection_encoder_destroy(st: *mut OpusProjectionEncoder);
+		pub fn opus_projection_encoder_ctl(
+			st: *mut OpusProjectionEncoder,
+			request: c_int,
+			...
+		) -> c_int;
+		pub fn opus_projection_encode(
+			st: *mut OpusProjectionEncoder,
+			pcm: *const i16,
+			frame_size: c_int,
+			data: *mut c_uchar,
+			max_data_bytes: i32,
+		) -> c_int;
+		pub fn opus_projection_encode_float(
+			st: *mut OpusProjectionEncoder,
+			pcm: *const f32,
+			frame_size: c_int,
+			data: *mut c_uchar,
+			max_data_bytes: i32,
+		) -> c_int;
+
+		pub fn opus_projection_decoder_create(
+			Fs: i32,
+			channels: c_int,
+			streams: c_int,
+			coupled_streams: c_int,
+			demixing_matrix: *mut c_uchar,
+			demixing_matrix_size: i32,
+			error: *mut c_int,
+		) -> *mut OpusProjectionDecoder;
+		pub fn opus_projection_decoder_destroy(st: *mut OpusProjectionDecoder);
+		pub fn opus_projection_decoder_ctl(
+			st: *mut OpusProjectionDecoder,
+			request: c_int,
+			...
+		) -> c_int;
+		pub fn opus_projection_decode(
+			st: *mut OpusProjectionDecoder,
+			data: *const c_uchar,
+			len: i32,
+			pcm: *mut i16,
+			frame_size: c_int,
+			decode_fec: c_int,
+		) -> c_int;
+		pub fn opus_projection_decode_float(
+			st: *mut OpusProjectionDecoder,
+			data: *const c_uchar,
+			len: i32,
+			pcm: *mut f32,
+			frame_size: c_int,
+			decode_fec: c_int,
+		) -> c_int;
+	}
+}
+
+// ============================================================================
+// Projection Encoder
+
+/// Encode ambisonics into Opus streams through a mixing matrix.
+#[derive(Debug)]
+pub struct ProjectionEncoder {
+	ptr: *mut ffi::OpusProjectionEncoder,
+	channels: c_int,
+	streams: u8,
+	coupled_streams: u8,
+}
+
+impl Drop for ProjectionEncoder {
+	fn drop(&mut self) {
+		unsafe { ffi::opus_projection_encoder_destroy(self.ptr) }
+	}
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl Send for ProjectionEncoder {}
+
+impl ProjectionEncoder {
+	/// Create and initialize a projection encoder for ambisonics.
+	///
+	/// `channels` must be `(order + 1)^2`, optionally plus two for a
+	/// non-diegetic stereo track. The only mapping family supported by
+	/// libopus is 3.
+	pub fn new(
+		sample_rate: u32,
+		channels: u8,
+		mapping_family: u8,
+		application: Application,
+	) -> Result<ProjectionEncoder> {
+		let mut error = 0;
+		let mut streams = 0;
+		let mut coupled_streams = 0;
+		let ptr = unsafe {
+			ffi::opus_projection_ambisonics_encoder_create(
+				sample_rate as i32,
+				channels as c_int,
+				mapping_family as c_int,
+				&mut streams,
+				&mut coupled_streams,
+				application as c_int,
+				&mut error,
+			)
+		};
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_projection_ambisonics_encoder_create", error))
+		} else {
+			Ok(ProjectionEncoder {
+				ptr,
+				channels: channels as c_int,
+				streams: streams as u8,
+				coupled_streams: coupled_streams as u8,
+			})
+		}
+	}
+
+	/// Get the total number of streams chosen by the encoder.
+	pub fn streams(&self) -> u8 {
+		self.streams
+	}
+
+	/// Get the number of those streams which are coupled stereo pairs.
+	pub fn coupled_streams(&self) -> u8 {
+		self.coupled_streams
+	}
+
+	/// Encode an Opus frame.
+	pub fn encode(&mut self, input: &[i16], output: &mut [u8]) -> Result<usize> {
+		let len = ffi!(
+			opus_projection_encode,
+			self.ptr,
+			input.as_ptr(),
+			len(input) / self.channels,
+			output.as_mut_ptr(),
+			len(output)
+		);
+		Ok(len as usize)
+	}
+
+	/// Encode an Opus frame from floating point input.
+	pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize> {
+		let len = ffi!(
+			opus_projection_encode_float,
+			self.ptr,
+			input.as_ptr(),
+			len(input) / self.channels,
+			output.as_mut_ptr(),
+			len(output)
+		);
+		Ok(len as usize)
+	}
+
+	/// Encode an Opus frame to a new buffer.
+	pub fn encode_vec(&mut self, input: &[i16], max_size: usize) -> Result<Vec<u8>> {
+		let mut output: Vec<u8> = vec![0; max_size];
+		let result = self.encode(input, output.as_mut_slice())?;
+		output.truncate(result);
+		Ok(output)
+	}
+
+	/// Encode an Opus frame from floating point input to a new buffer.
+	pub fn encode_vec_float(&mut self, input: &[f32], max_size: usize) -> Result<Vec<u8>> {
+		let mut output: Vec<u8> = vec![0; max_size];
+		let result = self.encode_float(input, output.as_mut_slice())?;
+		output.truncate(result);
+		Ok(output)
+	}
+
+	/// Get the gain of the demixing matrix, in Q7.8 dB.
+	pub fn get_demixing_matrix_gain(&mut self) -> Result<i32> {
+		let mut value: i32 = 0;
+		ctl!(
+			opus_projection_encoder_ctl,
+			self,
+			ffi::OPUS_PROJECTION_GET_DEMIXING_MATRIX_GAIN_REQUEST,
+			&mut value
+		);
+		Ok(value)
+	}
+
+	/// Get the demixing matrix the decoder needs, as little-endian 16-bit
+	/// coefficients.
+	///
+	/// This is the form it takes in an Ogg Opus header.
+	pub fn get_demixing_matrix(&mut self) -> Result<Vec<u8>> {
+		let mut size: i32 = 0;
+		ctl!(
+			opus_projection_encoder_ctl,
+			self,
+			ffi::OPUS_PROJECTION_GET_DEMIXING_MATRIX_SIZE_REQUEST,
+			&mut size
+		);
+		let mut matrix = vec![0u8; size as usize];
+		ctl!(
+			opus_projection_encoder_ctl,
+			self,
+			ffi::OPUS_PROJECTION_GET_DEMIXING_MATRIX_REQUEST,
+			matrix.as_mut_ptr(),
+			size
+		);
+		Ok(matrix)
+	}
+}
+
+generic_ctls!(ProjectionEncoder, opus_projection_encoder_ctl);
+encoder_ctls!(ProjectionEncoder, opus_projection_encoder_ctl);
+
+// ============================================================================
+// Projection Decoder
+
+/// Decode Opus streams back into ambisonics through a demixing matrix.
+#[derive(Debug)]
+pub struct ProjectionDecoder {
+	ptr: *mut ffi::OpusProjectionDecoder,
+	channels: c_int,
+}
+
+impl Drop for ProjectionDecoder {
+	fn drop(&mut self) {
+		unsafe { ffi::opus_projection_decoder_destroy(self.ptr) }
+	}
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl Send for ProjectionDecoder {}
+
+impl ProjectionDecoder {
+	/// Create and initialize a projection decoder.
+	///
+	/// The demixing matrix is as returned by
+	/// `ProjectionEncoder::get_demixing_matrix`.
+	pub fn new(
+		sample_rate: u32,
+		channels: u8,
+		streams: u8,
+		coupled_streams: u8,
+		demixing_matrix: &[u8],
+	) -> Result<ProjectionDecoder> {
+		let mut error = 0;
+		// libopus copies the matrix and does not modify it, despite the
+		// pointer not being const.
+		let ptr = unsafe {
+			ffi::opus_projection_decoder_create(
+				sample_rate as i32,
+				channels as c_int,
+				streams as c_int,
+				coupled_streams as c_int,
+				demixing_matrix.as_ptr() as *mut u8,
+				len(demixing_matrix),
+				&mut error,
+			)
+		};
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_projection_decoder_create", error))
+		} else {
+			Ok(ProjectionDecoder { ptr, channels: channels as c_int })
+		}
+	}
+
+	/// Decode an Opus packet.
+	///
+	/// To represent packet loss, pass an empty slice `&[]`.
+	pub fn decode(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
+		let ptr = match input.len() {
+			0 => std::ptr::null(),
+			_ => input.as_ptr(),
+		};
+		let len = ffi!(
+			opus_projection_decode,
+			self.ptr,
+			ptr,
+			len(input),
+			output.as_mut_ptr(),
+			len(output) / self.channels,
+			fec as c_int
+		);
+		Ok(len as usize)
+	}
+
+	/// Decode an Opus packet with floating point output.
+	///
+	/// To represent packet loss, pass an empty slice `&[]`.
+	pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize> {
+		let ptr = match input.len() {
+			0 => std::ptr::null(),
+			_ => input.as_ptr(),
+		};
+		let len = ffi!(
+			opus_projection_decode_float,
+			self.ptr,
+			ptr,
+			len(input),
+			output.as_mut_ptr(),
+			len(output) / self.channels,
+			fec as c_int
+		);
+		Ok(len as usize)
+	}
+}
+
+generic_ctls!(ProjectionDecoder, opus_projection_decoder_ctl);
+decoder_ctls!(ProjectionDecoder, opus_projection_decoder_ctl);
@@ -0,0 +1,327 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The RTP payload format for Opus, as described by RFC 7587.
+//!
+//! A `Payloader` wraps encoded packets into RTP packets for sending, and a
+//! `Depayloader` unwraps received RTP packets and decodes them, concealing
+//! any packets which were lost on the way.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use super::{packet, Decoder, Error, Result};
+
+/// The RTP clock rate for Opus, which is used whatever the sample rate.
+pub const CLOCK_RATE: u32 = 48000;
+
+const VERSION: u8 = 2;
+const HEADER_LEN: usize = 12;
+
+/// Packets this short carry no audio; the encoder produces them during DTX.
+const DTX_LEN: usize = 2;
+
+/// Timestamp gaps longer than this are treated as a discontinuity rather
+/// than concealed.
+const MAX_CONCEAL: u32 = CLOCK_RATE;
+
+// ============================================================================
+// Header
+
+/// The fixed part of an RTP packet header.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Header {
+	/// Set on the first packet of a talkspurt.
+	pub marker: bool,
+	/// The dynamic payload type negotiated for Opus.
+	pub payload_type: u8,
+	/// The sequence number, incremented by one for each packet sent.
+	pub sequence: u16,
+	/// The sampling instant of the first sample, in 48 kHz units.
+	pub timestamp: u32,
+	/// The synchronization source identifier.
+	pub ssrc: u32,
+}
+
+impl Header {
+	/// Parse an RTP packet into its header and payload.
+	///
+	/// Contributing sources, header extensions and padding are skipped.
+	pub fn parse(data: &[u8]) -> Result<(Header, &[u8])> {
+		const WHAT: &str = "rtp::Header::parse";
+		if data.len() < HEADER_LEN || data[0] >> 6 != VERSION {
+			return Err(Error::invalid_packet(WHAT));
+		}
+		let header = Header {
+			marker: data[1] & 0x80 != 0,
+			payload_type: data[1] & 0x7f,
+			sequence: u16::from_be_bytes([data[2], data[3]]),
+			timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+			ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+		};
+
+		let mut start = HEADER_LEN + 4 * (data[0] & 0x0f) as usize;
+		if data[0] & 0x10 != 0 {
+			if data.len() < start + 4 {
+				return Err(Error::invalid_packet(WHAT));
+			}
+			start += 4 + 4 * u16::from_be_bytes([data[start + 2], data[start + 3]]) as usize;
+		}
+		let mut end = data.len();
+		if data[0] & 0x20 != 0 {
+			end = end.saturating_sub(data[end - 1] as usize);
+		}
+		if start > end {
+			return Err(Error::invalid_packet(WHAT));
+		}
+		Ok((header, &data[start..end]))
+	}
+
+	/// Append the header to a buffer, with no contributing sources or
+	/// extension.
+	pub fn write_to(&self, out: &mut Vec<u8>) {
+		out.push(VERSION << 6);
+		out.push(if self.marker { 0x80 } else { 0 } | (self.payload_type & 0x7f));
+		out.extend_from_slice(&self.sequence.to_be_bytes());
+		out.extend_from_slice(&self.timestamp.to_be_bytes());
+		out.extend_from_slice(&self.ssrc.to_be_bytes());
+	}
+}
+
+// ============================================================================
+// Payloader
+
+/// Wraps encoded Opus packets into RTP packets.
+#[derive(Debug)]
+pub struct Payloader {
+	payload_type: u8,
+	ssrc: u32,
+	sequence: u16,
+	timestamp: u32,
+	talkspurt: bool,
+}
+
+impl Payloader {
+	/// Create a payloader with a random initial sequence number and
+	/// timestamp, as RFC 3550 recommends.
+	pub fn new(payload_type: u8, ssrc: u32) -> Result<Payloader> {
+		if payload_type > 127 {
+			return Err(Error::bad_arg("Payloader::new"));
+		}
+		let random = RandomState::new().build_hasher().finish();
+		Ok(Payloader {
+			payload_type,
+			ssrc,
+			sequence: random as u16,
+			timestamp: (random >> 32) as u32,
+			talkspurt: true,
+		})
+	}
+
+	/// Get the synchronization source identifier.
+	pub fn ssrc(&self) -> u32 {
+		self.ssrc
+	}
+
+	/// Get the sequence number the next packet will have.
+	pub fn sequence(&self) -> u16 {
+		self.sequence
+	}
+
+	/// Set the sequence number the next packet will have.
+	pub fn set_sequence(&mut self, value: u16) {
+		self.sequence = value;
+	}
+
+	/// Get the timestamp the next packet will have.
+	pub fn timestamp(&self) -> u32 {
+		self.timestamp
+	}
+
+	/// Set the timestamp the next packet will have.
+	pub fn set_timestamp(&mut self, value: u32) {
+		self.timestamp = value;
+	}
+
+	/// Wrap an encoded packet into an RTP packet.
+	///
+	/// DTX packets of two bytes or fewer are not sent: they only advance the
+	/// timestamp and return `None`. The next packet which is sent has the
+	/// marker bit set as the start of a new talkspurt.
+	pub fn payload(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>> {
+		let duration = packet::get_nb_samples(packet, CLOCK_RATE)? as u32;
+		let timestamp = self.timestamp;
+		self.timestamp = self.timestamp.wrapping_add(duration);
+		if packet.len() <= DTX_LEN {
+			self.talkspurt = true;
+			return Ok(None);
+		}
+
+		let header = Header {
+			marker: self.talkspurt,
+			payload_type: self.payload_type,
+			sequence: self.sequence,
+			timestamp,
+			ssrc: self.ssrc,
+		};
+		self.sequence = self.sequence.wrapping_add(1);
+		self.talkspurt = false;
+
+		let mut out = Vec::with_capacity(HEADER_LEN + packet.len());
+		header.write_to(&mut out);
+		out.extend_from_slice(packet);
+		Ok(Some(out))
+	}
+}
+
+// ============================================================================
+// Depayloader
+
+/// A sample type the decoder can produce.
+trait Sample: Copy + Default {
+	fn decode(decoder: &mut Decoder, input: &[u8], output: &mut [Self], fec: bool)
+		-> Result<usize>;
+}
+
+impl Sample for i16 {
+	fn decode(decoder: &mut Decoder, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
+		decoder.decode(input, output, fec)
+	}
+}
+
+impl Sample for f32 {
+	fn decode(decoder: &mut Decoder, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize> {
+		decoder.decode_float(input, output, fec)
+	}
+}
+
+/// Unwraps and decodes received RTP packets, concealing lost packets.
+///
+/// Packets must be pushed in the order they arrive. Packets which are lost
+/// are concealed when the next one arrives, using its forward error
+/// correction data if it has any. Packets which arrive after a later one
+/// has already been decoded are dropped.
+#[derive(Debug)]
+pub struct Depayloader {
+	decoder: Decoder,
+	sample_rate: u32,
+	ssrc: Option<u32>,
+	/// The expected sequence number and timestamp of the next packet.
+	next: Option<(u16, u32)>,
+	lost: u64,
+	late: u64,
+}
+
+impl Depayloader {
+	/// Create a depayloader which decodes with the given decoder.
+	pub fn new(mut decoder: Decoder) -> Result<Depayloader> {
+		let sample_rate = decoder.get_sample_rate()?;
+		Ok(Depayloader {
+			decoder,
+			sample_rate,
+			ssrc: None,
+			next: None,
+			lost: 0,
+			late: 0,
+		})
+	}
+
+	/// Get the underlying decoder.
+	pub fn decoder_mut(&mut self) -> &mut Decoder {
+		&mut self.decoder
+	}
+
+	/// Consume the depayloader, returning the decoder.
+	pub fn into_decoder(self) -> Decoder {
+		self.decoder
+	}
+
+	/// Get the number of packets found to be missing so far.
+	pub fn packets_lost(&self) -> u64 {
+		self.lost
+	}
+
+	/// Get the number of packets dropped for arriving out of order.
+	pub fn packets_late(&self) -> u64 {
+		self.late
+	}
+
+	/// Decode an RTP packet, returning the audio it completes.
+	///
+	/// The output includes concealment for any lost packets or DTX gaps
+	/// before this one, and is empty if the packet was dropped.
+	pub fn push(&mut self, data: &[u8]) -> Result<Vec<i16>> {
+		self.push_generic(data)
+	}
+
+	/// Decode an RTP packet with floating point output.
+	pub fn push_float(&mut self, data: &[u8]) -> Result<Vec<f32>> {
+		self.push_generic(data)
+	}
+
+	fn push_generic<T: Sample>(&mut self, data: &[u8]) -> Result<Vec<T>> {
+		let (header, payload) = Header::parse(data)?;
+		let duration = packet::get_nb_samples(payload, CLOCK_RATE)? as u32;
+		if self.ssrc != Some(header.ssrc) {
+			// A new source is a new stream.
+			if self.ssrc.is_some() {
+				self.decoder.reset_state()?;
+			}
+			self.ssrc = Some(header.ssrc);
+			self.next = None;
+		}
+
+		let channels = self.decoder.channels as usize;
+		let mut output = Vec::new();
+		let mut fill = 0;
+		if let Some((sequence, timestamp)) = self.next {
+			let ahead = header.sequence.wrapping_sub(sequence) as i16;
+			if ahead < 0 {
+				self.late += 1;
+				return Ok(output);
+			}
+			self.lost += ahead as u64;
+
+			let gap = header.timestamp.wrapping_sub(timestamp);
+			if gap > 0 && gap <= MAX_CONCEAL {
+				// Concealment must come in multiples of 2.5 ms.
+				let quantum = self.sample_rate as usize / 400;
+				let gap = self.to_rate(gap) / quantum * quantum;
+				// The audio just before this packet can be recovered from its
+				// forward error correction data.
+				let fec = if ahead > 0 { std::cmp::min(gap, self.to_rate(duration)) } else { 0 };
+				output.resize((gap + self.to_rate(duration)) * channels, T::default());
+				if gap > fec {
+					T::decode(
+						&mut self.decoder,
+						&[],
+						&mut output[..(gap - fec) * channels],
+						false,
+					)?;
+				}
+				if fec > 0 {
+					let range = (gap - fec) * channels..gap * channels;
+					T::decode(&mut self.decoder, payload, &mut output[range], true)?;
+				}
+				fill = gap;
+			}
+		}
+
+		output.resize((fill + self.to_rate(duration)) * channels, T::default());
+		let decoded = T::decode(&mut self.decoder, payload, &mut output[fill * channels..], false)?;
+		output.truncate((fill + decoded) * channels);
+		self.next =
+			Some((header.sequence.wrapping_add(1), header.timestamp.wrapping_add(duration)));
+		Ok(output)
+	}
+
+	/// Convert a duration in RTP clock units to the decoder's sample rate.
+	fn to_rate(&self, duration: u32) -> usize {
+		(duration as u64 * self.sample_rate as u64 / CLOCK_RATE as u64) as usize
+	}
+}
@@ -0,0 +1,491 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading and writing WAV files, for feeding an `Encoder` and saving what
+//! a `Decoder` produces.
+//!
+//! Samples are read and written as interleaved `i16` or `f32`, the shapes
+//! `encode` and `encode_float` take and `decode` and `decode_float`
+//! return, whatever the sample format of the file. 16, 24 and 32-bit
+//! integer and 32-bit float files are supported.
+//!
+//! This module is only available with the `wav` feature.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub use super::container::{Error, Result};
+
+use super::{Application, Channels, Encoder};
+
+/// The sample rates Opus can encode and decode at.
+const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+// Format tags.
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// A data chunk size meaning the size is unknown, as written by some
+/// streaming tools.
+const UNKNOWN_SIZE: u32 = 0xFFFF_FFFF;
+
+/// The largest fmt chunk accepted, leaving room beyond the 40 bytes of the
+/// extensible format.
+const MAX_FMT_SIZE: u32 = 64;
+
+/// The offsets of the sizes to fill in once a file is complete, from the
+/// start of the header.
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+
+// ============================================================================
+// Format
+
+/// The format of the samples in a WAV file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SampleFormat {
+	/// Signed 16-bit integers.
+	Pcm16,
+	/// Signed 24-bit integers.
+	Pcm24,
+	/// Signed 32-bit integers.
+	Pcm32,
+	/// 32-bit floating point, nominally from -1 to 1.
+	Float32,
+}
+
+impl SampleFormat {
+	/// Get the size of one sample in bytes.
+	pub fn sample_size(self) -> usize {
+		match self {
+			SampleFormat::Pcm16 => 2,
+			SampleFormat::Pcm24 => 3,
+			SampleFormat::Pcm32 | SampleFormat::Float32 => 4,
+		}
+	}
+
+	fn from_tag(tag: u16, bits: u16) -> Result<SampleFormat> {
+		match (tag, bits) {
+			(WAVE_FORMAT_PCM, 16) => Ok(SampleFormat::Pcm16),
+			(WAVE_FORMAT_PCM, 24) => Ok(SampleFormat::Pcm24),
+			(WAVE_FORMAT_PCM, 32) => Ok(SampleFormat::Pcm32),
+			(WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(SampleFormat::Float32),
+			_ => Err(Error::Unsupported("sample format is not supported")),
+		}
+	}
+
+	fn tag(self) -> u16 {
+		match self {
+			SampleFormat::Float32 => WAVE_FORMAT_IEEE_FLOAT,
+			_ => WAVE_FORMAT_PCM,
+		}
+	}
+
+	fn read_i16(self, data: &[u8]) -> i16 {
+		match self {
+			SampleFormat::Pcm16 => i16::from_le_bytes([data[0], data[1]]),
+			SampleFormat::Pcm24 => i16::from_le_bytes([data[1], data[2]]),
+			SampleFormat::Pcm32 => i16::from_le_bytes([data[2], data[3]]),
+			SampleFormat::Float32 => float_to_i16(self.read_f32(data)),
+		}
+	}
+
+	fn read_f32(self, data: &[u8]) -> f32 {
+		match self {
+			SampleFormat::Pcm16 => i16::from_le_bytes([data[0], data[1]]) as f32 / 32768.0,
+			SampleFormat::Pcm24 => {
+				// Sign-extend from the top of a 32-bit integer.
+				let value = i32::from_le_bytes([0, data[0], data[1], data[2]]);
+				(value >> 8) as f32 / 8_388_608.0
+			}
+			SampleFormat::Pcm32 => {
+				i32::from_le_bytes([data[0], data[1], data[2], data[3]]) as f32 / 2_147_483_648.0
+			}
+			SampleFormat::Float32 => f32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+		}
+	}
+
+	fn write_i16(self, out: &mut Vec<u8>, sample: i16) {
+		match self {
+			SampleFormat::Pcm16 => out.extend_from_slice(&sample.to_le_bytes()),
+			SampleFormat::Pcm24 => {
+				out.extend_from_slice(&((sample as i32) << 8).to_le_bytes()[..3])
+			}
+			SampleFormat::Pcm32 => out.extend_from_slice(&((sample as i32) << 16).to_le_bytes()),
+			SampleFormat::Float32 => {
+				out.extend_from_slice(&(sample as f32 / 32768.0).to_le_bytes())
+			}
+		}
+	}
+
+	fn write_f32(self, out: &mut Vec<u8>, sample: f32) {
+		match self {
+			SampleFormat::Pcm16 => out.extend_from_slice(&float_to_i16(sample).to_le_bytes()),
+			SampleFormat::Pcm24 => {
+				let value = (sample * 8_388_608.0).round().clamp(-8_388_608.0, 8_388_607.0) as i32;
+				out.extend_from_slice(&value.to_le_bytes()[..3]);
+			}
+			SampleFormat::Pcm32 => {
+				let value = (sample as f64 * 2_147_483_648.0).round();
+				let value = value.clamp(-2_147_483_648.0, 2_147_483_647.0) as i32;
+				out.extend_from_slice(&value.to_le_bytes());
+			}
+			SampleFormat::Float32 => out.extend_from_slice(&sample.to_le_bytes()),
+		}
+	}
+}
+
+fn float_to_i16(sample: f32) -> i16 {
+	(sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+}
+
+/// The layout of the audio in a WAV file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct WavSpec {
+	/// The number of interleaved channels.
+	pub channels: u16,
+	/// The number of samples per second, per channel.
+	pub sample_rate: u32,
+	/// The format of each sample.
+	pub format: SampleFormat,
+}
+
+impl WavSpec {
+	/// Create a spec for audio in the given channel setting.
+	pub fn new(channels: Channels, sample_rate: u32, format: SampleFormat) -> WavSpec {
+		WavSpec {
+			channels: channels as u16,
+			sample_rate,
+			format,
+		}
+	}
+
+	/// Check that Opus can encode audio of this layout as it is, returning
+	/// its channel setting.
+	///
+	/// The sample rate must be one of 8, 12, 16, 24 or 48 kHz, and there
+	/// must be one or two channels.
+	pub fn check_opus(&self) -> Result<Channels> {
+		if !OPUS_SAMPLE_RATES.contains(&self.sample_rate) {
+			return Err(Error::Unsupported("sample rate is not supported by Opus"));
+		}
+		match self.channels {
+			1 => Ok(Channels::Mono),
+			2 => Ok(Channels::Stereo),
+			_ => Err(Error::Unsupported("channel count is not supported by Opus")),
+		}
+	}
+
+	fn block_align(&self) -> usize {
+		self.channels as usize * self.format.sample_size()
+	}
+}
+
+// ============================================================================
+// Reader
+
+/// Reads the samples of a WAV file.
+#[derive(Debug)]
+pub struct WavReader<R> {
+	inner: R,
+	spec: WavSpec,
+	/// The bytes of sample data left, if known.
+	remaining: Option<u64>,
+	/// The number of samples per channel in the file, if known.
+	duration: Option<u64>,
+}
+
+impl<R: Read> WavReader<R> {
+	/// Read the file's headers, up to the start of its sample data.
+	pub fn new(mut reader: R) -> Result<WavReader<R>> {
+		let mut header = [0; 12];
+		read_exact(&mut reader, &mut header)?;
+		if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+			return Err(Error::Malformed("not a RIFF WAVE file"));
+		}
+
+		let mut spec = None;
+		loop {
+			let mut chunk = [0; 8];
+			read_exact(&mut reader, &mut chunk)?;
+			let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+			match &chunk[..4] {
+				b"fmt " => {
+					if size > MAX_FMT_SIZE {
+						return Err(Error::Malformed("fmt chunk is too long"));
+					}
+					let mut body = vec![0; size as usize];
+					read_exact(&mut reader, &mut body)?;
+					spec = Some(read_fmt(&body)?);
+				}
+				b"data" => {
+					let spec = spec.ok_or(Error::Malformed("data chunk before fmt chunk"))?;
+					let remaining = match size {
+						UNKNOWN_SIZE => None,
+						size => Some(size as u64),
+					};
+					return Ok(WavReader {
+						inner: reader,
+						spec,
+						remaining,
+						duration: remaining.map(|size| size / spec.block_align() as u64),
+					});
+				}
+				_ => {
+					let size = size as u64;
+					if io::copy(&mut (&mut reader).take(size), &mut io::sink())? < size {
+						return Err(Error::Malformed("no data chunk found"));
+					}
+				}
+			}
+			// Chunks are padded to an even length.
+			if size % 2 == 1 {
+				read_exact(&mut reader, &mut [0])?;
+			}
+		}
+	}
+
+	/// Get the layout of the audio.
+	pub fn spec(&self) -> WavSpec {
+		self.spec
+	}
+
+	/// Get the number of samples per channel in the file, if its header
+	/// gives it.
+	pub fn duration(&self) -> Option<u64> {
+		self.duration
+	}
+
+	/// Create an encoder for the audio, after checking that Opus supports
+	/// its layout.
+	pub fn encoder(&self, application: Application) -> Result<Encoder> {
+		let channels = self.spec.check_opus()?;
+		Ok(Encoder::new(self.spec.sample_rate, channels, application)?)
+	}
+
+	/// Read interleaved samples, converting them if need be.
+	///
+	/// Only whole sample frames are read, so `buf` should hold a multiple
+	/// of the channel count. Returns the number of samples read, which is
+	/// less than `buf` holds only at the end of the data.
+	pub fn read(&mut self, buf: &mut [i16]) -> Result<usize> {
+		let format = self.spec.format;
+		let data = self.read_frames(buf.len())?;
+		for (sample, out) in data.chunks(format.sample_size()).zip(buf.iter_mut()) {
+			*out = format.read_i16(sample);
+		}
+		Ok(data.len() / format.sample_size())
+	}
+
+	/// Read interleaved floating point samples, converting them if need
+	/// be.
+	///
+	/// As with [`read`](#method.read), only whole sample frames are read.
+	pub fn read_float(&mut self, buf: &mut [f32]) -> Result<usize> {
+		let format = self.spec.format;
+		let data = self.read_frames(buf.len())?;
+		for (sample, out) in data.chunks(format.sample_size()).zip(buf.iter_mut()) {
+			*out = format.read_f32(sample);
+		}
+		Ok(data.len() / format.sample_size())
+	}
+
+	/// Read all the remaining samples.
+	pub fn read_all(&mut self) -> Result<Vec<i16>> {
+		let format = self.spec.format;
+		let data = self.read_frames(usize::MAX)?;
+		Ok(data.chunks(format.sample_size()).map(|s| format.read_i16(s)).collect())
+	}
+
+	/// Read all the remaining samples as floating point.
+	pub fn read_all_float(&mut self) -> Result<Vec<f32>> {
+		let format = self.spec.format;
+		let data = self.read_frames(usize::MAX)?;
+		Ok(data.chunks(format.sample_size()).map(|s| format.read_f32(s)).collect())
+	}
+
+	/// Consume the reader, returning the underlying reader.
+	pub fn into_inner(self) -> R {
+		self.inner
+	}
+
+	/// Read the bytes of as many whole sample frames as fit in `samples`.
+	///
+	/// A file cut off partway through its data ends at the last whole
+	/// frame.
+	fn read_frames(&mut self, samples: usize) -> Result<Vec<u8>> {
+		let block_align = self.spec.block_align() as u64;
+		let frames = samples as u64 / self.spec.channels as u64;
+		let mut want = frames.saturating_mul(block_align);
+		if let Some(remaining) = self.remaining {
+			want = std::cmp::min(want, remaining);
+		}
+		let mut data = Vec::new();
+		(&mut self.inner).take(want).read_to_end(&mut data)?;
+		if let Some(ref mut remaining) = self.remaining {
+			*remaining -= data.len() as u64;
+			if (data.len() as u64) < want {
+				*remaining = 0;
+			}
+		}
+		data.truncate((data.len() as u64 / block_align * block_align) as usize);
+		Ok(data)
+	}
+}
+
+impl<R: Read + Seek> WavReader<R> {
+	/// Seek to the given sample frame.
+	///
+	/// This is only possible when the header gives the length of the data.
+	pub fn seek(&mut self, frame: u64) -> Result<()> {
+		let duration = self.duration.ok_or(Error::Unsupported("data has an unknown length"))?;
+		let block_align = self.spec.block_align() as u64;
+		let frame = std::cmp::min(frame, duration);
+		let position = (duration - self.remaining.unwrap_or(0) / block_align) as i64;
+		self.inner.seek(SeekFrom::Current((frame as i64 - position) * block_align as i64))?;
+		self.remaining = Some((duration - frame) * block_align);
+		Ok(())
+	}
+}
+
+/// Read a `fmt ` chunk.
+fn read_fmt(body: &[u8]) -> Result<WavSpec> {
+	if body.len() < 16 {
+		return Err(Error::Malformed("fmt chunk is too short"));
+	}
+	let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+	let mut tag = u16_at(0);
+	let channels = u16_at(2);
+	let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+	let block_align = u16_at(12);
+	let bits = u16_at(14);
+	if tag == WAVE_FORMAT_EXTENSIBLE {
+		if body.len() < 40 {
+			return Err(Error::Malformed("fmt chunk is too short"));
+		}
+		// The format tag leads the sub-format GUID.
+		tag = u16_at(24);
+	}
+	let format = SampleFormat::from_tag(tag, bits)?;
+	if channels == 0 || block_align as usize != channels as usize * format.sample_size() {
+		return Err(Error::Malformed("invalid block alignment"));
+	}
+	Ok(WavSpec { channels, sample_rate, format })
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+	match reader.read_exact(buf) {
+		Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+			Err(Error::Malformed("truncated header"))
+		}
+		result => Ok(result?),
+	}
+}
+
+// ============================================================================
+// Writer
+
+/// Writes samples to a WAV file.
+///
+/// The sizes in the header are filled in by [`finish`](#method.finish),
+/// so the writer must be seekable. The header is the plain kind, without
+/// the extensible format.
+#[derive(Debug)]
+pub struct WavWriter<W> {
+	inner: W,
+	spec: WavSpec,
+	/// The position of the header in the writer.
+	start: u64,
+	/// The bytes of sample data written.
+	written: u64,
+	buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+	/// Create a writer, writing the header.
+	pub fn new(mut writer: W, spec: WavSpec) -> Result<WavWriter<W>> {
+		if spec.channels == 0 {
+			return Err(Error::Unsupported("channel count is not supported"));
+		}
+		let block_align = spec.block_align() as u32;
+		let mut header = Vec::with_capacity(44);
+		header.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+		header.extend_from_slice(&16u32.to_le_bytes());
+		header.extend_from_slice(&spec.format.tag().to_le_bytes());
+		header.extend_from_slice(&spec.channels.to_le_bytes());
+		header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+		header.extend_from_slice(&(spec.sample_rate * block_align).to_le_bytes());
+		header.extend_from_slice(&(block_align as u16).to_le_bytes());
+		header.extend_from_slice(&(spec.format.sample_size() as u16 * 8).to_le_bytes());
+		header.extend_from_slice(b"data\0\0\0\0");
+		let start = writer.stream_position()?;
+		writer.write_all(&header)?;
+		Ok(WavWriter {
+			inner: writer,
+			spec,
+			start,
+			written: 0,
+			buffer: Vec::new(),
+		})
+	}
+
+	/// Get the layout of the audio.
+	pub fn spec(&self) -> WavSpec {
+		self.spec
+	}
+
+	/// Write interleaved samples, converting them if need be.
+	pub fn write(&mut self, pcm: &[i16]) -> Result<()> {
+		let format = self.spec.format;
+		self.buffer.clear();
+		for &sample in pcm {
+			format.write_i16(&mut self.buffer, sample);
+		}
+		self.flush_buffer()
+	}
+
+	/// Write interleaved floating point samples, converting them if need
+	/// be.
+	pub fn write_float(&mut self, pcm: &[f32]) -> Result<()> {
+		let format = self.spec.format;
+		self.buffer.clear();
+		for &sample in pcm {
+			format.write_f32(&mut self.buffer, sample);
+		}
+		self.flush_buffer()
+	}
+
+	/// Fill in the sizes in the header and complete the file.
+	pub fn finish(mut self) -> Result<W> {
+		if self.written % 2 == 1 {
+			self.inner.write_all(&[0])?;
+		}
+		let riff_size = 36 + self.written + self.written % 2;
+		if riff_size > u32::MAX as u64 {
+			return Err(Error::Unsupported("data is too long for a WAV file"));
+		}
+		self.inner.seek(SeekFrom::Start(self.start + RIFF_SIZE_OFFSET))?;
+		self.inner.write_all(&(riff_size as u32).to_le_bytes())?;
+		self.inner.seek(SeekFrom::Start(self.start + DATA_SIZE_OFFSET))?;
+		self.inner.write_all(&(self.written as u32).to_le_bytes())?;
+		self.inner.seek(SeekFrom::Start(self.start + 8 + riff_size))?;
+		self.inner.flush()?;
+		Ok(self.inner)
+	}
+
+	fn flush_buffer(&mut self) -> Result<()> {
+		let written = self.written + self.buffer.len() as u64;
+		// Leave room for the rest of the header in the RIFF size.
+		if written + 37 > u32::MAX as u64 {
+			return Err(Error::Unsupported("data is too long for a WAV file"));
+		}
+		self.inner.write_all(&self.buffer)?;
+		self.written = written;
+		Ok(())
+	}
+}
@@ -0,0 +1,792 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading and writing Opus in WebM and Matroska.
+//!
+//! The track's `CodecPrivate` holds the same identification header as Ogg
+//! Opus. Its `CodecDelay` gives the pre-skip in nanoseconds, and the final
+//! block carries the end trimming as `DiscardPadding`. Segments and
+//! clusters of unknown size, as written by browsers' `MediaRecorder`, are
+//! supported.
+//!
+//! This module is only available with the `webm` feature.
+//!
+//! See the [Matroska Opus mapping](https://www.matroska.org/technical/codec_specs.html).
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+pub use super::container::{Error, Result};
+
+use super::container::Trim;
+use super::ogg::{MappedDecoder, OpusHead};
+use super::{Encoder, FrameSize, StreamEncoder, StreamPacket};
+
+/// Opus always decodes at 48 kHz.
+const SAMPLE_RATE: u64 = 48000;
+
+/// The largest possible Opus packet duration: 120 ms at 48 kHz.
+const MAX_FRAME_SIZE: usize = 5760;
+
+const NS_PER_SECOND: u64 = 1_000_000_000;
+
+/// The timestamp scale written, and assumed when none is given: 1 ms.
+const TIMESTAMP_SCALE: u64 = 1_000_000;
+
+/// Decoding should start 80 ms before a seek target, as for Ogg Opus.
+const SEEK_PRE_ROLL: u64 = 80_000_000;
+
+/// The codec ID of Opus tracks.
+const CODEC_ID: &[u8] = b"A_OPUS";
+
+// Element IDs, with their length markers.
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE_ID: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL_ID: u32 = 0x56BB;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const DISCARD_PADDING: u32 = 0x75A2;
+
+/// The track type of audio tracks.
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+/// An element size meaning the size is unknown.
+const UNKNOWN_SIZE: u64 = 0x00FF_FFFF_FFFF_FFFF;
+
+/// Convert a duration in nanoseconds to samples at 48 kHz, rounding to the
+/// nearest sample.
+fn ns_to_samples(ns: u64) -> usize {
+	((ns * SAMPLE_RATE + NS_PER_SECOND / 2) / NS_PER_SECOND) as usize
+}
+
+// ============================================================================
+// EBML Reading
+
+/// Reads EBML elements from a byte stream.
+///
+/// A stream ending partway through an element is treated as ending before
+/// it, so recordings which were cut off can still be read.
+#[derive(Debug)]
+struct EbmlReader<R> {
+	inner: R,
+}
+
+impl<R: Read> EbmlReader<R> {
+	/// Read exactly `buf.len()` bytes, returning `false` at end of stream.
+	fn fill(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+		let mut pos = 0;
+		while pos < buf.len() {
+			match self.inner.read(&mut buf[pos..]) {
+				Ok(0) => return Ok(false),
+				Ok(n) => pos += n,
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(true)
+	}
+
+	/// Read a variable-length integer, returning it with its marker bit
+	/// and its length.
+	fn read_vint(&mut self, max_len: usize) -> Result<Option<(u64, usize)>> {
+		let mut first = [0];
+		if !self.fill(&mut first)? {
+			return Ok(None);
+		}
+		let len = first[0].leading_zeros() as usize + 1;
+		if len > max_len {
+			return Err(Error::Malformed("invalid variable-length integer"));
+		}
+		let mut rest = [0; 7];
+		if !self.fill(&mut rest[..len - 1])? {
+			return Ok(None);
+		}
+		let value = rest[..len - 1].iter().fold(first[0] as u64, |v, &b| v << 8 | b as u64);
+		Ok(Some((value, len)))
+	}
+
+	/// Read the header of the next element, returning its ID and its size,
+	/// which is `None` if unknown.
+	fn read_header(&mut self) -> Result<Option<(u32, Option<u64>)>> {
+		let id = match self.read_vint(4)? {
+			Some((id, _)) => id as u32,
+			None => return Ok(None),
+		};
+		let size = match self.read_vint(8)? {
+			Some((size, len)) => {
+				let size = size & !(1 << (7 * len));
+				// A size of all ones means the size is unknown.
+				if size == (1 << (7 * len)) - 1 {
+					None
+				} else {
+					Some(size)
+				}
+			}
+			None => return Ok(None),
+		};
+		Ok(Some((id, size)))
+	}
+
+	/// Read an element's body, returning `None` at end of stream.
+	fn read_body(&mut self, size: Option<u64>) -> Result<Option<Vec<u8>>> {
+		let size = match size {
+			Some(size) => size,
+			None => return Err(Error::Malformed("element of unknown size")),
+		};
+		let mut body = Vec::new();
+		(&mut self.inner).take(size).read_to_end(&mut body)?;
+		if (body.len() as u64) < size {
+			return Ok(None);
+		}
+		Ok(Some(body))
+	}
+
+	/// Skip an element's body, returning `false` at end of stream.
+	fn skip(&mut self, size: Option<u64>) -> Result<bool> {
+		let size = match size {
+			Some(size) => size,
+			None => return Err(Error::Malformed("element of unknown size")),
+		};
+		let skipped = io::copy(&mut (&mut self.inner).take(size), &mut io::sink())?;
+		Ok(skipped == size)
+	}
+}
+
+/// Iterate over the child elements in the body of a master element.
+fn children(mut body: &[u8]) -> impl Iterator<Item = Result<(u32, &[u8])>> {
+	std::iter::from_fn(move || {
+		if body.is_empty() {
+			return None;
+		}
+		let mut reader = EbmlReader { inner: &mut body };
+		let result = match reader.read_header() {
+			Ok(Some((id, Some(size)))) if size <= reader.inner.len() as u64 => {
+				let (child, rest) = reader.inner.split_at(size as usize);
+				*reader.inner = rest;
+				Ok((id, child))
+			}
+			Ok(_) => Err(Error::Malformed("child element overruns its parent")),
+			Err(e) => Err(e),
+		};
+		if result.is_err() {
+			body = &[];
+		}
+		Some(result)
+	})
+}
+
+fn read_uint(data: &[u8]) -> Result<u64> {
+	if data.len() > 8 {
+		return Err(Error::Malformed("integer element is too long"));
+	}
+	Ok(data.iter().fold(0, |v, &b| v << 8 | b as u64))
+}
+
+fn read_int(data: &[u8]) -> Result<i64> {
+	if data.is_empty() {
+		return Ok(0);
+	}
+	let value = read_uint(data)? as i64;
+	// Sign-extend from the element's length.
+	let shift = 64 - 8 * data.len();
+	Ok(value << shift >> shift)
+}
+
+// ============================================================================
+// Reader
+
+/// A packet read from an Opus track.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WebmPacket {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The block's timestamp, in nanoseconds.
+	pub timestamp: i64,
+	/// The duration of audio to discard from the end of the decoded packet,
+	/// in nanoseconds. Zero for all but the last packet of a stream.
+	pub discard_padding: i64,
+}
+
+/// Reads the first Opus track of a WebM or Matroska file.
+///
+/// Packets can be read as they are with
+/// [`next_packet`](#method.next_packet), or decoded to PCM at 48 kHz with
+/// the codec delay and discard padding removed and the output gain applied.
+#[derive(Debug)]
+pub struct WebmOpusReader<R> {
+	ebml: EbmlReader<R>,
+	track: u64,
+	head: OpusHead,
+	codec_delay: u64,
+	seek_pre_roll: u64,
+	timestamp_scale: u64,
+	cluster_timestamp: u64,
+	queue: VecDeque<WebmPacket>,
+	decoder: MappedDecoder,
+	trim: Trim,
+}
+
+impl<R: Read> WebmOpusReader<R> {
+	/// Read the file's headers up to the first cluster, and prepare a
+	/// decoder for its first Opus track.
+	pub fn new(reader: R) -> Result<WebmOpusReader<R>> {
+		let mut ebml = EbmlReader { inner: reader };
+		let mut timestamp_scale = TIMESTAMP_SCALE;
+		let mut track = None;
+		loop {
+			let (id, size) = match ebml.read_header()? {
+				Some(header) => header,
+				None => return Err(Error::Malformed("no clusters found")),
+			};
+			match id {
+				// The segment's children follow directly.
+				SEGMENT => {}
+				EBML => {
+					let body = ebml.read_body(size)?.ok_or(Error::Malformed("truncated header"))?;
+					check_doc_type(&body)?;
+				}
+				INFO => {
+					let body = ebml.read_body(size)?.ok_or(Error::Malformed("truncated header"))?;
+					for child in children(&body) {
+						if let (TIMESTAMP_SCALE_ID, data) = child? {
+							timestamp_scale = read_uint(data)?;
+						}
+					}
+				}
+				TRACKS => {
+					let body = ebml.read_body(size)?.ok_or(Error::Malformed("truncated header"))?;
+					for child in children(&body) {
+						if let (TRACK_ENTRY, data) = child? {
+							if track.is_none() {
+								track = read_track_entry(data)?;
+							}
+						}
+					}
+				}
+				CLUSTER => break,
+				_ => {
+					if !ebml.skip(size)? {
+						return Err(Error::Malformed("no clusters found"));
+					}
+				}
+			}
+		}
+
+		let track = track.ok_or(Error::Malformed("no Opus track found"))?;
+		let decoder = MappedDecoder::new(&track.head)?;
+		Ok(WebmOpusReader {
+			ebml,
+			track: track.number,
+			trim: Trim {
+				skip: ns_to_samples(track.codec_delay) as u64,
+				remaining: None,
+			},
+			head: track.head,
+			codec_delay: track.codec_delay,
+			seek_pre_roll: track.seek_pre_roll,
+			timestamp_scale,
+			cluster_timestamp: 0,
+			queue: VecDeque::new(),
+			decoder,
+		})
+	}
+
+	/// Get the track's identification header, from its `CodecPrivate`.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the number of interleaved channels in the decoded output.
+	pub fn channels(&self) -> usize {
+		self.head.channels as usize
+	}
+
+	/// Get the duration to discard from the start of the decoded output, in
+	/// nanoseconds.
+	pub fn codec_delay(&self) -> u64 {
+		self.codec_delay
+	}
+
+	/// Get how long before a seek target decoding should begin, in
+	/// nanoseconds.
+	pub fn seek_pre_roll(&self) -> u64 {
+		self.seek_pre_roll
+	}
+
+	/// Read the next packet of the track.
+	///
+	/// Returns `None` at the end of the file.
+	pub fn next_packet(&mut self) -> Result<Option<WebmPacket>> {
+		while self.queue.is_empty() {
+			let (id, size) = match self.ebml.read_header()? {
+				Some(header) => header,
+				None => return Ok(None),
+			};
+			match id {
+				// Descend into these, which may be of unknown size.
+				SEGMENT | CLUSTER => {}
+				TIMESTAMP => match self.ebml.read_body(size)? {
+					Some(body) => self.cluster_timestamp = read_uint(&body)?,
+					None => return Ok(None),
+				},
+				SIMPLE_BLOCK => match self.ebml.read_body(size)? {
+					Some(body) => self.read_block(&body, 0)?,
+					None => return Ok(None),
+				},
+				BLOCK_GROUP => {
+					let body = match self.ebml.read_body(size)? {
+						Some(body) => body,
+						None => return Ok(None),
+					};
+					let mut block = None;
+					let mut discard_padding = 0;
+					for child in children(&body) {
+						match child? {
+							(BLOCK, data) => block = Some(data),
+							(DISCARD_PADDING, data) => discard_padding = read_int(data)?,
+							_ => {}
+						}
+					}
+					if let Some(block) = block {
+						self.read_block(block, discard_padding)?;
+					}
+				}
+				_ => {
+					if !self.ebml.skip(size)? {
+						return Ok(None);
+					}
+				}
+			}
+		}
+		Ok(self.queue.pop_front())
+	}
+
+	/// Decode the next chunk of audio.
+	///
+	/// Returns `None` at the end of the file.
+	pub fn decode(&mut self) -> Result<Option<Vec<i16>>> {
+		let mut output = vec![0; MAX_FRAME_SIZE * self.channels()];
+		while let Some(packet) = self.next_packet()? {
+			let samples = self.decoder.decode(&packet.data, &mut output)?;
+			let padding = ns_to_samples(std::cmp::max(packet.discard_padding, 0) as u64);
+			let channels = self.channels();
+			if self.trim.apply(&mut output, samples, samples.saturating_sub(padding), channels) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Decode the next chunk of audio with floating point output.
+	///
+	/// Returns `None` at the end of the file.
+	pub fn decode_float(&mut self) -> Result<Option<Vec<f32>>> {
+		let mut output = vec![0.0; MAX_FRAME_SIZE * self.channels()];
+		while let Some(packet) = self.next_packet()? {
+			let samples = self.decoder.decode_float(&packet.data, &mut output)?;
+			let padding = ns_to_samples(std::cmp::max(packet.discard_padding, 0) as u64);
+			let channels = self.channels();
+			if self.trim.apply(&mut output, samples, samples.saturating_sub(padding), channels) {
+				return Ok(Some(output));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Queue the frames of a block if it belongs to the track.
+	fn read_block(&mut self, block: &[u8], discard_padding: i64) -> Result<()> {
+		let mut data = block;
+		let (track, len) = match (EbmlReader { inner: &mut data }).read_vint(8)? {
+			Some(vint) => vint,
+			None => return Err(Error::Malformed("truncated block")),
+		};
+		if track & !(1 << (7 * len)) != self.track {
+			return Ok(());
+		}
+		if data.len() < 3 {
+			return Err(Error::Malformed("truncated block"));
+		}
+		let relative = i16::from_be_bytes([data[0], data[1]]) as i64;
+		let flags = data[2];
+		data = &data[3..];
+
+		let frames = read_lacing(&mut data, flags)?;
+		let timestamp = (self.cluster_timestamp as i64 + relative) * self.timestamp_scale as i64;
+		let count = frames.len();
+		let mut offset = 0;
+		let mut elapsed = 0;
+		for (i, frame) in frames.into_iter().enumerate() {
+			let packet = &data[offset..offset + frame];
+			offset += frame;
+			self.queue.push_back(WebmPacket {
+				data: packet.to_vec(),
+				// Laced frames share the block's timestamp, so the later
+				// ones are timed from the durations before them.
+				timestamp: timestamp + (elapsed * NS_PER_SECOND / SAMPLE_RATE) as i64,
+				discard_padding: if i == count - 1 { discard_padding } else { 0 },
+			});
+			elapsed += super::packet::get_nb_samples(packet, SAMPLE_RATE as u32)? as u64;
+		}
+		Ok(())
+	}
+}
+
+/// Check that the EBML header describes a WebM or Matroska file.
+fn check_doc_type(body: &[u8]) -> Result<()> {
+	for child in children(body) {
+		if let (DOC_TYPE, data) = child? {
+			let doc_type = data.split(|&b| b == 0).next().unwrap_or(data);
+			if doc_type != b"webm" && doc_type != b"matroska" {
+				return Err(Error::Malformed("not a WebM or Matroska file"));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// The fields of an Opus track entry.
+#[derive(Debug)]
+struct TrackEntry {
+	number: u64,
+	head: OpusHead,
+	codec_delay: u64,
+	seek_pre_roll: u64,
+}
+
+/// Read a track entry, returning `None` if it is not an Opus track.
+fn read_track_entry(body: &[u8]) -> Result<Option<TrackEntry>> {
+	let mut number = None;
+	let mut codec_id: &[u8] = &[];
+	let mut head = None;
+	let mut codec_delay = 0;
+	let mut seek_pre_roll = 0;
+	for child in children(body) {
+		match child? {
+			(TRACK_NUMBER, data) => number = Some(read_uint(data)?),
+			(CODEC_ID_ID, data) => codec_id = data,
+			(CODEC_PRIVATE, data) => head = Some(data),
+			(CODEC_DELAY, data) => codec_delay = read_uint(data)?,
+			(SEEK_PRE_ROLL_ID, data) => seek_pre_roll = read_uint(data)?,
+			_ => {}
+		}
+	}
+	if codec_id != CODEC_ID {
+		return Ok(None);
+	}
+	let number = number.ok_or(Error::Malformed("Opus track has no number"))?;
+	let head = head.ok_or(Error::Malformed("Opus track has no CodecPrivate"))?;
+	Ok(Some(TrackEntry {
+		number,
+		head: OpusHead::parse(head)?,
+		codec_delay,
+		seek_pre_roll,
+	}))
+}
+
+/// Read the lacing header of a block, returning the size of each frame.
+fn read_lacing(data: &mut &[u8], flags: u8) -> Result<Vec<usize>> {
+	const TRUNCATED: Error = Error::Malformed("truncated block lacing");
+	let lacing = (flags >> 1) & 3;
+	if lacing == 0 {
+		return Ok(vec![data.len()]);
+	}
+	let count = *data.first().ok_or(TRUNCATED)? as usize + 1;
+	*data = &data[1..];
+	let mut sizes = Vec::with_capacity(count);
+	match lacing {
+		// Xiph lacing: each size is a run of bytes of 255 and a final byte.
+		1 => {
+			for _ in 1..count {
+				let mut size = 0;
+				loop {
+					let byte = *data.first().ok_or(TRUNCATED)?;
+					*data = &data[1..];
+					size += byte as usize;
+					if byte < 255 {
+						break;
+					}
+				}
+				sizes.push(size);
+			}
+		}
+		// Fixed-size lacing.
+		2 => {
+			if data.len() % count != 0 {
+				return Err(Error::Malformed("uneven fixed-size lacing"));
+			}
+			return Ok(vec![data.len() / count; count]);
+		}
+		// EBML lacing: the first size, then signed differences.
+		_ => {
+			let mut reader = EbmlReader { inner: &mut *data };
+			let (first, len) = reader.read_vint(8)?.ok_or(TRUNCATED)?;
+			let mut size = (first & !(1 << (7 * len))) as i64;
+			sizes.push(size as usize);
+			for _ in 2..count {
+				let (value, len) = reader.read_vint(8)?.ok_or(TRUNCATED)?;
+				let bias = (1i64 << (7 * len - 1)) - 1;
+				size += (value & !(1 << (7 * len))) as i64 - bias;
+				if size < 0 {
+					return Err(Error::Malformed("invalid EBML lacing"));
+				}
+				sizes.push(size as usize);
+			}
+		}
+	}
+	let total: usize = sizes.iter().sum();
+	if total > data.len() {
+		return Err(TRUNCATED);
+	}
+	sizes.push(data.len() - total);
+	Ok(sizes)
+}
+
+// ============================================================================
+// EBML Writing
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+	let skip = id.leading_zeros() as usize / 8;
+	out.extend_from_slice(&id.to_be_bytes()[skip..]);
+}
+
+fn write_size(out: &mut Vec<u8>, size: u64) {
+	let mut len = 1;
+	// The all-ones value of each length is reserved for unknown sizes.
+	while len < 8 && size >= (1 << (7 * len)) - 1 {
+		len += 1;
+	}
+	out.extend_from_slice(&(size | 1 << (7 * len)).to_be_bytes()[8 - len..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+	write_id(out, id);
+	write_size(out, body.len() as u64);
+	out.extend_from_slice(body);
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+	let skip = std::cmp::min(value.leading_zeros() as usize / 8, 7);
+	write_element(out, id, &value.to_be_bytes()[skip..]);
+}
+
+fn write_int(out: &mut Vec<u8>, id: u32, value: i64) {
+	// Keep a sign bit, so a leading set bit is not read back as negative.
+	let redundant = if value < 0 { value.leading_ones() } else { value.leading_zeros() };
+	let skip = std::cmp::min((redundant as usize - 1) / 8, 7);
+	write_element(out, id, &value.to_be_bytes()[skip..]);
+}
+
+// ============================================================================
+// Writer
+
+/// Encodes PCM audio into a WebM file with a single Opus track.
+///
+/// Input is interleaved PCM at the encoder's sample rate, and may be
+/// supplied in chunks of any length. The headers are written along with
+/// the first audio, and [`finish`](#method.finish) must be called to
+/// complete the file.
+///
+/// The segment is written with an unknown size, as in live recordings, so
+/// the output need not be seekable.
+#[derive(Debug)]
+pub struct WebmOpusWriter<W> {
+	inner: W,
+	stream: StreamEncoder,
+	sample_rate: u32,
+	channels: u8,
+	headers_written: bool,
+	/// The body of the pending cluster.
+	cluster: Vec<u8>,
+	/// The timestamp of the pending cluster, in milliseconds.
+	cluster_timestamp: Option<u64>,
+}
+
+impl<W: Write> WebmOpusWriter<W> {
+	/// Create a writer which encodes with the given encoder, in 20 ms
+	/// frames.
+	pub fn new(writer: W, mut encoder: Encoder) -> Result<WebmOpusWriter<W>> {
+		let sample_rate = encoder.get_sample_rate()?;
+		let channels = encoder.channels as u8;
+		Ok(WebmOpusWriter {
+			inner: writer,
+			stream: StreamEncoder::new(encoder, FrameSize::Ms20)?,
+			sample_rate,
+			channels,
+			headers_written: false,
+			cluster: Vec::new(),
+			cluster_timestamp: None,
+		})
+	}
+
+	/// Get the underlying encoder.
+	pub fn encoder_mut(&mut self) -> &mut Encoder {
+		self.stream.encoder_mut()
+	}
+
+	/// Write interleaved PCM.
+	pub fn write(&mut self, pcm: &[i16]) -> Result<()> {
+		self.stream.write(pcm)?;
+		self.write_packets()
+	}
+
+	/// Write interleaved floating point PCM.
+	pub fn write_float(&mut self, pcm: &[f32]) -> Result<()> {
+		self.stream.write_float(pcm)?;
+		self.write_packets()
+	}
+
+	/// Encode the remaining input and complete the file.
+	///
+	/// The final frame is padded with silence, which the last block's
+	/// `DiscardPadding` trims.
+	pub fn finish(mut self) -> Result<W> {
+		self.stream.finish();
+		self.write_packets()?;
+		self.write_headers()?;
+		self.flush_cluster()?;
+		Ok(self.inner)
+	}
+
+	fn write_headers(&mut self) -> Result<()> {
+		if self.headers_written {
+			return Ok(());
+		}
+		let pre_skip = self.stream.lookahead() as u64 * SAMPLE_RATE / self.sample_rate as u64;
+		let head = OpusHead {
+			version: 1,
+			channels: self.channels,
+			pre_skip: pre_skip as u16,
+			input_sample_rate: self.sample_rate,
+			output_gain: 0,
+			mapping_family: 0,
+			streams: 1,
+			coupled_streams: self.channels - 1,
+			mapping: (0..self.channels).collect(),
+			demixing_matrix: Vec::new(),
+		};
+
+		let mut out = Vec::new();
+		let mut ebml = Vec::new();
+		write_uint(&mut ebml, EBML_VERSION, 1);
+		write_uint(&mut ebml, EBML_READ_VERSION, 1);
+		write_uint(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+		write_uint(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+		write_element(&mut ebml, DOC_TYPE, b"webm");
+		write_uint(&mut ebml, DOC_TYPE_VERSION, 4);
+		write_uint(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+		write_element(&mut out, EBML, &ebml);
+
+		write_id(&mut out, SEGMENT);
+		write_size(&mut out, UNKNOWN_SIZE);
+
+		let mut info = Vec::new();
+		write_uint(&mut info, TIMESTAMP_SCALE_ID, TIMESTAMP_SCALE);
+		let app = format!("opus-rs with {}", super::version());
+		write_element(&mut info, MUXING_APP, app.as_bytes());
+		write_element(&mut info, WRITING_APP, app.as_bytes());
+		write_element(&mut out, INFO, &info);
+
+		let mut audio = Vec::new();
+		write_element(&mut audio, SAMPLING_FREQUENCY, &(SAMPLE_RATE as f64).to_be_bytes());
+		write_uint(&mut audio, CHANNELS, self.channels as u64);
+		let mut entry = Vec::new();
+		write_uint(&mut entry, TRACK_NUMBER, 1);
+		write_uint(&mut entry, TRACK_UID, 1);
+		write_uint(&mut entry, TRACK_TYPE, TRACK_TYPE_AUDIO);
+		write_element(&mut entry, CODEC_ID_ID, CODEC_ID);
+		write_element(&mut entry, CODEC_PRIVATE, &head.to_bytes());
+		write_uint(&mut entry, CODEC_DELAY, pre_skip * NS_PER_SECOND / SAMPLE_RATE);
+		write_uint(&mut entry, SEEK_PRE_ROLL_ID, SEEK_PRE_ROLL);
+		write_element(&mut entry, AUDIO, &audio);
+		let mut tracks = Vec::new();
+		write_element(&mut tracks, TRACK_ENTRY, &entry);
+		write_element(&mut out, TRACKS, &tracks);
+
+		self.inner.write_all(&out)?;
+		self.headers_written = true;
+		Ok(())
+	}
+
+	fn write_packets(&mut self) -> Result<()> {
+		while let Some(packet) = self.stream.next_packet()? {
+			self.write_headers()?;
+			self.write_block(&packet)?;
+		}
+		Ok(())
+	}
+
+	fn write_block(&mut self, packet: &StreamPacket) -> Result<()> {
+		let rate = self.sample_rate as u64;
+		let timestamp = packet.timestamp * NS_PER_SECOND / rate / TIMESTAMP_SCALE;
+		// Start a new cluster every second, well within the range of block
+		// timestamps.
+		let cluster_timestamp = match self.cluster_timestamp {
+			Some(start) if timestamp - start < 1000 => start,
+			_ => {
+				self.flush_cluster()?;
+				write_uint(&mut self.cluster, TIMESTAMP, timestamp);
+				self.cluster_timestamp = Some(timestamp);
+				timestamp
+			}
+		};
+
+		let mut block = vec![0x81];
+		block.extend_from_slice(&((timestamp - cluster_timestamp) as i16).to_be_bytes());
+		if packet.padding == 0 {
+			// Every Opus packet can be decoded independently.
+			block.push(0x80);
+			block.extend_from_slice(&packet.data);
+			write_element(&mut self.cluster, SIMPLE_BLOCK, &block);
+		} else {
+			block.push(0);
+			block.extend_from_slice(&packet.data);
+			let mut group = Vec::new();
+			write_element(&mut group, BLOCK, &block);
+			let padding = packet.padding as u64 * NS_PER_SECOND / rate;
+			write_int(&mut group, DISCARD_PADDING, padding as i64);
+			write_element(&mut self.cluster, BLOCK_GROUP, &group);
+		}
+		Ok(())
+	}
+
+	fn flush_cluster(&mut self) -> Result<()> {
+		if self.cluster.is_empty() {
+			return Ok(());
+		}
+		let mut out = Vec::new();
+		write_element(&mut out, CLUSTER, &self.cluster);
+		self.inner.write_all(&out)?;
+		self.cluster.clear();
+		Ok(())
+	}
+}
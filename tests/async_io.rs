@@ -0,0 +1,134 @@
+//! Tests for the async codecs and streams.
+#![cfg(feature = "async")]
+
+extern crate bytes;
+extern crate futures;
+extern crate opus;
+extern crate tokio;
+extern crate tokio_util;
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+use futures::executor::{block_on, block_on_stream};
+use futures::SinkExt;
+use opus::async_io::{DecoderCodec, EncoderCodec, OggOpusStream};
+use opus::ogg::{OggOpusReader, OggOpusWriter};
+use opus::{Application, Channels, Decoder, Encoder, FrameSize, StreamEncoder};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::codec::{Encoder as _, FramedRead, FramedWrite};
+
+fn sine(len: usize) -> Vec<f32> {
+	(0..len).map(|i| (i as f32 * 0.01).sin() * 0.5).collect()
+}
+
+/// A source which returns a few bytes at a time, and is not ready on every
+/// other poll.
+struct Trickle {
+	data: Vec<u8>,
+	pos: usize,
+	ready: bool,
+}
+
+impl AsyncRead for Trickle {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context,
+		buf: &mut ReadBuf,
+	) -> Poll<io::Result<()>> {
+		self.ready = !self.ready;
+		if !self.ready {
+			cx.waker().wake_by_ref();
+			return Poll::Pending;
+		}
+		let count = std::cmp::min(
+			std::cmp::min(buf.remaining(), 1 + self.pos % 700),
+			self.data.len() - self.pos,
+		);
+		buf.put_slice(&self.data[self.pos..self.pos + count]);
+		self.pos += count;
+		Poll::Ready(Ok(()))
+	}
+}
+
+#[test]
+fn codec_round_trip() {
+	let encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let stream = StreamEncoder::new(encoder, FrameSize::Ms20).unwrap();
+	let mut codec = EncoderCodec::new(stream);
+
+	// 2000 samples per channel, in awkward chunks.
+	let pcm = sine(2 * 2000);
+	// A lost first packet is concealed as 20 ms of audio.
+	let mut wire = BytesMut::from(&[0, 0][..]);
+	for chunk in pcm.chunks(2 * 333) {
+		codec.encode(chunk, &mut wire).unwrap();
+	}
+	assert!(codec.encode(vec![0.0; 3], &mut wire).is_err());
+	codec.finish(&mut wire).unwrap();
+	// An empty packet stands for a lost one.
+	wire.extend_from_slice(&[0, 0]);
+
+	let decoder = Decoder::new(48000, Channels::Stereo).unwrap();
+	let frames = FramedRead::new(&wire[..], DecoderCodec::new(decoder));
+	let frames: Vec<Vec<f32>> = block_on_stream(frames).map(|frame| frame.unwrap()).collect();
+	let lookahead = 2000 + codec.stream_mut().lookahead();
+	assert_eq!(frames.len(), (lookahead + 959) / 960 + 2);
+	assert!(frames.iter().all(|frame| frame.len() == 2 * 960));
+}
+
+#[test]
+fn codec_framed_finish() {
+	let encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let stream = StreamEncoder::new(encoder, FrameSize::Ms20).unwrap();
+	let mut sink = FramedWrite::new(Vec::new(), EncoderCodec::new(stream));
+
+	// Less than a frame, which is held back until the end.
+	let pcm = sine(500);
+	block_on(sink.send(Some(pcm))).unwrap();
+	assert_eq!(sink.get_ref().len(), 0);
+	block_on(sink.send(None)).unwrap();
+	let lookahead = 500 + sink.encoder_mut().stream_mut().lookahead();
+	let wire = sink.into_inner();
+
+	let decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let frames = FramedRead::new(&wire[..], DecoderCodec::new(decoder));
+	let frames: Vec<Vec<f32>> = block_on_stream(frames).map(|frame| frame.unwrap()).collect();
+	assert_eq!(frames.len(), (lookahead + 959) / 960);
+}
+
+#[test]
+fn ogg_stream() {
+	let mut writer = OggOpusWriter::new(Vec::new(), 48000, 2, Application::Audio).unwrap();
+	writer.tags_mut().add("TITLE", "Sine!");
+	writer.write_float(&sine(2 * 30000)).unwrap();
+	let data = writer.finish().unwrap();
+
+	let mut expected = Vec::new();
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	while let Some(chunk) = reader.decode_float().unwrap() {
+		expected.extend_from_slice(&chunk);
+	}
+
+	let stream = OggOpusStream::new(Trickle { data, pos: 0, ready: false });
+	assert!(stream.head().is_none());
+	let mut stream = block_on_stream(stream);
+	let mut output = Vec::new();
+	for chunk in &mut stream {
+		output.extend_from_slice(&chunk.unwrap());
+	}
+	let stream = stream.into_inner();
+	assert_eq!(stream.head().unwrap().channels, 2);
+	assert_eq!(stream.tags().unwrap().get("title"), Some("Sine!"));
+	assert_eq!(output, expected);
+	assert_eq!(output.len(), 2 * 30000);
+}
+
+#[test]
+fn ogg_stream_error() {
+	let mut stream = block_on_stream(OggOpusStream::new(&b"not an Ogg stream"[..]));
+	assert!(stream.next().unwrap().is_err());
+	assert!(stream.next().is_none());
+}
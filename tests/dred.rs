@@ -0,0 +1,45 @@
+//! Tests for Deep Redundancy, which needs libopus 1.5 built with DRED.
+#![cfg(feature = "dred")]
+
+extern crate opus;
+
+use opus::dred::{DredDecoder, DredState};
+use opus::{Application, Channels, Decoder, Encoder};
+
+const FRAME: usize = 960;
+
+#[test]
+fn dred_round_trip() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_dred_duration(50).unwrap();
+	assert_eq!(encoder.get_dred_duration().unwrap(), 50);
+	// DRED is only sent when loss is expected.
+	encoder.set_packet_loss_perc(20).unwrap();
+
+	let packets: Vec<Vec<u8>> = (0..50)
+		.map(|n| {
+			let pcm: Vec<i16> = (0..FRAME)
+				.map(|i| (((n * FRAME + i) as f32 * 0.02).sin() * 8000.0) as i16)
+				.collect();
+			encoder.encode_vec(&pcm, 4000).unwrap()
+		})
+		.collect();
+
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let mut output = vec![0i16; FRAME];
+	for packet in &packets[..40] {
+		decoder.decode(packet, &mut output, false).unwrap();
+	}
+
+	// Packets 40 to 42 are lost; rebuild them from packet 43.
+	let mut dred_decoder = DredDecoder::new().unwrap();
+	let mut state = DredState::new().unwrap();
+	let info = dred_decoder.parse(&mut state, &packets[43], 48000, 48000).unwrap().unwrap();
+	assert!(info.available as usize >= 3 * FRAME, "{:?}", info);
+	assert_eq!(state.sample_rate(), 48000);
+	for lost in 0..3 {
+		let offset = ((3 - lost) * FRAME) as u32;
+		assert_eq!(decoder.dred_decode(&state, offset, &mut output).unwrap(), FRAME);
+	}
+	assert_eq!(decoder.decode(&packets[43], &mut output, false).unwrap(), FRAME);
+}
@@ -0,0 +1,132 @@
+//! Tests for the adaptive jitter buffer.
+
+extern crate opus;
+
+use opus::jitter::{JitterBuffer, JitterStats};
+use opus::{Application, Channels, Decoder, Encoder};
+
+const FRAME: usize = 960;
+
+fn packets(count: usize) -> Vec<Vec<u8>> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_inband_fec(true).unwrap();
+	encoder.set_packet_loss_perc(20).unwrap();
+	(0..count)
+		.map(|n| {
+			let pcm: Vec<i16> = (0..FRAME)
+				.map(|i| (((n * FRAME + i) as f32 * 0.03).sin() * 8000.0) as i16)
+				.collect();
+			encoder.encode_vec(&pcm, 4000).unwrap()
+		})
+		.collect()
+}
+
+fn buffer() -> JitterBuffer {
+	JitterBuffer::new(Decoder::new(48000, Channels::Mono).unwrap(), FRAME).unwrap()
+}
+
+/// Push the packets at the given frame indices, ahead of each pop, and check
+/// every frame has the right size.
+fn run(buffer: &mut JitterBuffer, packets: &[Vec<u8>], arrivals: &[&[usize]]) {
+	for arrived in arrivals {
+		for &n in *arrived {
+			let sequence = (n as u16).wrapping_add(65000);
+			let timestamp = (n as u32 * FRAME as u32).wrapping_sub(5000);
+			buffer.push(sequence, timestamp, &packets[n]).unwrap();
+		}
+		assert_eq!(buffer.pop().unwrap().len(), FRAME);
+	}
+}
+
+#[test]
+fn in_order() {
+	let packets = packets(10);
+	let mut buffer = buffer();
+	let arrivals: Vec<Vec<usize>> = (0..10).map(|n| vec![n]).collect();
+	let arrivals: Vec<&[usize]> = arrivals.iter().map(|a| &a[..]).collect();
+	run(&mut buffer, &packets, &arrivals);
+	assert_eq!(buffer.stats(), JitterStats::default());
+	assert_eq!(buffer.target_delay(), FRAME);
+}
+
+#[test]
+fn reordering() {
+	let packets = packets(8);
+	let mut buffer = buffer();
+	buffer.set_delay_range(2 * FRAME, 4 * FRAME).unwrap();
+	run(&mut buffer, &packets, &[&[1], &[0, 3], &[2], &[4], &[6], &[5], &[7], &[], &[]]);
+	assert_eq!(buffer.stats(), JitterStats::default());
+}
+
+#[test]
+fn fec_recovery() {
+	let packets = packets(8);
+	let mut buffer = buffer();
+	run(&mut buffer, &packets, &[&[0], &[1], &[2], &[4], &[5], &[6], &[7]]);
+	let stats = buffer.stats();
+	assert_eq!(stats.lost, 1);
+	assert_eq!(stats.fec_recovered, 1);
+	assert_eq!(stats.concealed, 0);
+}
+
+#[test]
+fn celt_loss_conceals() {
+	// CELT-only packets carry no redundancy, so a loss is concealed.
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::LowDelay).unwrap();
+	encoder.set_inband_fec(true).unwrap();
+	let packets: Vec<Vec<u8>> = (0..8)
+		.map(|n| {
+			let pcm: Vec<i16> = (0..FRAME).map(|i| ((n * FRAME + i) % 100) as i16 * 80).collect();
+			encoder.encode_vec(&pcm, 4000).unwrap()
+		})
+		.collect();
+	let mut buffer = buffer();
+	run(&mut buffer, &packets, &[&[0], &[1], &[2], &[4], &[5], &[6], &[7]]);
+	let stats = buffer.stats();
+	assert_eq!(stats.lost, 1);
+	assert_eq!(stats.fec_recovered, 0);
+	assert_eq!(stats.concealed, 1);
+}
+
+#[test]
+fn burst_loss_conceals() {
+	let packets = packets(10);
+	let mut buffer = buffer();
+	buffer.set_delay_range(2 * FRAME, 2 * FRAME).unwrap();
+	// Concealment plays while nothing arrives, and the audio it covered is
+	// skipped, but packet 5 arrives in time to recover packet 4.
+	run(&mut buffer, &packets, &[&[0], &[1], &[], &[], &[], &[5], &[6], &[7], &[8]]);
+	let stats = buffer.stats();
+	assert_eq!(stats.lost, 3);
+	assert_eq!(stats.fec_recovered, 1);
+	assert!(stats.concealed >= 2, "{:?}", stats);
+}
+
+#[test]
+fn late_packets_dropped() {
+	let packets = packets(8);
+	let mut buffer = buffer();
+	run(&mut buffer, &packets, &[&[0], &[1], &[3], &[4], &[2, 5], &[5], &[6]]);
+	let stats = buffer.stats();
+	assert_eq!(stats.late, 2);
+	assert_eq!(stats.lost, 1);
+}
+
+#[test]
+fn delay_adapts_to_jitter() {
+	let packets = packets(40);
+	let mut buffer = buffer();
+	// Packets arrive in bursts of four every four frames.
+	let arrivals: Vec<Vec<usize>> =
+		(0..40).map(|n| if n % 4 == 3 { (n - 3..=n).collect() } else { Vec::new() }).collect();
+	let arrivals: Vec<&[usize]> = arrivals.iter().map(|a| &a[..]).collect();
+	run(&mut buffer, &packets, &arrivals);
+	assert!(buffer.target_delay() >= 3 * FRAME, "target {}", buffer.target_delay());
+	assert_eq!(buffer.stats().late, 0);
+}
+
+#[test]
+fn bad_frame_size() {
+	let decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	assert!(JitterBuffer::new(decoder, 100).is_err());
+}
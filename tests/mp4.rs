@@ -0,0 +1,289 @@
+//! Tests for Opus in MP4.
+#![cfg(feature = "mp4")]
+
+extern crate opus;
+
+use opus::mp4::{Error, Mp4OpusReader, Mp4OpusWriter};
+use opus::ogg::OpusHead;
+use opus::{Application, Channels, Encoder};
+use std::io::Cursor;
+
+const FRAME: usize = 960;
+
+fn stereo_head(pre_skip: u16) -> OpusHead {
+	OpusHead {
+		version: 0,
+		channels: 2,
+		pre_skip,
+		input_sample_rate: 44100,
+		output_gain: -256,
+		mapping_family: 0,
+		streams: 1,
+		coupled_streams: 1,
+		mapping: vec![0, 1],
+		demixing_matrix: Vec::new(),
+	}
+}
+
+fn encode(channels: Channels, count: usize) -> (u16, Vec<Vec<u8>>) {
+	let mut encoder = Encoder::new(48000, channels, Application::Audio).unwrap();
+	let pre_skip = encoder.get_lookahead().unwrap() as u16;
+	let channels = channels as usize;
+	let packets = (0..count)
+		.map(|n| {
+			let pcm: Vec<i16> = (0..FRAME * channels)
+				.map(|i| (((n * FRAME * channels + i) as f32 * 0.01).sin() * 8000.0) as i16)
+				.collect();
+			encoder.encode_vec(&pcm, 4000).unwrap()
+		})
+		.collect();
+	(pre_skip, packets)
+}
+
+fn box_types(mut data: &[u8]) -> Vec<String> {
+	let mut types = Vec::new();
+	while data.len() >= 8 {
+		let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+		types.push(String::from_utf8_lossy(&data[4..8]).into_owned());
+		data = &data[size..];
+	}
+	types
+}
+
+#[test]
+fn dops() {
+	let head = stereo_head(312);
+	let dops = head.to_dops();
+	assert_eq!(dops, [0, 2, 0x01, 0x38, 0, 0, 0xac, 0x44, 0xff, 0x00, 0]);
+	assert_eq!(OpusHead::parse_dops(&dops).unwrap(), head);
+
+	let surround = OpusHead {
+		channels: 6,
+		mapping_family: 1,
+		streams: 4,
+		coupled_streams: 2,
+		mapping: vec![0, 4, 1, 2, 3, 5],
+		..stereo_head(312)
+	};
+	let dops = surround.to_dops();
+	assert_eq!(dops.len(), 11 + 2 + 6);
+	assert_eq!(OpusHead::parse_dops(&dops).unwrap(), surround);
+
+	assert!(OpusHead::parse_dops(&dops[..15]).is_err());
+	assert!(OpusHead::parse_dops(&[1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn fragmented_round_trip() {
+	let (pre_skip, packets) = encode(Channels::Stereo, 120);
+	let mut writer = Mp4OpusWriter::new(Vec::new(), stereo_head(pre_skip)).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	let data = writer.finish().unwrap();
+	// A fragment for each second of audio.
+	assert_eq!(box_types(&data), ["ftyp", "moov", "moof", "mdat", "moof", "mdat", "moof", "mdat"]);
+
+	let mut reader = Mp4OpusReader::new(Cursor::new(&data)).unwrap();
+	assert_eq!(reader.head(), &stereo_head(pre_skip));
+	assert_eq!(reader.channels(), 2);
+	assert_eq!(reader.packets(), 120);
+	assert_eq!(reader.duration(), None);
+	for (i, expected) in packets.iter().enumerate() {
+		let packet = reader.next_packet().unwrap().unwrap();
+		assert_eq!(&packet.data, expected);
+		assert_eq!(packet.timestamp, (i * FRAME) as u64);
+		assert_eq!(packet.duration, FRAME as u64);
+	}
+	assert_eq!(reader.next_packet().unwrap(), None);
+
+	// The edit list skips the pre-skip.
+	let mut reader = Mp4OpusReader::new(Cursor::new(&data)).unwrap();
+	let mut length = 0;
+	while let Some(pcm) = reader.decode_float().unwrap() {
+		length += pcm.len();
+	}
+	assert_eq!(length, 2 * (120 * FRAME - pre_skip as usize));
+}
+
+#[test]
+fn trimmed_fragments() {
+	let (pre_skip, packets) = encode(Channels::Stereo, 60);
+	let length = 60 * FRAME as u64 - pre_skip as u64 - 500;
+	let mut writer = Mp4OpusWriter::new(Cursor::new(Vec::new()), stereo_head(pre_skip)).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	let data = writer.finish_trimmed(length).unwrap().into_inner();
+
+	// The edit list now trims the padding at the end.
+	let mut reader = Mp4OpusReader::new(Cursor::new(&data)).unwrap();
+	assert_eq!(reader.duration(), Some(length));
+	assert_eq!(reader.packets(), 60);
+	let mut decoded = 0;
+	while let Some(pcm) = reader.decode().unwrap() {
+		decoded += pcm.len();
+	}
+	assert_eq!(decoded as u64, 2 * length);
+	assert_eq!(reader.duration(), Some(length));
+
+	let mut writer = Mp4OpusWriter::new(Cursor::new(Vec::new()), stereo_head(pre_skip)).unwrap();
+	writer.write_packet(&packets[0]).unwrap();
+	assert!(writer.finish_trimmed(FRAME as u64).is_err());
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+	let mut out = (body.len() as u32 + 8).to_be_bytes().to_vec();
+	out.extend_from_slice(kind);
+	out.extend_from_slice(body);
+	out
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+	values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect()
+}
+
+/// Build a plain file with its movie box after the media data, as written
+/// by most recorders, with two chunks of packets.
+fn plain_file(packets: &[Vec<u8>], pre_skip: u16, duration_ms: u32) -> Vec<u8> {
+	let mut out = mp4_box(b"ftyp", b"M4A \0\0\0\0M4A mp42isom");
+	let mdat_start = out.len() as u32 + 8;
+	let split = packets.len() / 2;
+	out.extend_from_slice(&mp4_box(b"mdat", &packets.concat()));
+	let second_chunk = mdat_start + packets[..split].iter().map(|p| p.len() as u32).sum::<u32>();
+
+	let mut entry = vec![0; 6];
+	entry.extend_from_slice(&[0, 1]);
+	entry.extend_from_slice(&[0; 8]);
+	entry.extend_from_slice(&[0, 1, 0, 16, 0, 0, 0, 0]);
+	entry.extend_from_slice(&words(&[48000 << 16]));
+	let head = OpusHead {
+		channels: 1,
+		coupled_streams: 0,
+		mapping: vec![0],
+		..stereo_head(pre_skip)
+	};
+	entry.extend_from_slice(&mp4_box(b"dOps", &head.to_dops()));
+	let stsd = [words(&[0, 1]), mp4_box(b"Opus", &entry)].concat();
+	let mut stsz = words(&[0, 0, packets.len() as u32]);
+	stsz.extend(words(&packets.iter().map(|p| p.len() as u32).collect::<Vec<_>>()));
+	let stbl = [
+		mp4_box(b"stsd", &stsd),
+		mp4_box(b"stts", &words(&[0, 1, packets.len() as u32, FRAME as u32])),
+		mp4_box(b"stsc", &words(&[0, 2, 1, split as u32, 1, 2, (packets.len() - split) as u32, 1])),
+		mp4_box(b"stsz", &stsz),
+		mp4_box(b"stco", &words(&[0, 2, mdat_start, second_chunk])),
+	]
+	.concat();
+	let mdia = [
+		mp4_box(b"mdhd", &words(&[0, 0, 0, 48000, 0, 0x55c4_0000])),
+		mp4_box(b"minf", &mp4_box(b"stbl", &stbl)),
+	]
+	.concat();
+	// An empty edit, then one which skips the pre-skip and trims the end.
+	let elst = words(&[0, 2, 10, u32::MAX, 0x0001_0000, duration_ms, pre_skip as u32, 0x0001_0000]);
+	let trak = [
+		mp4_box(b"tkhd", &words(&[0, 0, 0, 7, 0, 0])),
+		mp4_box(b"edts", &mp4_box(b"elst", &elst)),
+		mp4_box(b"mdia", &mdia),
+	]
+	.concat();
+	let moov = [mp4_box(b"mvhd", &words(&[0, 0, 0, 1000, 0])), mp4_box(b"trak", &trak)].concat();
+	out.extend_from_slice(&mp4_box(b"moov", &moov));
+	out
+}
+
+#[test]
+fn sample_tables() {
+	let (pre_skip, packets) = encode(Channels::Mono, 9);
+	let data = plain_file(&packets, pre_skip, 150);
+
+	let mut reader = Mp4OpusReader::new(Cursor::new(&data)).unwrap();
+	assert_eq!(reader.head().channels, 1);
+	assert_eq!(reader.head().pre_skip, pre_skip);
+	assert_eq!(reader.packets(), 9);
+	assert_eq!(reader.duration(), Some(150 * 48));
+	let mut read = Vec::new();
+	while let Some(packet) = reader.next_packet().unwrap() {
+		read.push(packet.data);
+	}
+	assert_eq!(read, packets);
+
+	let mut reader = Mp4OpusReader::new(Cursor::new(&data)).unwrap();
+	let mut length = 0;
+	while let Some(pcm) = reader.decode().unwrap() {
+		length += pcm.len();
+	}
+	assert_eq!(length, 150 * 48);
+}
+
+/// Overwrite the word `offset` bytes into the body of the first box of the
+/// given type.
+fn patch(data: &[u8], kind: &[u8; 4], offset: usize, value: u32) -> Vec<u8> {
+	let mut data = data.to_vec();
+	let start = data.windows(4).position(|w| w == kind).unwrap() + 4 + offset;
+	data[start..start + 4].copy_from_slice(&value.to_be_bytes());
+	data
+}
+
+fn expect_malformed<T>(result: Result<T, Error>) {
+	match result {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("{:?}", other.map(|_| ())),
+	}
+}
+
+#[test]
+fn reject_invalid() {
+	let (pre_skip, packets) = encode(Channels::Mono, 2);
+	let data = plain_file(&packets, pre_skip, 0);
+	// Cut off before the movie box.
+	match Mp4OpusReader::new(Cursor::new(&data[..data.len() - 20])) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("{:?}", other.map(|_| ())),
+	}
+	assert!(Mp4OpusReader::new(Cursor::new(b"not an mp4 file at all")).is_err());
+
+	// Entry counts larger than their boxes or the file could hold.
+	let valid = plain_file(&packets, pre_skip, 40);
+	expect_malformed(Mp4OpusReader::new(Cursor::new(patch(&valid, b"stts", 4, 0x1000_0000))));
+	let data = patch(&patch(&valid, b"stsz", 4, 1), b"stsz", 8, u32::MAX);
+	expect_malformed(Mp4OpusReader::new(Cursor::new(data)));
+
+	let mut writer = Mp4OpusWriter::new(Vec::new(), stereo_head(pre_skip)).unwrap();
+	writer.write_packet(&packets[0]).unwrap();
+	let fragmented = writer.finish().unwrap();
+	assert!(Mp4OpusReader::new(Cursor::new(&fragmented)).is_ok());
+	expect_malformed(Mp4OpusReader::new(Cursor::new(patch(&fragmented, b"trun", 4, 0x1000_0000))));
+	let data = patch(&patch(&fragmented, b"trun", 0, 0x1), b"trun", 4, 0x1000_0000);
+	expect_malformed(Mp4OpusReader::new(Cursor::new(data)));
+
+	// Times too large to convert to 48 kHz.
+	expect_malformed(Mp4OpusReader::new(Cursor::new(patch(&fragmented, b"elst", 16, 0x7fff_ffff))));
+	let data = patch(&fragmented, b"tfdt", 4, u32::MAX);
+	let mut reader = Mp4OpusReader::new(Cursor::new(data)).unwrap();
+	expect_malformed(reader.next_packet());
+
+	// A packet larger than any Opus packet.
+	let data = patch(&valid, b"stsz", 12, 100_000);
+	let mut reader = Mp4OpusReader::new(Cursor::new(data)).unwrap();
+	expect_malformed(reader.next_packet());
+
+	// A channel mapping which refers to a stream that does not exist.
+	let mut head = stereo_head(312);
+	head.mapping_family = 1;
+	head.coupled_streams = 0;
+	expect_malformed(OpusHead::parse_dops(&head.to_dops()));
+
+	// Ambisonics with a demixing matrix, which dOps cannot describe.
+	let mut dops = stereo_head(312).to_dops();
+	dops[10] = 3;
+	match OpusHead::parse_dops(&dops) {
+		Err(Error::Unsupported(_)) => {}
+		other => panic!("{:?}", other),
+	}
+
+	let mut head = stereo_head(312);
+	head.mapping.pop();
+	assert!(Mp4OpusWriter::new(Vec::new(), head).is_err());
+}
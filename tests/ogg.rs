@@ -0,0 +1,589 @@
+//! Tests for Ogg Opus stream handling.
+#![cfg(feature = "ogg-opus")]
+
+extern crate opus;
+
+use std::io::{Cursor, Read};
+
+use opus::ogg::{Error, OggOpusPcmReader, OggOpusReader, OpusHead, OpusTags, PcmFormat};
+use opus::{Application, Channels, Encoder};
+
+const FRAME: usize = 960;
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = 0u32;
+	for &b in data {
+		crc ^= (b as u32) << 24;
+		for _ in 0..8 {
+			crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+		}
+	}
+	crc
+}
+
+fn page(out: &mut Vec<u8>, flags: u8, granule: i64, sequence: u32, packets: &[&[u8]]) {
+	let mut lacing = Vec::new();
+	let mut body = Vec::new();
+	for packet in packets {
+		let mut len = packet.len();
+		while len >= 255 {
+			lacing.push(255);
+			len -= 255;
+		}
+		lacing.push(len as u8);
+		body.extend_from_slice(packet);
+	}
+	let start = out.len();
+	out.extend_from_slice(b"OggS\0");
+	out.push(flags);
+	out.extend_from_slice(&granule.to_le_bytes());
+	out.extend_from_slice(&0x1234u32.to_le_bytes());
+	out.extend_from_slice(&sequence.to_le_bytes());
+	out.extend_from_slice(&[0; 4]);
+	out.push(lacing.len() as u8);
+	out.extend_from_slice(&lacing);
+	out.extend_from_slice(&body);
+	let crc = crc32(&out[start..]);
+	out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn head(channels: u8, pre_skip: u16, gain: i16) -> Vec<u8> {
+	let mut head = b"OpusHead\x01".to_vec();
+	head.push(channels);
+	head.extend_from_slice(&pre_skip.to_le_bytes());
+	head.extend_from_slice(&48000u32.to_le_bytes());
+	head.extend_from_slice(&gain.to_le_bytes());
+	head.push(0);
+	head
+}
+
+fn tags() -> Vec<u8> {
+	let mut tags = b"OpusTags".to_vec();
+	tags.extend_from_slice(&4u32.to_le_bytes());
+	tags.extend_from_slice(b"test");
+	tags.extend_from_slice(&1u32.to_le_bytes());
+	tags.extend_from_slice(&11u32.to_le_bytes());
+	tags.extend_from_slice(b"TITLE=Sine!");
+	tags
+}
+
+fn sine(frames: usize) -> Vec<i16> {
+	(0..frames * FRAME).map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16).collect()
+}
+
+/// Build a mono stream with one packet per page, ending at `final_granule`.
+fn stream(frames: usize, start: i64, final_granule: i64, gain: i16) -> Vec<u8> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let pcm = sine(frames);
+
+	let mut out = Vec::new();
+	page(&mut out, 0x02, 0, 0, &[&head(1, 312, gain)]);
+	page(&mut out, 0x00, 0, 1, &[&tags()]);
+	for (i, chunk) in pcm.chunks(FRAME).enumerate() {
+		let packet = encoder.encode_vec(chunk, 4000).unwrap();
+		let last = i == frames - 1;
+		let granule = if last { final_granule } else { start + ((i + 1) * FRAME) as i64 };
+		page(&mut out, if last { 0x04 } else { 0 }, granule, 2 + i as u32, &[&packet]);
+	}
+	out
+}
+
+fn decode_all<R: std::io::Read>(reader: &mut OggOpusReader<R>) -> Vec<i16> {
+	let mut pcm = Vec::new();
+	while let Some(chunk) = reader.decode().unwrap() {
+		pcm.extend_from_slice(&chunk);
+	}
+	pcm
+}
+
+#[test]
+fn read_headers() {
+	let data = stream(5, 0, 5 * FRAME as i64, -256);
+	let reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.channels(), 1);
+	assert_eq!(reader.head().pre_skip, 312);
+	assert_eq!(reader.head().output_gain, -256);
+	assert_eq!(reader.head().mapping, [0]);
+	assert_eq!(reader.tags().vendor, "test");
+	assert_eq!(reader.tags().get("title"), Some("Sine!"));
+}
+
+#[test]
+fn pre_skip_and_end_trimming() {
+	// The final page trims the last packet down to 100 samples.
+	let end = (4 * FRAME + 100) as i64;
+	let data = stream(5, 0, end, 0);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), end as usize - 312);
+}
+
+#[test]
+fn nonzero_start_granule() {
+	let start = 48000 * 3;
+	let end = start + (5 * FRAME) as i64;
+	let data = stream(5, start, end, 0);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), 5 * FRAME - 312);
+}
+
+#[test]
+fn head_round_trip() {
+	let stereo = head(2, 3840, -512);
+	let parsed = OpusHead::parse(&stereo).unwrap();
+	assert_eq!(parsed.channels, 2);
+	assert_eq!(parsed.pre_skip, 3840);
+	assert_eq!(parsed.input_sample_rate, 48000);
+	assert_eq!(parsed.output_gain, -512);
+	assert_eq!((parsed.mapping_family, parsed.streams, parsed.coupled_streams), (0, 1, 1));
+	assert_eq!(parsed.to_bytes(), stereo);
+
+	let mut surround = head(3, 312, 0);
+	surround[18] = 1;
+	surround.extend_from_slice(&[2, 1, 0, 2, 1]);
+	let parsed = OpusHead::parse(&surround).unwrap();
+	assert_eq!(parsed.mapping, [0, 2, 1]);
+	assert_eq!(parsed.to_bytes(), surround);
+	let layout = parsed.layout().unwrap();
+	assert_eq!(layout, opus::ChannelLayout::vorbis(3).unwrap());
+	let mut stereo = OpusHead::parse(&stereo).unwrap();
+	stereo.mapping_family = 1;
+	stereo.set_layout(&layout);
+	assert_eq!(stereo.channels, 3);
+	assert_eq!(stereo.to_bytes()[18..], surround[18..]);
+
+	// Vorbis channel order only goes up to 7.1.
+	let mut nine = head(9, 312, 0);
+	nine[18] = 1;
+	nine.extend_from_slice(&[9, 0]);
+	nine.extend(0..9);
+	match OpusHead::parse(&nine) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("{:?}", other),
+	}
+	nine[18] = 255;
+	assert_eq!(OpusHead::parse(&nine).unwrap().channels, 9);
+
+	let mut ambisonics = head(4, 312, 0);
+	ambisonics[18] = 3;
+	ambisonics.extend_from_slice(&[2, 2]);
+	ambisonics.extend((0..2 * 4 * 4).map(|i| i as u8));
+	let parsed = OpusHead::parse(&ambisonics).unwrap();
+	assert!(parsed.mapping.is_empty());
+	assert_eq!(parsed.demixing_matrix.len(), 32);
+	assert_eq!(parsed.to_bytes(), ambisonics);
+	assert!(OpusHead::parse(&ambisonics[..ambisonics.len() - 1]).is_err());
+}
+
+#[test]
+fn tags_round_trip() {
+	fn build(comments: &[&str], trailer: &[u8]) -> Vec<u8> {
+		let mut data = b"OpusTags".to_vec();
+		data.extend_from_slice(&4u32.to_le_bytes());
+		data.extend_from_slice(b"test");
+		data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+		for comment in comments {
+			data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+			data.extend_from_slice(comment.as_bytes());
+		}
+		data.extend_from_slice(trailer);
+		data
+	}
+
+	let data = build(&["TITLE=Sine!", "ARTIST=A", "artist=B=C", "odd"], &[0x01, 0xff, 0x00]);
+	let parsed = OpusTags::parse(&data).unwrap();
+	assert_eq!(parsed.vendor, "test");
+	assert_eq!(parsed.get("Title"), Some("Sine!"));
+	assert_eq!(parsed.get_all("ARTIST").collect::<Vec<_>>(), ["A", "B=C"]);
+	assert_eq!(parsed.comments[3], ("odd".to_owned(), String::new()));
+	assert_eq!(parsed.binary, [0x01, 0xff, 0x00]);
+	assert_eq!(
+		parsed.to_bytes(),
+		build(&["TITLE=Sine!", "ARTIST=A", "artist=B=C", "odd="], &[0x01, 0xff, 0x00])
+	);
+
+	// Padding without the flag bit is dropped.
+	let mut parsed = OpusTags::parse(&build(&["ARTIST=A"], &[0; 16])).unwrap();
+	assert!(parsed.binary.is_empty());
+	parsed.remove("artist");
+	parsed.add("ALBUM", "X");
+	assert_eq!(parsed.to_bytes(), build(&["ALBUM=X"], &[]));
+}
+
+#[test]
+fn output_gain() {
+	fn energy(gain: i16) -> f64 {
+		let data = stream(10, 0, 10 * FRAME as i64, gain);
+		let mut reader = OggOpusReader::new(&data[..]).unwrap();
+		decode_all(&mut reader).iter().map(|&s| (s as f64) * (s as f64)).sum()
+	}
+	// -20 dB in Q7.8 is a tenth of the amplitude.
+	let ratio = (energy(-20 * 256) / energy(0)).sqrt();
+	assert!(ratio > 0.09 && ratio < 0.11, "ratio was {}", ratio);
+}
+
+#[test]
+fn skips_other_streams_and_garbage() {
+	let data = stream(3, 0, 3 * FRAME as i64, 0);
+	let mut noisy = b"garbage before the stream".to_vec();
+	noisy.extend_from_slice(&data);
+	let mut reader = OggOpusReader::new(&noisy[..]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), 3 * FRAME - 312);
+}
+
+#[test]
+fn reject_non_opus() {
+	let mut data = Vec::new();
+	page(&mut data, 0x02, 0, 0, &[b"\x01vorbis"]);
+	match OggOpusReader::new(&data[..]) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("expected Malformed, got {:?}", other.map(|_| ())),
+	}
+}
+
+#[test]
+fn reject_bad_mapping() {
+	let mut bad = head(3, 312, 0);
+	bad[18] = 1;
+	bad.extend_from_slice(&[2, 1, 0, 4, 1]);
+	let mut data = Vec::new();
+	page(&mut data, 0x02, 0, 0, &[&bad]);
+	page(&mut data, 0x00, 0, 1, &[&tags()]);
+	match OggOpusReader::new(&data[..]) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("expected Malformed, got {:?}", other.map(|_| ())),
+	}
+}
+
+#[test]
+fn multistream() {
+	let mapping = [0, 2, 1];
+	let mut encoder = opus::MSEncoder::new(48000, 2, 1, &mapping, Application::Audio).unwrap();
+	let mut surround = head(3, 312, 0);
+	surround[18] = 1;
+	surround.extend_from_slice(&[2, 1]);
+	surround.extend_from_slice(&mapping);
+
+	let mut data = Vec::new();
+	page(&mut data, 0x02, 0, 0, &[&surround]);
+	page(&mut data, 0x00, 0, 1, &[&tags()]);
+	let packets: Vec<Vec<u8>> =
+		(0..4).map(|_| encoder.encode_vec(&[100; 3 * FRAME], 4000).unwrap()).collect();
+	let refs: Vec<&[u8]> = packets.iter().map(|p| &p[..]).collect();
+	page(&mut data, 0x04, 4 * FRAME as i64, 2, &refs);
+
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.channels(), 3);
+	let mut total = 0;
+	while let Some(chunk) = reader.decode_float().unwrap() {
+		total += chunk.len();
+	}
+	assert_eq!(total, 3 * (4 * FRAME - 312));
+}
+
+#[test]
+fn projection() {
+	let mut encoder =
+		opus::projection::ProjectionEncoder::new(48000, 4, 3, Application::Audio).unwrap();
+	let mut ambisonics = head(4, 312, 0);
+	ambisonics[18] = 3;
+	ambisonics.extend_from_slice(&[encoder.streams(), encoder.coupled_streams()]);
+	ambisonics.extend_from_slice(&encoder.get_demixing_matrix().unwrap());
+
+	let mut data = Vec::new();
+	page(&mut data, 0x02, 0, 0, &[&ambisonics]);
+	page(&mut data, 0x00, 0, 1, &[&tags()]);
+	let packets: Vec<Vec<u8>> =
+		(0..4).map(|_| encoder.encode_vec(&[100; 4 * FRAME], 4000).unwrap()).collect();
+	let refs: Vec<&[u8]> = packets.iter().map(|p| &p[..]).collect();
+	page(&mut data, 0x04, 4 * FRAME as i64, 2, &refs);
+
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.channels(), 4);
+	assert_eq!(decode_all(&mut reader).len(), 4 * (4 * FRAME - 312));
+}
+
+fn write_sine(sample_rate: u32, channels: u8, samples: usize) -> Vec<u8> {
+	let mut writer =
+		opus::ogg::OggOpusWriter::new(Vec::new(), sample_rate, channels, Application::Audio)
+			.unwrap();
+	writer.tags_mut().add("TITLE", "Sine!");
+	let pcm: Vec<f32> =
+		(0..samples * channels as usize).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+	// Deliberately awkward chunk sizes.
+	for chunk in pcm.chunks(333 * channels as usize) {
+		writer.write_float(chunk).unwrap();
+	}
+	writer.finish().unwrap()
+}
+
+#[test]
+fn write_mono_round_trip() {
+	let data = write_sine(48000, 1, 12345);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head().mapping_family, 0);
+	assert_eq!(reader.head().input_sample_rate, 48000);
+	assert_eq!(reader.head().pre_skip, 312);
+	assert_eq!(reader.tags().get("title"), Some("Sine!"));
+	let output = decode_all(&mut reader);
+	assert_eq!(output.len(), 12345);
+
+	// With the pre-skip removed, the output lines up with the input.
+	let error: f32 = output[1000..11000]
+		.iter()
+		.enumerate()
+		.map(|(i, &s)| (s as f32 / 32768.0 - ((i + 1000) as f32 * 0.01).sin() * 0.5).abs())
+		.sum();
+	assert!(error / 10000.0 < 0.05, "mean error {}", error / 10000.0);
+}
+
+#[test]
+fn write_resampled_round_trip() {
+	// Output is always at 48 kHz, three times as many samples as 16 kHz.
+	let data = write_sine(16000, 2, 16000 * 3 + 7);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head().input_sample_rate, 16000);
+	assert_eq!(decode_all(&mut reader).len(), 2 * 3 * (16000 * 3 + 7));
+}
+
+#[test]
+fn write_surround_round_trip() {
+	let data = write_sine(48000, 6, 48000);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head().mapping_family, 1);
+	assert_eq!((reader.head().streams, reader.head().coupled_streams), (4, 2));
+	assert_eq!(reader.head().mapping, [0, 4, 1, 2, 3, 5]);
+	assert_eq!(decode_all(&mut reader).len(), 6 * 48000);
+}
+
+#[test]
+fn write_pages() {
+	let data = write_sine(48000, 1, 48000 * 5);
+	let mut granules = Vec::new();
+	let mut flags = Vec::new();
+	let mut pos = 0;
+	while pos < data.len() {
+		assert_eq!(&data[pos..pos + 4], b"OggS");
+		flags.push(data[pos + 5]);
+		let mut granule = [0; 8];
+		granule.copy_from_slice(&data[pos + 6..pos + 14]);
+		granules.push(i64::from_le_bytes(granule));
+		let segments = data[pos + 26] as usize;
+		let body: usize = data[pos + 27..pos + 27 + segments].iter().map(|&l| l as usize).sum();
+		pos += 27 + segments + body;
+	}
+	// Headers, then roughly a page per second of audio.
+	assert!(granules.len() >= 7 && granules.len() <= 9, "{} pages", granules.len());
+	assert_eq!(flags[0], 0x02);
+	assert_eq!(*flags.last().unwrap(), 0x04);
+	assert_eq!(&granules[..2], &[0, 0]);
+	assert!(granules.windows(2).all(|w| w[0] <= w[1]));
+	assert_eq!(*granules.last().unwrap(), 312 + 48000 * 5);
+}
+
+#[test]
+fn write_empty() {
+	let data = write_sine(48000, 2, 0);
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), 0);
+}
+
+/// Seek to each sample and check the output against a linear decode.
+fn check_seeks(data: Vec<u8>, samples: &[u64]) {
+	let full = decode_all(&mut OggOpusReader::new(&data[..]).unwrap());
+	let mut reader = OggOpusReader::new(Cursor::new(data)).unwrap();
+	for &n in samples {
+		reader.seek_to_sample(n).unwrap();
+		let output = decode_all(&mut reader);
+		assert_eq!(output.len(), full.len() - n as usize, "seeking to {}", n);
+		// Give the decoder 100 ms to converge, then the output should line up
+		// closely; being a single sample off would give an error around 100.
+		let error: f64 = output
+			.iter()
+			.zip(&full[n as usize..])
+			.skip(4800)
+			.take(4800)
+			.map(|(&a, &b)| (a as f64 - b as f64).abs())
+			.sum();
+		let mean = error / output.len().saturating_sub(4800).clamp(1, 4800) as f64;
+		assert!(mean < 10.0, "seeking to {}: mean error {}", n, mean);
+	}
+}
+
+#[test]
+fn seek_to_sample() {
+	// Long enough that the seek has to bisect, with pages of about a second.
+	let data = write_sine(48000, 1, 48000 * 30);
+	check_seeks(
+		data,
+		&[48000 * 20 + 17, 0, 100, 47990, 48000, 48000 * 29 + 5000, 3000, 48000 * 30],
+	);
+}
+
+#[test]
+fn seek_nonzero_start_granule() {
+	let start = 48000 * 3;
+	let data = stream(60, start, start + (60 * FRAME) as i64, 0);
+	check_seeks(data, &[10 * FRAME as u64 + 1, 1000, 59 * FRAME as u64]);
+}
+
+#[test]
+fn seek_past_end() {
+	let data = write_sine(48000, 2, 10000);
+	let mut reader = OggOpusReader::new(Cursor::new(data)).unwrap();
+	reader.seek_to_sample(20000).unwrap();
+	assert!(reader.decode().unwrap().is_none());
+}
+
+#[test]
+fn pcm_reader() {
+	let end = (4 * FRAME + 100) as i64;
+	let data = stream(5, 0, end, 0);
+	let full = decode_all(&mut OggOpusReader::new(&data[..]).unwrap());
+
+	let mut bytes = Vec::new();
+	let mut reader = OggOpusPcmReader::new(&data[..], PcmFormat::S16Le).unwrap();
+	reader.read_to_end(&mut bytes).unwrap();
+	assert_eq!(bytes.len(), 2 * (end as usize - 312));
+	let pcm: Vec<i16> = bytes.chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+	assert_eq!(pcm, full);
+
+	// Reads of odd sizes split samples across calls.
+	let mut reader = OggOpusPcmReader::new(&data[..], PcmFormat::S16Le).unwrap();
+	let mut split = Vec::new();
+	let mut buf = [0; 7];
+	loop {
+		match reader.read(&mut buf).unwrap() {
+			0 => break,
+			n => split.extend_from_slice(&buf[..n]),
+		}
+	}
+	assert_eq!(split, bytes);
+	assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn pcm_reader_float() {
+	let data = write_sine(48000, 2, 10000);
+	let mut float = Vec::new();
+	let mut decoder = OggOpusReader::new(&data[..]).unwrap();
+	while let Some(chunk) = decoder.decode_float().unwrap() {
+		float.extend_from_slice(&chunk);
+	}
+
+	let mut reader = OggOpusPcmReader::new(&data[..], PcmFormat::F32Le).unwrap();
+	let mut bytes = Vec::new();
+	reader.read_to_end(&mut bytes).unwrap();
+	assert_eq!(bytes.len(), 4 * 2 * 10000);
+	let pcm: Vec<f32> =
+		bytes.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+	assert_eq!(pcm, float);
+
+	// Seeking discards anything buffered.
+	let mut reader = OggOpusPcmReader::new(Cursor::new(data), PcmFormat::F32Le).unwrap();
+	reader.read_exact(&mut [0; 10]).unwrap();
+	reader.seek_to_sample(9000).unwrap();
+	bytes.clear();
+	reader.read_to_end(&mut bytes).unwrap();
+	assert_eq!(bytes.len(), 4 * 2 * 1000);
+}
+
+#[test]
+fn pcm_reader_error() {
+	let error = std::io::Error::from(Error::Malformed("bad"));
+	assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+	assert!(error.to_string().contains("bad"));
+	let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short");
+	assert_eq!(std::io::Error::from(Error::Io(io)).kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+/// Build a mono stream of 60 ms packets, each of three 20 ms frames.
+fn long_frames(packets: usize) -> Vec<u8> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	encoder.set_bitrate(opus::Bitrate::Bits(96000)).unwrap();
+	let pcm = sine(3 * packets);
+
+	let mut out = Vec::new();
+	page(&mut out, 0x02, 0, 0, &[&head(1, 312, 0)]);
+	page(&mut out, 0x00, 0, 1, &[&tags()]);
+	for (i, chunk) in pcm.chunks(3 * FRAME).enumerate() {
+		let packet = encoder.encode_vec(chunk, 4000).unwrap();
+		assert_eq!(opus::packet::get_nb_frames(&packet).unwrap(), 3);
+		let flags = if i == packets - 1 { 0x04 } else { 0 };
+		page(&mut out, flags, ((i + 1) * 3 * FRAME) as i64, 2 + i as u32, &[&packet]);
+	}
+	out
+}
+
+#[test]
+fn cut() {
+	let data = long_frames(20);
+	let full = decode_all(&mut OggOpusReader::new(&data[..]).unwrap());
+
+	let clip = opus::ogg::cut(&data[..], Vec::new(), 20000, 30000).unwrap();
+	let mut reader = OggOpusReader::new(&clip[..]).unwrap();
+	// The multi-frame packet at the start was split.
+	let pre_skip = reader.head().pre_skip as usize;
+	assert!((3840..3840 + FRAME).contains(&pre_skip), "pre-skip {}", pre_skip);
+	assert_eq!(reader.tags().get("title"), Some("Sine!"));
+	let output = decode_all(&mut reader);
+	assert_eq!(output.len(), 10000);
+	// As after a seek, the output lines up once the decoder has converged.
+	let error: f64 = output
+		.iter()
+		.zip(&full[20000..])
+		.skip(4800)
+		.map(|(&a, &b)| (a as f64 - b as f64).abs())
+		.sum();
+	assert!(error / 5200.0 < 10.0, "mean error {}", error / 5200.0);
+
+	// From the start, and past the end.
+	let clip = opus::ogg::cut(&data[..], Vec::new(), 0, 1000).unwrap();
+	let mut reader = OggOpusReader::new(&clip[..]).unwrap();
+	assert_eq!(reader.head().pre_skip, 312);
+	assert_eq!(decode_all(&mut reader), &full[..1000]);
+	let clip = opus::ogg::cut(&data[..], Vec::new(), 50000, 100000).unwrap();
+	let tail = decode_all(&mut OggOpusReader::new(&clip[..]).unwrap());
+	assert_eq!(tail.len(), full.len() - 50000);
+	let clip = opus::ogg::cut(&data[..], Vec::new(), 100000, 100000).unwrap();
+	assert!(decode_all(&mut OggOpusReader::new(&clip[..]).unwrap()).is_empty());
+
+	assert!(opus::ogg::cut(&data[..], Vec::new(), 2, 1).is_err());
+}
+
+#[test]
+fn concat() {
+	let first = write_sine(48000, 2, 10000);
+	let second = write_sine(48000, 2, 20000);
+	let joined = opus::ogg::concat(vec![&first[..], &second[..]], Vec::new()).unwrap();
+	// A single logical stream, with the headers of the first.
+	assert_eq!(joined.windows(8).filter(|w| w == b"OpusHead").count(), 1);
+	let mut reader = OggOpusReader::new(Cursor::new(&joined[..])).unwrap();
+	assert_eq!(reader.head().pre_skip, 312);
+	assert_eq!(reader.tags().get("title"), Some("Sine!"));
+	// The first stream runs on to the end of its last frame, and the second
+	// keeps its pre-skip, which is shorter than a frame.
+	let expected = decode_all(&mut OggOpusReader::new(&first[..]).unwrap());
+	let output = decode_all(&mut reader);
+	assert_eq!(output.len(), 2 * ((312 + 10000 + FRAME - 1) / FRAME * FRAME + 20000));
+	assert_eq!(output[..expected.len()], expected[..]);
+
+	// Seeking counts from the start of the joined stream.
+	let mut reader = OggOpusReader::new(Cursor::new(&joined[..])).unwrap();
+	reader.seek_to_sample(5000).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), output.len() - 2 * 5000);
+
+	// A long pre-skip is cut at frame boundaries, splitting packets.
+	let data = long_frames(4);
+	let clip = opus::ogg::cut(&long_frames(20)[..], Vec::new(), 21000, 31000).unwrap();
+	let pre_skip = OggOpusReader::new(&clip[..]).unwrap().head().pre_skip as usize;
+	let joined = opus::ogg::concat(vec![&data[..], &clip[..]], Vec::new()).unwrap();
+	let output = decode_all(&mut OggOpusReader::new(&joined[..]).unwrap());
+	assert_eq!(output.len(), 12 * FRAME - 312 + pre_skip % FRAME + 10000);
+
+	let mono = write_sine(48000, 1, 1000);
+	match opus::ogg::concat(vec![&first[..], &mono[..]], Vec::new()) {
+		Err(Error::Opus(e)) => assert_eq!(e.code(), opus::ErrorCode::BadArg),
+		other => panic!("{:?}", other.map(|_| ())),
+	}
+	assert!(opus::ogg::concat(Vec::<&[u8]>::new(), Vec::new()).is_err());
+}
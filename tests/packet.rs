@@ -0,0 +1,310 @@
+//! Tests for the native packet parser.
+
+extern crate opus;
+
+use opus::packet::{self, Extension, Mode, ParseError, Toc};
+use opus::{Bandwidth, PacketMerger, PacketSplitter};
+
+#[test]
+fn toc() {
+	let silk = Toc(0x00);
+	assert_eq!(
+		(silk.mode(), silk.bandwidth(), silk.frame_size()),
+		(Mode::Silk, Bandwidth::Narrowband, 480)
+	);
+	let silk = Toc(7 << 3);
+	assert_eq!(
+		(silk.mode(), silk.bandwidth(), silk.frame_size()),
+		(Mode::Silk, Bandwidth::Mediumband, 2880)
+	);
+	let hybrid = Toc(15 << 3 | 0x04 | 2);
+	assert_eq!(
+		(hybrid.mode(), hybrid.bandwidth(), hybrid.frame_size()),
+		(Mode::Hybrid, Bandwidth::Fullband, 960)
+	);
+	assert!(hybrid.stereo());
+	assert_eq!(hybrid.code(), 2);
+	let celt = Toc(24 << 3 | 3);
+	assert_eq!(
+		(celt.mode(), celt.bandwidth(), celt.frame_size()),
+		(Mode::Celt, Bandwidth::Superwideband, 120)
+	);
+	assert!(!celt.stereo());
+	assert_eq!(celt.code(), 3);
+
+	// Agrees with libopus for every configuration.
+	for toc in 0..=255u8 {
+		let packet = [toc, 0, 0, 0];
+		assert_eq!(Toc(toc).bandwidth(), packet::get_bandwidth(&packet).unwrap());
+		assert_eq!(Toc(toc).frame_size(), packet::get_samples_per_frame(&packet, 48000).unwrap());
+	}
+}
+
+#[test]
+fn parse_codes() {
+	let packet = packet::parse(&[0xf8, 1, 2, 3]).unwrap();
+	assert_eq!(packet.frames, [&[1, 2, 3][..]]);
+	assert_eq!(packet.payload_offset, 1);
+	assert_eq!(packet.samples(), 960);
+
+	let packet = packet::parse(&[0xf9, 1, 2, 3, 4]).unwrap();
+	assert_eq!(packet.frames, [&[1, 2][..], &[3, 4][..]]);
+
+	let packet = packet::parse(&[0xfa, 1, 2, 3, 4]).unwrap();
+	assert_eq!(packet.frames, [&[2][..], &[3, 4][..]]);
+	assert_eq!(packet.payload_offset, 2);
+
+	// Two-byte frame length: 252 + 4 * 1 = 256.
+	let mut long = vec![0xfa, 252, 1];
+	long.extend_from_slice(&[7; 300]);
+	let packet = packet::parse(&long).unwrap();
+	assert_eq!((packet.frames[0].len(), packet.frames[1].len()), (256, 44));
+}
+
+#[test]
+fn parse_code_3() {
+	// CBR with 256 + 2 bytes of padding.
+	let mut data = vec![0xfb, 0x43, 255, 2, 1, 1, 2, 2, 3, 3];
+	data.extend_from_slice(&[9; 256]);
+	let packet = packet::parse(&data).unwrap();
+	assert_eq!(packet.frames, [&[1, 1][..], &[2, 2][..], &[3, 3][..]]);
+	assert_eq!(packet::parse_detailed(&data).unwrap().padding, &[9; 256][..]);
+	assert_eq!(packet.payload_offset, 4);
+	assert_eq!(packet.samples(), 3 * 960);
+
+	// VBR without padding.
+	let packet = packet::parse_detailed(&[0xfb, 0x83, 1, 0, 1, 2, 2]).unwrap();
+	assert_eq!(packet.packet.frames, [&[1][..], &[][..], &[2, 2][..]]);
+	assert!(packet.padding.is_empty());
+}
+
+#[test]
+fn parse_matches_libopus() {
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Stereo, opus::Application::Audio).unwrap();
+	let mut rp = opus::Repacketizer::new().unwrap();
+	let pcm: Vec<i16> = (0..960 * 2).map(|i| (i as i16).wrapping_mul(37)).collect();
+	let packets: Vec<Vec<u8>> = (0..3).map(|_| encoder.encode_vec(&pcm, 1000).unwrap()).collect();
+	let mut out = vec![0; 4000];
+	let len = rp.combine(&[&packets[0], &packets[1], &packets[2]], &mut out).unwrap();
+	let padded = len + 300;
+	packet::pad(&mut out[..padded], len).unwrap();
+
+	let packet = packet::parse(&out[..padded]).unwrap();
+	assert_eq!(packet.frames.len(), packet::get_nb_frames(&out[..padded]).unwrap());
+	assert_eq!(packet.samples(), packet::get_nb_samples(&out[..padded], 48000).unwrap());
+	for (frame, original) in packet.frames.iter().zip(&packets) {
+		assert_eq!(*frame, &original[1..]);
+	}
+	// Two of the extra bytes hold the padding length.
+	assert_eq!(packet::parse_detailed(&out[..padded]).unwrap().padding.len(), 298);
+}
+
+#[test]
+fn parse_errors() {
+	let cases: &[(&[u8], ParseError)] = &[
+		(&[], ParseError::Empty),
+		(&[0xf9, 1, 2, 3], ParseError::UnevenFrames),
+		(&[0xfa], ParseError::TruncatedLength),
+		(&[0xfa, 253], ParseError::TruncatedLength),
+		(&[0xfa, 5, 1], ParseError::FramesOverflow),
+		(&[0xfb], ParseError::MissingFrameCount),
+		(&[0xfb, 0x00], ParseError::NoFrames),
+		(&[0xfb, 0x07], ParseError::TooLong),
+		(&[0xfb, 0x41, 255], ParseError::TruncatedPadding),
+		(&[0xfb, 0x41, 3, 1], ParseError::PaddingOverflow),
+		(&[0xfb, 0x02, 1, 2, 3], ParseError::UnevenFrames),
+		(&[0xfb, 0x83, 1, 1], ParseError::FramesOverflow),
+	];
+	for &(data, error) in cases {
+		assert_eq!(packet::parse_detailed(data).unwrap_err(), error, "{:?}", data);
+		// The reason survives conversion to the crate's error type.
+		let wrapped = packet::parse(data).unwrap_err();
+		assert_eq!(wrapped.code(), opus::ErrorCode::InvalidPacket);
+		assert_eq!(wrapped.parse_error(), Some(error));
+	}
+
+	let mut big = vec![0xf8];
+	big.extend_from_slice(&[0; 1276]);
+	assert_eq!(packet::parse_detailed(&big).unwrap_err(), ParseError::FrameTooLarge);
+
+	let error: opus::Error = ParseError::Empty.into();
+	assert_eq!(error.code(), opus::ErrorCode::InvalidPacket);
+	assert_eq!(error.to_string(), "packet::parse: packet is empty");
+	assert_eq!(opus::Error::from(ParseError::Empty).parse_error(), Some(ParseError::Empty));
+}
+
+fn encode(count: usize) -> Vec<Vec<u8>> {
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Mono, opus::Application::Audio).unwrap();
+	let pcm: Vec<i16> = (0..960).map(|i| ((i as f32 * 0.1).sin() * 5000.0) as i16).collect();
+	(0..count).map(|_| encoder.encode_vec(&pcm, 1000).unwrap()).collect()
+}
+
+#[test]
+fn build_round_trip() {
+	let toc = Toc(0xf8);
+	let cases: &[&[&[u8]]] = &[
+		&[&[1, 2]],
+		&[&[1, 2], &[3, 4]],
+		&[&[1], &[2, 3]],
+		&[&[1], &[2], &[3]],
+		&[&[1], &[], &[2]],
+	];
+	for (frames, code) in cases.iter().zip(&[0, 1, 2, 3, 3]) {
+		let packet = packet::build(toc, frames, &[]).unwrap();
+		let parsed = packet::parse(&packet).unwrap();
+		assert_eq!(parsed.config().code(), *code);
+		assert_eq!(&parsed.frames[..], *frames);
+	}
+
+	let big = [5; 700];
+	let packet = packet::build(toc, &[&big, &[1]], &[]).unwrap();
+	assert_eq!(packet::parse(&packet).unwrap().frames, [&big[..], &[1][..]]);
+	assert!(packet::build(toc, &[&[0; 1276]], &[]).is_err());
+	assert!(packet::build(toc, &[&[][..]; 7], &[]).is_err());
+}
+
+#[test]
+fn parse_extensions() {
+	// Padding byte, then extensions on frames 0, 1 and 3.
+	let padding = [0x01, 2 << 1 | 1, 0xaa, 0x02, 40 << 1 | 1, 3, 1, 2, 3, 0x03, 2, 50 << 1, 9, 9];
+	let extensions = packet::parse_extensions(&padding, 4).unwrap();
+	let expected = [
+		Extension { id: 2, frame: 0, data: &[0xaa] },
+		Extension { id: 40, frame: 1, data: &[1, 2, 3] },
+		Extension { id: 50, frame: 3, data: &[9, 9] },
+	];
+	assert_eq!(extensions, expected);
+
+	assert_eq!(packet::parse_extensions(&padding, 3).unwrap_err(), ParseError::ExtensionFrame);
+	assert_eq!(
+		packet::parse_extensions(&padding[..5], 4).unwrap_err(),
+		ParseError::TruncatedExtension
+	);
+	assert_eq!(packet::parse_extensions(&[0x05], 1).unwrap_err(), ParseError::TruncatedExtension);
+	// A zero byte ends the extensions.
+	assert!(packet::parse_extensions(&[0x00, 0x05], 1).unwrap().is_empty());
+}
+
+#[test]
+fn build_with_extensions() {
+	let packets = encode(3);
+	let frames: Vec<&[u8]> = packets.iter().map(|p| &p[1..]).collect();
+	let long = [7; 300];
+	let extensions = [
+		Extension { id: 33, frame: 0, data: &long },
+		Extension { id: 3, frame: 2, data: &[] },
+		Extension { id: 100, frame: 2, data: b"last" },
+	];
+	let packet = packet::build(Toc(packets[0][0]), &frames, &extensions).unwrap();
+	let parsed = packet::parse_detailed(&packet).unwrap();
+	assert_eq!(parsed.packet.frames, frames);
+	assert_eq!(parsed.extensions().unwrap(), extensions);
+
+	// Older decoders simply skip the padding.
+	let mut decoder = opus::Decoder::new(48000, opus::Channels::Mono).unwrap();
+	let mut output = vec![0i16; 5760];
+	assert_eq!(decoder.decode(&packet, &mut output, false).unwrap(), 3 * 960);
+
+	let bad = |id, frame, data: &'static [u8]| {
+		packet::build(Toc(packets[0][0]), &frames, &[Extension { id, frame, data }]).is_err()
+	};
+	assert!(bad(1, 0, &[]));
+	assert!(bad(2, 0, &[1, 2]));
+	assert!(bad(128, 0, &[]));
+	assert!(bad(40, 3, &[]));
+}
+
+#[test]
+fn extensions_survive_repacketizing() {
+	let packets = encode(3);
+	let first =
+		packet::add_extensions(&packets[0], &[Extension { id: 2, frame: 0, data: &[1] }]).unwrap();
+	let second =
+		packet::add_extensions(&packets[2], &[Extension { id: 64, frame: 0, data: b"side" }])
+			.unwrap();
+	assert_eq!(packet::parse(&first).unwrap().frames, [&packets[0][1..]]);
+
+	let combined = packet::combine(&[&first, &packets[1], &second]).unwrap();
+	let parsed = packet::parse_detailed(&combined).unwrap();
+	assert_eq!(parsed.packet.frames.len(), 3);
+	assert_eq!(
+		parsed.extensions().unwrap(),
+		[Extension { id: 2, frame: 0, data: &[1] }, Extension { id: 64, frame: 2, data: b"side" }]
+	);
+
+	// Packets with different configurations can't be combined.
+	let mut stereo = packets[1].clone();
+	stereo[0] |= 0x04;
+	assert!(packet::combine(&[&packets[0], &stereo]).is_err());
+}
+
+#[test]
+fn packet_splitter() {
+	let packets = encode(3);
+	let tagged =
+		packet::add_extensions(&packets[1], &[Extension { id: 40, frame: 0, data: b"x" }]).unwrap();
+	let combined = packet::combine(&[&packets[0], &tagged, &packets[2]]).unwrap();
+
+	let mut splitter = PacketSplitter::new();
+	splitter.push(&combined).unwrap();
+	drop(combined);
+	let split: Vec<_> = std::iter::from_fn(|| splitter.next_packet()).collect();
+	assert_eq!(split.len(), 3);
+	assert!(split.iter().all(|p| p.duration == 960));
+	assert_eq!(split[0].data, packets[0]);
+	assert_eq!(split[1].data, tagged);
+	assert_eq!(split[2].data, packets[2]);
+	assert!(splitter.push(&[]).is_err());
+}
+
+#[test]
+fn packet_merger() {
+	let packets = encode(5);
+	let mut merger = PacketMerger::new().max_duration(1920);
+	for packet in &packets {
+		merger.push(packet).unwrap();
+	}
+	merger.flush().unwrap();
+	let merged: Vec<_> = std::iter::from_fn(|| merger.next_packet()).collect();
+	assert_eq!(merged.iter().map(|p| p.duration).collect::<Vec<_>>(), [1920, 1920, 960]);
+	let frames = packet::parse(&merged[1].data).unwrap().frames;
+	assert_eq!(frames, [&packets[2][1..], &packets[3][1..]]);
+
+	// A byte budget for about two frames, with a three frame packet as input.
+	let budget = packets[0].len() + packets[1].len() + 2;
+	let mut merger = PacketMerger::new().max_bytes(budget);
+	merger.push(&packet::combine(&[&packets[0], &packets[1], &packets[2]]).unwrap()).unwrap();
+	merger.push(&packets[3]).unwrap();
+	merger.flush().unwrap();
+	let merged: Vec<_> = std::iter::from_fn(|| merger.next_packet()).collect();
+	assert!(merged.iter().all(|p| p.data.len() <= budget));
+	assert_eq!(merged.iter().map(|p| p.duration).sum::<usize>(), 4 * 960);
+
+	// Extensions count against the budget too.
+	let data = [7; 300];
+	let tagged =
+		packet::add_extensions(&packets[4], &[Extension { id: 64, frame: 0, data: &data }])
+			.unwrap();
+	let budget = packets[0].len() + tagged.len() + 8;
+	let mut merger = PacketMerger::new().max_bytes(budget);
+	for packet in &[&packets[0], &tagged, &packets[1], &tagged] {
+		merger.push(packet).unwrap();
+	}
+	merger.flush().unwrap();
+	let merged: Vec<_> = std::iter::from_fn(|| merger.next_packet()).collect();
+	assert!(merged.iter().all(|p| p.data.len() <= budget));
+	assert_eq!(merged.len(), 2);
+
+	// A change of configuration starts a new packet.
+	let mut stereo = packets[1].clone();
+	stereo[0] |= 0x04;
+	let mut merger = PacketMerger::new();
+	merger.push(&packets[0]).unwrap();
+	merger.push(&stereo).unwrap();
+	merger.flush().unwrap();
+	assert_eq!(merger.next_packet().unwrap().duration, 960);
+	assert_eq!(merger.next_packet().unwrap().data, stereo);
+	assert!(merger.next_packet().is_none());
+}
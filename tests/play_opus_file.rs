@@ -50,7 +50,7 @@ fn play_opus_file_example(file_path: &str) -> Result<(), Box<dyn std::error::Err
     // You need to get the sample rate and channel count from the Opus header
     let sample_rate = 48000; // This should come from the Opus header
     let channels = Channels::Stereo; // This should come from the Opus header
-    let mut decoder = Decoder::new(sample_rate, channels)?;
+    let _decoder = Decoder::new(sample_rate, channels)?;
     
     // Step 3: Set up audio output
     // You would need an audio library like `cpal` or `rodio`:
@@ -139,18 +139,15 @@ fn test_with_playback_features(file_path: &str) {
                         opus_header_found = true;
                         println!("✓ Found Opus header in packet {}", packet_count);
                         
-                        if packet.data.len() >= 19 {
-                            let channels = packet.data[9];
-                            let pre_skip = u16::from_le_bytes([packet.data[10], packet.data[11]]);
-                            let sample_rate = u32::from_le_bytes([
-                                packet.data[12], packet.data[13], 
-                                packet.data[14], packet.data[15]
-                            ]);
-                            
-                            println!("  Channels: {}", channels);
-                            println!("  Pre-skip: {}", pre_skip);
-                            println!("  Original sample rate: {} Hz", sample_rate);
-                            println!("  (Opus always decodes to 48kHz internally)");
+                        match opus::ogg::OpusHead::parse(&packet.data) {
+                            Ok(head) => {
+                                println!("  Channels: {}", head.channels);
+                                println!("  Pre-skip: {}", head.pre_skip);
+                                println!("  Original sample rate: {} Hz", head.input_sample_rate);
+                                println!("  Output gain: {} (Q7.8 dB)", head.output_gain);
+                                println!("  (Opus always decodes to 48kHz internally)");
+                            }
+                            Err(e) => println!("⚠ Invalid Opus header: {}", e),
                         }
                         break;
                     } else if packet.data.starts_with(b"OpusTags") {
@@ -0,0 +1,35 @@
+//! Tests for ambisonics projection coding.
+
+extern crate opus;
+
+use opus::projection::{ProjectionDecoder, ProjectionEncoder};
+use opus::Application;
+
+const FRAME: usize = 960;
+
+#[test]
+fn projection_round_trip() {
+	// First order ambisonics with a non-diegetic stereo track.
+	let mut encoder = ProjectionEncoder::new(48000, 6, 3, Application::Audio).unwrap();
+	let (streams, coupled) = (encoder.streams(), encoder.coupled_streams());
+	assert_eq!(streams as usize + coupled as usize, 6);
+	let matrix = encoder.get_demixing_matrix().unwrap();
+	assert_eq!(matrix.len(), 2 * 6 * 6);
+	encoder.get_demixing_matrix_gain().unwrap();
+	encoder.set_bitrate(opus::Bitrate::Bits(256000)).unwrap();
+
+	let mut decoder = ProjectionDecoder::new(48000, 6, streams, coupled, &matrix).unwrap();
+	let input: Vec<f32> =
+		(0..FRAME * 6).map(|i| ((i / 6) as f32 * 0.02 * (1 + i % 6) as f32).sin() * 0.3).collect();
+	let mut output = vec![0.0; FRAME * 6];
+	for _ in 0..5 {
+		let packet = encoder.encode_vec_float(&input, 8000).unwrap();
+		assert_eq!(decoder.decode_float(&packet, &mut output, false).unwrap(), FRAME);
+	}
+	let energy: f32 = output.iter().map(|s| s * s).sum();
+	assert!(energy > 1.0, "{}", energy);
+
+	assert_eq!(decoder.decode_float(&[], &mut output, false).unwrap(), FRAME);
+	assert!(ProjectionEncoder::new(48000, 5, 3, Application::Audio).is_err());
+	assert!(ProjectionDecoder::new(48000, 6, streams, coupled, &matrix[1..]).is_err());
+}
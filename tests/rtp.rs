@@ -0,0 +1,135 @@
+//! Tests for the RTP payload format.
+
+extern crate opus;
+
+use opus::rtp::{Depayloader, Header, Payloader};
+use opus::{Application, Channels, Decoder, Encoder};
+
+const FRAME: usize = 960;
+
+/// A TOC-only packet, as produced during DTX.
+const DTX: &[u8] = &[0xf8];
+
+fn packets(count: usize) -> Vec<Vec<u8>> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_inband_fec(true).unwrap();
+	encoder.set_packet_loss_perc(20).unwrap();
+	(0..count)
+		.map(|n| {
+			let pcm: Vec<i16> = (0..FRAME)
+				.map(|i| (((n * FRAME + i) as f32 * 0.03).sin() * 8000.0) as i16)
+				.collect();
+			encoder.encode_vec(&pcm, 4000).unwrap()
+		})
+		.collect()
+}
+
+fn payloader() -> Payloader {
+	let mut payloader = Payloader::new(111, 0xdead_beef).unwrap();
+	payloader.set_sequence(65534);
+	payloader.set_timestamp(u32::MAX - 1000);
+	payloader
+}
+
+#[test]
+fn header_round_trip() {
+	let header = Header {
+		marker: true,
+		payload_type: 111,
+		sequence: 513,
+		timestamp: 0x0102_0304,
+		ssrc: 0xdead_beef,
+	};
+	let mut data = Vec::new();
+	header.write_to(&mut data);
+	assert_eq!(data, [0x80, 0xef, 2, 1, 1, 2, 3, 4, 0xde, 0xad, 0xbe, 0xef]);
+	data.extend_from_slice(b"opus");
+	assert_eq!(Header::parse(&data).unwrap(), (header, &b"opus"[..]));
+
+	// One CSRC, a one-word extension and two bytes of padding.
+	let mut extended = data[..12].to_vec();
+	extended[0] |= 0x20 | 0x10 | 0x01;
+	extended.extend_from_slice(&[0; 4]);
+	extended.extend_from_slice(&[0xbe, 0xde, 0, 1, 0, 0, 0, 0]);
+	extended.extend_from_slice(b"opus");
+	extended.extend_from_slice(&[0, 2]);
+	assert_eq!(Header::parse(&extended).unwrap(), (header, &b"opus"[..]));
+
+	assert!(Header::parse(&data[..11]).is_err());
+	assert!(Header::parse(&extended[..20]).is_err());
+}
+
+#[test]
+fn payloader_sequence_and_timestamps() {
+	let packets = packets(3);
+	let mut payloader = payloader();
+	let mut headers = Vec::new();
+	for packet in &packets[..2] {
+		headers.push(Header::parse(&payloader.payload(packet).unwrap().unwrap()).unwrap().0);
+	}
+	// A DTX gap is not sent, but still takes time.
+	assert_eq!(payloader.payload(DTX).unwrap(), None);
+	assert_eq!(payloader.payload(DTX).unwrap(), None);
+	let data = payloader.payload(&packets[2]).unwrap().unwrap();
+	let (header, payload) = Header::parse(&data).unwrap();
+	assert_eq!(payload, &packets[2][..]);
+	headers.push(header);
+
+	let sequences: Vec<u16> = headers.iter().map(|h| h.sequence).collect();
+	assert_eq!(sequences, [65534, 65535, 0]);
+	let timestamps: Vec<u32> = headers.iter().map(|h| h.timestamp).collect();
+	assert_eq!(timestamps, [u32::MAX - 1000, u32::MAX - 40, 2839]);
+	let markers: Vec<bool> = headers.iter().map(|h| h.marker).collect();
+	assert_eq!(markers, [true, false, true]);
+	assert!(headers.iter().all(|h| h.ssrc == 0xdead_beef && h.payload_type == 111));
+}
+
+#[test]
+fn depayloader_in_order() {
+	let mut payloader = payloader();
+	let mut depayloader = Depayloader::new(Decoder::new(48000, Channels::Mono).unwrap()).unwrap();
+	for packet in packets(5) {
+		let data = payloader.payload(&packet).unwrap().unwrap();
+		assert_eq!(depayloader.push(&data).unwrap().len(), FRAME);
+	}
+	assert_eq!(depayloader.packets_lost(), 0);
+	assert_eq!(depayloader.packets_late(), 0);
+}
+
+#[test]
+fn depayloader_loss_and_reordering() {
+	let mut payloader = payloader();
+	let rtp: Vec<Vec<u8>> =
+		packets(8).iter().map(|p| payloader.payload(p).unwrap().unwrap()).collect();
+	let mut depayloader = Depayloader::new(Decoder::new(16000, Channels::Mono).unwrap()).unwrap();
+
+	let frame = FRAME / 3;
+	assert_eq!(depayloader.push(&rtp[0]).unwrap().len(), frame);
+	// Two packets lost: one concealed, one recovered from FEC.
+	assert_eq!(depayloader.push(&rtp[3]).unwrap().len(), 3 * frame);
+	assert_eq!(depayloader.packets_lost(), 2);
+	// Stragglers and duplicates are dropped.
+	assert!(depayloader.push(&rtp[1]).unwrap().is_empty());
+	assert!(depayloader.push(&rtp[3]).unwrap().is_empty());
+	assert_eq!(depayloader.packets_late(), 2);
+	assert_eq!(depayloader.push_float(&rtp[4]).unwrap().len(), frame);
+	assert_eq!(depayloader.push_float(&rtp[6]).unwrap().len(), 2 * frame);
+	assert_eq!(depayloader.packets_lost(), 3);
+}
+
+#[test]
+fn depayloader_dtx_gap() {
+	let packets = packets(2);
+	let mut payloader = payloader();
+	let first = payloader.payload(&packets[0]).unwrap().unwrap();
+	for _ in 0..10 {
+		assert!(payloader.payload(DTX).unwrap().is_none());
+	}
+	let second = payloader.payload(&packets[1]).unwrap().unwrap();
+
+	let mut depayloader = Depayloader::new(Decoder::new(48000, Channels::Mono).unwrap()).unwrap();
+	assert_eq!(depayloader.push(&first).unwrap().len(), FRAME);
+	// The silence is filled in, but nothing was lost.
+	assert_eq!(depayloader.push(&second).unwrap().len(), 11 * FRAME);
+	assert_eq!(depayloader.packets_lost(), 0);
+}
@@ -163,3 +163,337 @@ fn repacketizer() {
 		assert_eq!(&out[..len], &[249, 255, 254, 71, 71]);
 	}
 }
+
+#[test]
+fn dnn_blob() {
+	use opus::ErrorCode::*;
+	use opus::LibraryFeature;
+
+	// Decoder complexity arrived with the neural models in Opus 1.5. The
+	// bundled libopus is older and reports its version as "unknown".
+	let version: Vec<u32> = opus::version()
+		.trim_start_matches("libopus ")
+		.split('.')
+		.take(2)
+		.map(|part| part.parse().unwrap_or(0))
+		.collect();
+	let neural = version >= vec![1, 5];
+
+	// Not valid weights, so rejected either way.
+	let blob = opus::DnnBlob::from(vec![0; 64]);
+	assert_eq!(blob.as_bytes().len(), 64);
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Mono, opus::Application::Voip).unwrap();
+	let mut decoder = opus::Decoder::new(48000, opus::Channels::Mono).unwrap();
+	let errors =
+		[encoder.set_dnn_blob(&blob).unwrap_err(), decoder.set_dnn_blob(&blob).unwrap_err()];
+	for e in &errors {
+		if !neural || e.code() == Unimplemented {
+			assert_eq!(e.code(), Unimplemented);
+			assert_eq!(e.missing_feature(), Some(LibraryFeature::DnnBlob));
+			assert!(e.to_string().contains("built without runtime neural network"), "{}", e);
+		} else {
+			assert_eq!(e.code(), BadArg);
+		}
+	}
+
+	match decoder.set_complexity(10) {
+		Ok(()) => {
+			assert!(neural, "{}", opus::version());
+			assert_eq!(decoder.get_complexity().unwrap(), 10);
+		}
+		Err(e) => {
+			assert!(!neural, "{}", e);
+			assert_eq!(e.code(), Unimplemented);
+			assert_eq!(e.missing_feature(), Some(LibraryFeature::DeepPlc));
+			assert!(e.to_string().contains("built without deep PLC and OSCE"), "{}", e);
+		}
+	}
+	if !neural {
+		let e = decoder.get_complexity().unwrap_err();
+		assert_eq!(e.missing_feature(), Some(LibraryFeature::DeepPlc));
+	}
+	let packet = encoder.encode_vec(&[0_i16; MONO_20MS], 256).unwrap();
+	let mut output = [0_i16; MONO_20MS];
+	assert_eq!(decoder.decode(&packet, &mut output, false).unwrap(), MONO_20MS);
+	assert_eq!(decoder.decode(&[], &mut output, false).unwrap(), MONO_20MS);
+}
+
+#[test]
+fn surround() {
+	let app = opus::Application::Audio;
+	let (mut encoder, layout) = opus::MSEncoder::new_surround(48000, 6, 1, app).unwrap();
+	assert_eq!((layout.streams, layout.coupled_streams), (4, 2));
+	assert_eq!(layout.mapping, [0, 4, 1, 2, 3, 5]);
+
+	let mut decoder =
+		opus::MSDecoder::new(48000, layout.streams, layout.coupled_streams, &layout.mapping)
+			.unwrap();
+	let input: Vec<i16> = (0..MONO_20MS * 6).map(|i| (i as i16).wrapping_mul(97)).collect();
+	let packet = encoder.encode_vec(&input, 4000).unwrap();
+	let mut output = vec![0_i16; MONO_20MS * 6];
+	assert_eq!(decoder.decode(&packet, &mut output, false).unwrap(), MONO_20MS);
+
+	// Discrete channels are each coded alone.
+	let (_, layout) = opus::MSEncoder::new_surround(48000, 10, 255, app).unwrap();
+	assert_eq!((layout.streams, layout.coupled_streams), (10, 0));
+	assert_eq!(layout.mapping, (0..10).collect::<Vec<u8>>());
+
+	assert!(opus::MSEncoder::new_surround(48000, 6, 0, app).is_err());
+	assert!(opus::MSEncoder::new_surround(48000, 9, 1, app).is_err());
+}
+
+#[test]
+fn multistream_per_stream_ctls() {
+	let app = opus::Application::Audio;
+	let (mut encoder, layout) = opus::MSEncoder::new_surround(48000, 6, 1, app).unwrap();
+	encoder.set_bitrate(opus::Bitrate::Bits(256000)).unwrap();
+	// The LFE is coded alone in the last stream.
+	encoder.stream(3).unwrap().set_bitrate(opus::Bitrate::Bits(8000)).unwrap();
+	encoder.stream(0).unwrap().set_signal(opus::Signal::Voice).unwrap();
+	assert_eq!(encoder.stream(3).unwrap().get_bitrate().unwrap(), opus::Bitrate::Bits(8000));
+	assert_eq!(encoder.stream(0).unwrap().get_signal().unwrap(), opus::Signal::Voice);
+	assert_eq!(encoder.stream(1).unwrap().get_signal().unwrap(), opus::Signal::Auto);
+	assert!(encoder.stream(4).is_err());
+
+	let mut decoder =
+		opus::MSDecoder::new(48000, layout.streams, layout.coupled_streams, &layout.mapping)
+			.unwrap();
+	let input: Vec<i16> = (0..MONO_20MS * 6).map(|i| (i as i16).wrapping_mul(97)).collect();
+	let packet = encoder.encode_vec(&input, 4000).unwrap();
+	let mut output = vec![0_i16; MONO_20MS * 6];
+	decoder.decode(&packet, &mut output, false).unwrap();
+
+	// The multistream final range combines those of the streams.
+	let mut combined = 0;
+	for index in 0..layout.streams {
+		let range = decoder.stream(index).unwrap().get_final_range().unwrap();
+		assert_eq!(range, encoder.stream(index).unwrap().get_final_range().unwrap());
+		combined ^= range;
+	}
+	assert_eq!(decoder.get_final_range().unwrap(), combined);
+	decoder.stream(0).unwrap().get_pitch().unwrap();
+	assert!(decoder.stream(4).is_err());
+}
+
+#[test]
+fn channel_layout() {
+	use opus::ChannelLayout;
+
+	// 5.1: L, C, R, Ls, Rs, LFE.
+	let layout = ChannelLayout::vorbis(6).unwrap();
+	assert_eq!((layout.channels(), layout.streams(), layout.coupled_streams()), (6, 4, 2));
+	let sources: Vec<_> = (0..6).map(|c| layout.source(c)).collect();
+	assert_eq!(
+		sources,
+		[Some((0, 0)), Some((2, 0)), Some((0, 1)), Some((1, 0)), Some((1, 1)), Some((3, 0))]
+	);
+	for channels in 1..=8 {
+		let layout = ChannelLayout::vorbis(channels).unwrap();
+		let mut encoder =
+			opus::MSEncoder::with_layout(48000, &layout, opus::Application::Audio).unwrap();
+		let mut decoder = opus::MSDecoder::with_layout(48000, &layout).unwrap();
+		let input = vec![0_i16; MONO_20MS * channels as usize];
+		let packet = encoder.encode_vec(&input, 4000).unwrap();
+		let mut output = vec![0_i16; MONO_20MS * channels as usize];
+		assert_eq!(decoder.decode(&packet, &mut output, false).unwrap(), MONO_20MS);
+	}
+	assert!(ChannelLayout::vorbis(0).is_err());
+	assert!(ChannelLayout::vorbis(9).is_err());
+
+	// The streams libopus chooses for 5.1 are the Vorbis layout.
+	let (_, surround) =
+		opus::MSEncoder::new_surround(48000, 6, 1, opus::Application::Audio).unwrap();
+	assert_eq!(ChannelLayout::from(surround), layout);
+
+	let silent = ChannelLayout::new(1, 0, &[0, 255]).unwrap();
+	assert_eq!((silent.source(0), silent.source(1)), (Some((0, 0)), None));
+	assert!(ChannelLayout::new(0, 0, &[0]).is_err());
+	assert!(ChannelLayout::new(1, 2, &[0]).is_err());
+	assert!(ChannelLayout::new(1, 1, &[]).is_err());
+	assert!(ChannelLayout::new(2, 1, &[0, 3]).is_err());
+}
+
+#[test]
+fn encoder_config() {
+	use opus::{Bandwidth, Bitrate, EncoderConfig, FrameSize, Signal};
+
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Stereo, opus::Application::Audio).unwrap();
+	let config = EncoderConfig::new()
+		.bitrate(Bitrate::Bits(96000))
+		.vbr(false)
+		.complexity(5)
+		.inband_fec(true)
+		.packet_loss_perc(10)
+		.signal(Signal::Music)
+		.max_bandwidth(Bandwidth::Superwideband)
+		.lsb_depth(16)
+		.frame_duration(FrameSize::Ms10);
+	encoder.set_config(&config).unwrap();
+	let read = encoder.get_config().unwrap();
+	assert_eq!(read.bitrate, Some(Bitrate::Bits(96000)));
+	assert_eq!(read.vbr, Some(false));
+	assert_eq!(read.complexity, Some(5));
+	assert_eq!(read.inband_fec, Some(true));
+	assert_eq!(read.packet_loss_perc, Some(10));
+	assert_eq!(read.signal, Some(Signal::Music));
+	assert_eq!(read.max_bandwidth, Some(Bandwidth::Superwideband));
+	assert_eq!(read.lsb_depth, Some(16));
+	assert_eq!(read.frame_duration, Some(FrameSize::Ms10));
+	// Settings which were not given are read back as they were.
+	assert_eq!(read.dtx, Some(false));
+	assert_eq!(read.prediction_disabled, Some(false));
+
+	// A rejected setting leaves the encoder as it was.
+	let bad = EncoderConfig::new().complexity(2).lsb_depth(99);
+	assert!(encoder.set_config(&bad).is_err());
+	assert_eq!(encoder.get_config().unwrap(), read);
+
+	let (mut surround, _) =
+		opus::MSEncoder::new_surround(48000, 6, 1, opus::Application::Audio).unwrap();
+	surround.set_config(&EncoderConfig::new().complexity(3).signal(Signal::Voice)).unwrap();
+	let read = surround.get_config().unwrap();
+	assert_eq!((read.complexity, read.signal), (Some(3), Some(Signal::Voice)));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn encoder_config_serde() {
+	extern crate serde;
+
+	fn assert_serde<T: serde::Serialize + for<'de> serde::Deserialize<'de>>() {}
+	assert_serde::<opus::EncoderConfig>();
+}
+
+#[test]
+fn clone_and_snapshot() {
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Mono, opus::Application::Audio).unwrap();
+	let input: Vec<i16> =
+		(0..MONO_20MS * 5).map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16).collect();
+	let packets: Vec<Vec<u8>> =
+		input.chunks(MONO_20MS).map(|pcm| encoder.encode_vec(pcm, 4000).unwrap()).collect();
+
+	// A forked decoder carries on exactly as the original.
+	let mut decoder = opus::Decoder::new(48000, opus::Channels::Mono).unwrap();
+	let mut output = [0_i16; MONO_20MS];
+	decoder.decode(&packets[0], &mut output, false).unwrap();
+	let mut fork = decoder.clone();
+	let mut forked = [0_i16; MONO_20MS];
+	for packet in &packets[1..] {
+		decoder.decode(packet, &mut output, false).unwrap();
+		fork.decode(packet, &mut forked, false).unwrap();
+		assert_eq!(output, forked);
+	}
+
+	// An encoder returned to a checkpoint repeats its output.
+	let checkpoint = encoder.snapshot();
+	let first = encoder.encode_vec(&input[..MONO_20MS], 4000).unwrap();
+	encoder.set_bitrate(opus::Bitrate::Bits(6000)).unwrap();
+	let cheap = encoder.encode_vec(&input[..MONO_20MS], 4000).unwrap();
+	assert!(cheap.len() < first.len());
+	encoder.restore(&checkpoint).unwrap();
+	assert_eq!(encoder.encode_vec(&input[..MONO_20MS], 4000).unwrap(), first);
+	assert_eq!(encoder.clone().get_final_range().unwrap(), encoder.get_final_range().unwrap());
+
+	let mut stereo = opus::Decoder::new(48000, opus::Channels::Stereo).unwrap();
+	assert!(stereo.restore(&decoder.snapshot()).is_err());
+}
+
+#[test]
+fn state_memory_and_pool() {
+	use opus::{Channels, Decoder, DecoderPool, StateMemory};
+
+	let mut encoder = opus::Encoder::with_memory(
+		48000,
+		Channels::Mono,
+		opus::Application::Audio,
+		StateMemory::for_encoder(Channels::Mono),
+	)
+	.unwrap();
+	let packet = encoder.encode_vec(&[1000_i16; MONO_20MS], 4000).unwrap();
+	let memory = encoder.into_memory();
+	assert!(memory.size() >= StateMemory::for_encoder(Channels::Mono).size());
+
+	// Memory which is too small is refused.
+	let small = StateMemory::new(16);
+	assert!(Decoder::with_memory(48000, Channels::Mono, small).is_err());
+
+	let mut pool = DecoderPool::new(48000, Channels::Mono);
+	let mut output = [0_i16; MONO_20MS];
+	let mut first = pool.get().unwrap();
+	first.set_gain(-2560).unwrap();
+	first.decode(&packet, &mut output, false).unwrap();
+	let expected = {
+		let mut fresh = Decoder::new(48000, Channels::Mono).unwrap();
+		let mut expected = [0_i16; MONO_20MS];
+		fresh.decode(&packet, &mut expected, false).unwrap();
+		expected
+	};
+	assert_ne!(output, expected);
+	pool.put(first);
+	assert_eq!(pool.idle(), 1);
+
+	// A recycled decoder behaves as a new one.
+	let mut second = pool.get().unwrap();
+	assert_eq!(pool.idle(), 0);
+	assert_eq!(second.get_gain().unwrap(), 0);
+	second.decode(&packet, &mut output, false).unwrap();
+	assert_eq!(output, expected);
+
+	pool.put(second);
+	pool.put(Decoder::new(48000, Channels::Stereo).unwrap());
+	pool.put(Decoder::new(8000, Channels::Mono).unwrap());
+	assert_eq!(pool.idle(), 3);
+	pool.shrink_to(1);
+	assert_eq!(pool.idle(), 1);
+}
+
+#[test]
+fn stream_encoder() {
+	use opus::{FrameSize, StreamEncoder};
+
+	let encoder =
+		opus::Encoder::new(16000, opus::Channels::Stereo, opus::Application::Audio).unwrap();
+	assert!(StreamEncoder::new(encoder, FrameSize::Arg).is_err());
+
+	let encoder =
+		opus::Encoder::new(16000, opus::Channels::Stereo, opus::Application::Audio).unwrap();
+	let mut stream = StreamEncoder::new(encoder, FrameSize::Ms10).unwrap();
+	assert_eq!(stream.frame_size(), 160);
+	let lookahead = stream.lookahead();
+	assert!(lookahead > 0);
+	assert!(stream.write(&[0; 3]).is_err());
+
+	// Uneven chunks from a capture callback, 1000 samples in total.
+	let mut packets = Vec::new();
+	for &chunk in &[37, 300, 1, 500, 162] {
+		stream.write(&vec![100i16; chunk * 2]).unwrap();
+		while let Some(packet) = stream.next_packet().unwrap() {
+			packets.push(packet);
+		}
+	}
+	assert_eq!(packets.len(), 6);
+	stream.finish();
+	assert!(stream.write(&[0; 2]).is_err());
+	while let Some(packet) = stream.next_packet().unwrap() {
+		packets.push(packet);
+	}
+
+	let total = 1000 + lookahead;
+	assert_eq!(packets.len(), (total + 159) / 160);
+	for (i, packet) in packets.iter().enumerate() {
+		assert_eq!(packet.timestamp, i as u64 * 160);
+		assert_eq!(packet.duration, 160);
+		assert_eq!(opus::packet::get_nb_samples(&packet.data, 16000).unwrap(), 160);
+	}
+	let last = packets.last().unwrap();
+	assert_eq!(last.padding, packets.len() * 160 - total);
+	assert!(packets[..packets.len() - 1].iter().all(|p| p.padding == 0));
+
+	stream.reset().unwrap();
+	assert_eq!(stream.timestamp(), 0);
+	stream.write(&[0; 2 * 160]).unwrap();
+	assert_eq!(stream.next_packet().unwrap().unwrap().timestamp, 0);
+}
@@ -0,0 +1,227 @@
+//! Tests for WAV reading and writing.
+#![cfg(feature = "wav")]
+
+extern crate opus;
+
+use opus::wav::{Error, SampleFormat, WavReader, WavSpec, WavWriter};
+use opus::{Application, Channels, Decoder};
+use std::io::Cursor;
+
+const FORMATS: [SampleFormat; 4] =
+	[SampleFormat::Pcm16, SampleFormat::Pcm24, SampleFormat::Pcm32, SampleFormat::Float32];
+
+fn sine(len: usize) -> Vec<i16> {
+	(0..len).map(|i| ((i as f32 * 0.03).sin() * 12000.0) as i16).collect()
+}
+
+fn write_wav(spec: WavSpec, pcm: &[i16]) -> Vec<u8> {
+	let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+	// Awkward chunk sizes, as from a decoder.
+	for chunk in pcm.chunks(998) {
+		writer.write(chunk).unwrap();
+	}
+	writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn formats() {
+	let pcm = sine(2 * 1001);
+	for &format in &FORMATS {
+		let spec = WavSpec::new(Channels::Stereo, 24000, format);
+		let data = write_wav(spec, &pcm);
+		assert_eq!(data.len(), 44 + pcm.len() * format.sample_size(), "{:?}", format);
+
+		let mut reader = WavReader::new(&data[..]).unwrap();
+		assert_eq!(reader.spec(), spec);
+		assert_eq!(reader.duration(), Some(1001));
+		// Only whole sample frames are read.
+		let mut buf = [0; 7];
+		assert_eq!(reader.read(&mut buf).unwrap(), 6);
+		assert_eq!(&buf[..6], &pcm[..6]);
+		let mut rest = reader.read_all().unwrap();
+		rest.splice(0..0, buf[..6].iter().cloned());
+		assert_eq!(rest, pcm, "{:?}", format);
+		assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+		let mut reader = WavReader::new(&data[..]).unwrap();
+		let float = reader.read_all_float().unwrap();
+		let error = float.iter().zip(&pcm).map(|(&f, &s)| (f - s as f32 / 32768.0).abs());
+		assert!(error.fold(0.0, f32::max) < 1e-6, "{:?}", format);
+	}
+}
+
+#[test]
+fn float_precision() {
+	let pcm: Vec<f32> = (0..999).map(|i| (i as f32 * 0.01).sin() * 0.9).collect();
+	for &(format, tolerance) in &[
+		(SampleFormat::Pcm16, 1.0 / 32768.0),
+		(SampleFormat::Pcm24, 1.0 / 8388608.0),
+		(SampleFormat::Float32, 0.0),
+	] {
+		let spec = WavSpec::new(Channels::Mono, 16000, format);
+		let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+		writer.write_float(&pcm).unwrap();
+		writer.write_float(&[2.0, -2.0]).unwrap();
+		let data = writer.finish().unwrap().into_inner();
+		// Odd-length data is padded.
+		assert_eq!(data.len() % 2, 0);
+
+		let mut reader = WavReader::new(&data[..]).unwrap();
+		let read = reader.read_all_float().unwrap();
+		assert_eq!(read.len(), 1001);
+		for (&a, &b) in read.iter().zip(&pcm) {
+			assert!((a - b).abs() <= tolerance, "{:?}: {} != {}", format, a, b);
+		}
+		// Out of range samples are clipped for integer formats.
+		if format != SampleFormat::Float32 {
+			assert!(read[999] <= 1.0 && read[1000] == -1.0, "{:?}", format);
+		}
+	}
+}
+
+#[test]
+fn encode_decode() {
+	const FRAME: usize = 320;
+	let pcm = sine(16000 + 77);
+	let data = write_wav(WavSpec::new(Channels::Mono, 16000, SampleFormat::Pcm16), &pcm);
+
+	let mut reader = WavReader::new(&data[..]).unwrap();
+	let mut encoder = reader.encoder(Application::Audio).unwrap();
+	let mut decoder = Decoder::new(16000, Channels::Mono).unwrap();
+	let spec = WavSpec::new(Channels::Mono, 16000, SampleFormat::Float32);
+	let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+	let mut input = [0; FRAME];
+	let mut output = [0.0; FRAME];
+	loop {
+		let len = reader.read(&mut input).unwrap();
+		if len == 0 {
+			break;
+		}
+		for sample in &mut input[len..] {
+			*sample = 0;
+		}
+		let packet = encoder.encode_vec(&input, 4000).unwrap();
+		assert_eq!(decoder.decode_float(&packet, &mut output, false).unwrap(), FRAME);
+		writer.write_float(&output).unwrap();
+	}
+	let data = writer.finish().unwrap().into_inner();
+
+	let reader = WavReader::new(&data[..]).unwrap();
+	assert_eq!(reader.spec(), spec);
+	assert_eq!(reader.duration(), Some(51 * FRAME as u64));
+}
+
+#[test]
+fn opus_checks() {
+	let spec = WavSpec::new(Channels::Stereo, 48000, SampleFormat::Pcm16);
+	assert_eq!(spec.check_opus().unwrap(), Channels::Stereo);
+	for &(channels, sample_rate) in &[(2, 44100), (3, 48000), (1, 22050)] {
+		let spec = WavSpec {
+			channels,
+			sample_rate,
+			format: SampleFormat::Pcm16,
+		};
+		match spec.check_opus() {
+			Err(Error::Unsupported(_)) => {}
+			other => panic!("{:?}: {:?}", spec, other),
+		}
+		let data = write_wav(spec, &[0; 6]);
+		assert!(WavReader::new(&data[..]).unwrap().encoder(Application::Audio).is_err());
+	}
+}
+
+fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+	let mut out = id.to_vec();
+	out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+	out.extend_from_slice(body);
+	if body.len() % 2 == 1 {
+		out.push(0);
+	}
+	out
+}
+
+#[test]
+fn extensible() {
+	// A 24-bit extensible header, an odd-sized chunk before the data, and
+	// a data size left unknown by a streaming tool.
+	let mut fmt = Vec::new();
+	for &(value, size) in &[
+		(0xfffe, 2),
+		(2, 2),
+		(48000, 4),
+		(48000 * 6, 4),
+		(6, 2),
+		(24, 2),
+		(22, 2),
+		(24, 2),
+		(3, 4),
+		(1, 2),
+	] {
+		fmt.extend_from_slice(&(value as u32).to_le_bytes()[..size]);
+	}
+	fmt.extend_from_slice(&[
+		0, 0, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+	]);
+	let samples: Vec<u8> = [0x00, 0x00, 0x01, 0xff, 0xff, 0xff, 0x00, 0x80, 0x7f, 0x00, 0x00, 0x80]
+		.iter()
+		.cloned()
+		.chain(Some(0x12))
+		.collect();
+	let mut data = b"RIFF\xff\xff\xff\xffWAVE".to_vec();
+	data.extend_from_slice(&chunk(b"fmt ", &fmt));
+	data.extend_from_slice(&chunk(b"LIST", b"INFOISFT\x03\0\0\0abc"));
+	data.extend_from_slice(b"data\xff\xff\xff\xff");
+	data.extend_from_slice(&samples);
+
+	let mut reader = WavReader::new(&data[..]).unwrap();
+	assert_eq!(reader.spec(), WavSpec::new(Channels::Stereo, 48000, SampleFormat::Pcm24));
+	assert_eq!(reader.duration(), None);
+	// The stray byte at the end is not a whole frame.
+	assert_eq!(reader.read_all().unwrap(), [0x0100, -1, 0x7f80, -0x8000]);
+
+	let mut bad = data.clone();
+	bad[8..12].copy_from_slice(b"AVI ");
+	assert!(WavReader::new(&bad[..]).is_err());
+	assert!(WavReader::new(&data[..40]).is_err());
+
+	// A fmt chunk far larger than any format needs.
+	let plain = write_wav(WavSpec::new(Channels::Stereo, 48000, SampleFormat::Pcm16), &[0; 2]);
+	let mut bad = b"RIFF\xff\xff\xff\xffWAVE".to_vec();
+	bad.extend_from_slice(&chunk(b"fmt ", &[&plain[20..36], &[0; 100][..]].concat()));
+	bad.extend_from_slice(&plain[36..]);
+	match WavReader::new(&bad[..]) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("{:?}", other.map(|_| ())),
+	}
+}
+
+#[test]
+fn write_after_prefix() {
+	let pcm = sine(2 * 100);
+	let spec = WavSpec::new(Channels::Stereo, 16000, SampleFormat::Pcm16);
+	let mut cursor = Cursor::new(b"prefix".to_vec());
+	cursor.set_position(6);
+	let mut writer = WavWriter::new(cursor, spec).unwrap();
+	writer.write(&pcm).unwrap();
+	let mut cursor = writer.finish().unwrap();
+	assert_eq!(cursor.position(), 6 + 44 + 400);
+
+	let data = cursor.get_mut();
+	assert_eq!(&data[..6], b"prefix");
+	assert_eq!(data[6..], write_wav(spec, &pcm)[..]);
+}
+
+#[test]
+fn seek() {
+	let pcm = sine(2 * 500);
+	let data = write_wav(WavSpec::new(Channels::Stereo, 8000, SampleFormat::Pcm24), &pcm);
+	let mut reader = WavReader::new(Cursor::new(&data)).unwrap();
+	reader.seek(400).unwrap();
+	assert_eq!(reader.read_all().unwrap(), &pcm[800..]);
+	reader.seek(10).unwrap();
+	let mut buf = [0; 20];
+	assert_eq!(reader.read(&mut buf).unwrap(), 20);
+	assert_eq!(&buf[..], &pcm[20..40]);
+	reader.seek(5).unwrap();
+	assert_eq!(reader.read_all().unwrap(), &pcm[10..]);
+}
@@ -0,0 +1,172 @@
+//! Tests for Opus in WebM.
+#![cfg(feature = "webm")]
+
+extern crate opus;
+
+use opus::webm::{Error, WebmOpusReader, WebmOpusWriter};
+use opus::{Application, Channels, Encoder};
+
+fn write_sine(sample_rate: u32, channels: Channels, samples: usize) -> Vec<u8> {
+	let encoder = Encoder::new(sample_rate, channels, Application::Audio).unwrap();
+	let mut writer = WebmOpusWriter::new(Vec::new(), encoder).unwrap();
+	let channels = channels as usize;
+	let pcm: Vec<f32> = (0..samples * channels).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+	// Deliberately awkward chunk sizes.
+	for chunk in pcm.chunks(333 * channels) {
+		writer.write_float(chunk).unwrap();
+	}
+	writer.finish().unwrap()
+}
+
+fn decode_all<R: std::io::Read>(reader: &mut WebmOpusReader<R>) -> Vec<i16> {
+	let mut pcm = Vec::new();
+	while let Some(chunk) = reader.decode().unwrap() {
+		pcm.extend_from_slice(&chunk);
+	}
+	pcm
+}
+
+#[test]
+fn write_round_trip() {
+	let data = write_sine(48000, Channels::Stereo, 12345);
+	assert_eq!(&data[..4], b"\x1a\x45\xdf\xa3");
+
+	let mut reader = WebmOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.channels(), 2);
+	assert_eq!(reader.head().pre_skip, 312);
+	assert_eq!(reader.codec_delay(), 6_500_000);
+	assert_eq!(reader.seek_pre_roll(), 80_000_000);
+	let mut packets = Vec::new();
+	while let Some(packet) = reader.next_packet().unwrap() {
+		packets.push(packet);
+	}
+	// Enough 20 ms packets to cover the input and the codec delay.
+	assert_eq!(packets.len(), (12345 + 312 + 959) / 960);
+	for (i, packet) in packets.iter().enumerate() {
+		assert_eq!(packet.timestamp, i as i64 * 20_000_000);
+	}
+	let padding = (packets.len() * 960 - 12345 - 312) as i64;
+	assert_eq!(packets.last().unwrap().discard_padding, padding * 1_000_000_000 / 48000);
+	assert!(packets[..packets.len() - 1].iter().all(|p| p.discard_padding == 0));
+
+	let mut reader = WebmOpusReader::new(&data[..]).unwrap();
+	let output = decode_all(&mut reader);
+	assert_eq!(output.len(), 2 * 12345);
+	// With the codec delay removed, the output lines up with the input.
+	let error: f32 = output[2000..20000]
+		.iter()
+		.enumerate()
+		.map(|(i, &s)| (s as f32 / 32768.0 - ((i + 2000) as f32 * 0.01).sin() * 0.5).abs())
+		.sum();
+	assert!(error / 18000.0 < 0.05, "mean error {}", error / 18000.0);
+}
+
+#[test]
+fn write_resampled() {
+	// Output is always at 48 kHz, three times as many samples as 16 kHz.
+	let data = write_sine(16000, Channels::Mono, 16000 + 7);
+	let mut reader = WebmOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head().input_sample_rate, 16000);
+	assert_eq!(decode_all(&mut reader).len(), 3 * (16000 + 7));
+}
+
+fn element(id: &[u8], body: &[u8]) -> Vec<u8> {
+	let mut out = id.to_vec();
+	assert!(body.len() < 0x3fff);
+	out.push(0x40 | (body.len() >> 8) as u8);
+	out.push(body.len() as u8);
+	out.extend_from_slice(body);
+	out
+}
+
+/// Build a file like a browser recording: unknown-size segment and cluster,
+/// a video track ahead of the audio, and laced blocks.
+fn recording(packets: &[Vec<u8>]) -> Vec<u8> {
+	let mut head = b"OpusHead\x01\x01".to_vec();
+	head.extend_from_slice(&312u16.to_le_bytes());
+	head.extend_from_slice(&48000u32.to_le_bytes());
+	head.extend_from_slice(&[0, 0, 0]);
+
+	let mut out = element(b"\x1a\x45\xdf\xa3", &element(b"\x42\x82", b"webm"));
+	out.extend_from_slice(b"\x18\x53\x80\x67\x01\xff\xff\xff\xff\xff\xff\xff");
+	let video = [element(b"\xd7", &[1]), element(b"\x86", b"V_VP8")].concat();
+	let audio = [
+		element(b"\xd7", &[2]),
+		element(b"\x86", b"A_OPUS"),
+		element(b"\x63\xa2", &head),
+		element(b"\x56\xaa", &[0x63, 0x2e, 0xa0]),
+	]
+	.concat();
+	let tracks = [element(b"\xae", &video), element(b"\xae", &audio)].concat();
+	out.extend_from_slice(&element(b"\x16\x54\xae\x6b", &tracks));
+
+	out.extend_from_slice(b"\x1f\x43\xb6\x75\xff");
+	out.extend_from_slice(&element(b"\xe7", &[0x03, 0xe8]));
+	out.extend_from_slice(&element(b"\xa3", &[0x81, 0, 0, 0x80, 1, 2, 3]));
+	let mut block = vec![0x82, 0, 0, 0x80];
+	block.extend_from_slice(&packets[0]);
+	out.extend_from_slice(&element(b"\xa3", &block));
+	// Xiph lacing of the next two packets.
+	let mut block = vec![0x82, 0, 20, 0x82, 1];
+	let mut len = packets[1].len();
+	while len >= 255 {
+		block.push(255);
+		len -= 255;
+	}
+	block.push(len as u8);
+	block.extend_from_slice(&packets[1]);
+	block.extend_from_slice(&packets[2]);
+	out.extend_from_slice(&element(b"\xa3", &block));
+	// The last packet, trimmed to 100 samples.
+	let mut block = vec![0x82, 0, 60, 0];
+	block.extend_from_slice(&packets[3]);
+	let group =
+		[element(b"\xa1", &block), element(b"\x75\xa2", &[0x01, 0x11, 0x62, 0xfa])].concat();
+	out.extend_from_slice(&element(b"\xa0", &group));
+	// Cues after an unknown-size cluster.
+	out.extend_from_slice(&element(b"\x1c\x53\xbb\x6b", &[0xbb, 0x80]));
+	out
+}
+
+#[test]
+fn read_recording() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let pcm: Vec<i16> = (0..960).map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16).collect();
+	let packets: Vec<Vec<u8>> = (0..4).map(|_| encoder.encode_vec(&pcm, 4000).unwrap()).collect();
+	let data = recording(&packets);
+
+	let mut reader = WebmOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.codec_delay(), 6_500_000);
+	let mut read = Vec::new();
+	while let Some(packet) = reader.next_packet().unwrap() {
+		read.push(packet);
+	}
+	assert_eq!(
+		read.iter().map(|p| &p.data).collect::<Vec<_>>(),
+		packets.iter().collect::<Vec<_>>()
+	);
+	let timestamps: Vec<i64> = read.iter().map(|p| p.timestamp / 1_000_000).collect();
+	assert_eq!(timestamps, [1000, 1020, 1040, 1060]);
+	// 860 samples of padding.
+	assert_eq!(read[3].discard_padding, 17_916_666);
+
+	let mut reader = WebmOpusReader::new(&data[..]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), 3 * 960 + 100 - 312);
+
+	// A recording cut off partway through a block still reads.
+	let mut reader = WebmOpusReader::new(&data[..data.len() - 40]).unwrap();
+	assert_eq!(decode_all(&mut reader).len(), 3 * 960 - 312);
+}
+
+#[test]
+fn reject_invalid() {
+	let mut data = write_sine(48000, Channels::Mono, 1000);
+	assert!(WebmOpusReader::new(&data[..20]).is_err());
+	// Change the doc type.
+	let pos = data.windows(4).position(|w| w == b"webm").unwrap();
+	data[pos..pos + 4].copy_from_slice(b"webp");
+	match WebmOpusReader::new(&data[..]) {
+		Err(Error::Malformed(_)) => {}
+		other => panic!("{:?}", other.map(|_| ())),
+	}
+}